@@ -1,24 +1,37 @@
 use bevy::{prelude::*, time::Stopwatch};
-use bevy_rapier3d::prelude::{
-    Collider, CollisionGroups, Friction, Group, LockedAxes, RigidBody, Velocity,
-};
+use bevy_rapier3d::prelude::Velocity;
 use rand::Rng;
 use std::{cmp::Ordering, f32::consts::PI, time::Duration};
 
 use crate::{
-    audio::audio::AudioType,
     buildings::{
         defensive_buildings::{AlienTarget, DamageDealing, TargetSelecting},
         grid::Grid,
+        resources::{ResourceState, ResourceType},
+    },
+    game_timer::{
+        game_timer::InGameTime,
+        gameplay_schedule::{on_gameplay_tick, GameplayClock, GameplayStage},
     },
-    game_timer::game_timer::InGameTime,
-    health::health::{DeathEvent, Health},
-    AppStage, AppState,
+    health::health::{death_timers, DeathEvent, Health},
+    rules::game_rules::{GameRng, GameRules},
+    stats::game_stats::GameStats,
+    AppState,
 };
-const ALIEN_SPEED: f32 = 5.;
+use super::{
+    alien_animation::AlienAnimationPlugin,
+    alien_kinds::{AlienBehavior, AlienKindsPlugin, AlienSpeed, AlienTemplates},
+    bosses::BossPlugin,
+    crowd::CrowdPlugin,
+    flow_field::{update_flow_field, FlowField, FlowFieldPlugin},
+    nests::{Nest, NestPlugin, NEST_COUNT, NEST_SPAWN_RADIUS},
+    pathfinding::{find_path, path_to_waypoints, AlienPath, WAYPOINT_REACHED_DISTANCE},
+    targeting::AlienTargetingPlugin,
+};
+
+// The speed of the basic alien, the other kinds are defined relative to it
+pub const ALIEN_SPEED: f32 = 5.;
 
-// The period at the start of the game where aliens don't spawn
-const GRACE_PERIOD: Duration = Duration::from_secs(10);
 #[derive(Resource)]
 pub struct AlienCount {
     pub count: u32,
@@ -29,25 +42,46 @@ impl Default for AlienCount {
         AlienCount { count: 0 }
     }
 }
+// A multiplier on the spawn rate on top of the GameRules. Always 1 in the game, the balance simulator sweeps it
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SpawnRateMultiplier(pub f32);
+
+impl Default for SpawnRateMultiplier {
+    fn default() -> Self {
+        SpawnRateMultiplier(1.)
+    }
+}
+
 pub struct AlienPlugin;
 impl Plugin for AlienPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<AlienCount>()
-            .insert_resource(AlienModel(None))
-            .add_system_set(
-                SystemSet::on_enter(AppState::InGame)
-                    .label(AppStage::RegisterResources)
-                    .with_system(register_aliens),
-            )
+            .init_resource::<SpawnRateMultiplier>()
+            .add_plugin(AlienKindsPlugin)
+            .add_plugin(AlienAnimationPlugin)
+            .add_plugin(FlowFieldPlugin)
+            .add_plugin(AlienTargetingPlugin)
+            .add_plugin(NestPlugin)
+            .add_plugin(BossPlugin)
+            .add_plugin(CrowdPlugin)
             .init_resource::<AlienSpawnAngle>()
             .add_event::<AlienSpawnEvent >()
-            .add_system_set(
-                SystemSet::on_update(AppState::InGame)
-                    .with_system(alien_ai)
-                    .with_system(spawn_aliens)
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(reset_alien_spawning))
+            // Both draw from the GameRng, in a fixed order so a seed always plays out the same
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick()
                     .with_system(alien_spawning_randomize_angle)
-                    .with_system(alien_cleanup)
-                    .with_system(alien_death),
+                    .with_system(spawn_aliens.after(alien_spawning_randomize_angle)),
+            )
+            // The deaths of the tick first, then the living aliens pick their targets (see targeting) and move.
+            // The physics moves them at the end of the tick
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick()
+                    .with_system(alien_death.after(death_timers).after(spawn_aliens))
+                    .with_system(alien_cleanup.after(alien_death))
+                    .with_system(alien_ai.after(alien_cleanup).after(update_flow_field)),
             );
     }
 }
@@ -63,29 +97,23 @@ impl Default for Alien {
     }
 }
 
-#[derive(Resource, Clone)]
-pub struct AlienModel(Option<Handle<Scene>>);
-
-pub fn register_aliens(ass: Res<AssetServer>, mut res: ResMut<AlienModel>) {
-    let gltf = ass.load("spacekit_2/Models/GLTF format/alien.glb#Scene0");
-    res.0 = gltf.into();
-}
-
 // Returns a modulo-based function which spawns enemies in waves.
 // More details in the TDD
+// No aliens spawn during the grace period at the start of the game, it depends on the difficulty
 pub fn get_probability_to_spawn_an_alien(
     t: Duration,
+    grace_period: Duration,
     building_count: u32,
     alien_count: u32,
 ) -> f32 {
     // FOR TESTING
     let t = t + Duration::from_secs(30);
 
-    if t < GRACE_PERIOD {
+    if t < grace_period {
         return 0.;
     } else {
         let mut secs = t.as_millis() as f32 / (1000.);
-        secs -= GRACE_PERIOD.as_secs_f32();
+        secs -= grace_period.as_secs_f32();
         // let mut res = ((f32::sin(x.powf(1.3) / 2.)) + 0.3 + (x / 50.).powf(2.4)) * (x / 15.);
         let x = secs;
 
@@ -111,21 +139,55 @@ pub fn get_probability_to_spawn_an_alien(
 mod test_alien_spawn_prob {
     use std::time::Duration;
 
-    use crate::aliens::alien::get_probability_to_spawn_an_alien;
+    use crate::aliens::alien::{aliens_to_spawn, get_probability_to_spawn_an_alien};
+
+    const GRACE: Duration = Duration::from_secs(10);
 
     #[test]
     fn print_probabilities_over_time() {
         for i in 1..(30 * 60) {
-            let prob = get_probability_to_spawn_an_alien(Duration::from_millis(i * 100), 0, 0);
+            let prob =
+                get_probability_to_spawn_an_alien(Duration::from_millis(i * 100), GRACE, 0, 0);
             println!("{}", prob);
         }
 
         let total: f32 = (1..120)
-            .map(|i| get_probability_to_spawn_an_alien(Duration::from_secs(i), 0, 0))
+            .map(|i| get_probability_to_spawn_an_alien(Duration::from_secs(i), GRACE, 0, 0))
             .sum();
         println!("Total: {}", total * 12.5);
         assert_eq!(1, 1);
     }
+
+    #[test]
+    fn nothing_spawns_during_the_grace_period() {
+        let grace = Duration::from_secs(60);
+        assert_eq!(
+            get_probability_to_spawn_an_alien(Duration::from_secs(29), grace, 0, 0),
+            0.
+        );
+        assert!(get_probability_to_spawn_an_alien(Duration::from_secs(110), grace, 0, 0) > 0.);
+    }
+
+    #[test]
+    fn endless_waves_are_not_capped() {
+        assert_eq!(aliens_to_spawn(0.5, true, 0.4), 1);
+        assert_eq!(aliens_to_spawn(0.5, true, 0.6), 0);
+        assert_eq!(aliens_to_spawn(2.5, true, 0.9), 1);
+        assert_eq!(aliens_to_spawn(2.5, false, 0.4), 3);
+        assert_eq!(aliens_to_spawn(2.5, false, 0.6), 2);
+        assert_eq!(aliens_to_spawn(0., false, 0.), 0);
+    }
+}
+
+// How many aliens to spawn this tick for the given probability, `roll` is a random number between 0 and 1.
+// In a standard game there's at most one per tick, which caps the waves.
+// In endless mode the probability keeps climbing past 1 and the waves with it
+pub fn aliens_to_spawn(prob: f32, capped: bool, roll: f32) -> u32 {
+    if capped {
+        return (roll < prob) as u32;
+    }
+    let whole = prob.max(0.).floor();
+    return whole as u32 + (roll < prob - whole) as u32;
 }
 
 #[derive(Resource, Clone, Debug)]
@@ -138,6 +200,13 @@ pub struct AlienSpawnAngle {
     deviation: f32,
     timer: Timer,
 }
+impl AlienSpawnAngle {
+    // The bearing the aliens are currently coming from, in radians around the base center
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+}
+
 impl Default for AlienSpawnAngle {
     fn default() -> Self {
         AlienSpawnAngle {
@@ -148,12 +217,22 @@ impl Default for AlienSpawnAngle {
     }
 }
 
+// The aliens of the last game are gone, and every game starts with the aliens coming from the same side
+pub fn reset_alien_spawning(mut count: ResMut<AlienCount>, mut angle: ResMut<AlienSpawnAngle>) {
+    *count = AlienCount::default();
+    *angle = AlienSpawnAngle::default();
+}
+
 // All the aliens should come from a similar spot, but so that they don't always come from the same one
 // we change the bearing from which they come every 20-40s
-pub fn alien_spawning_randomize_angle(mut res: ResMut<AlienSpawnAngle>, time: Res<Time>) {
+pub fn alien_spawning_randomize_angle(
+    mut res: ResMut<AlienSpawnAngle>,
+    time: Res<GameplayClock>,
+    mut rng: ResMut<GameRng>,
+) {
     res.timer.tick(time.delta());
     if res.timer.finished() {
-        let mut rng = rand::thread_rng();
+        let rng = &mut rng.0;
         let min_d = 10_f32;
         let max_d = 30_f32;
         let dur = (rng.gen::<f32>() * (max_d - min_d)) + min_d;
@@ -180,72 +259,94 @@ pub fn spawn_aliens(
     mut commands: Commands,
     mut count: ResMut<AlienCount>,
     grid: Res<Grid>,
-    model: Res<AlienModel>,
+    templates: Res<AlienTemplates>,
     time: Res<InGameTime>,
+    nests: Query<(&Transform, &Nest, &Health)>,
+    rules: Res<GameRules>,
+    multiplier: Res<SpawnRateMultiplier>,
+    mut rng: ResMut<GameRng>,
     mut ev_w: EventWriter<AlienSpawnEvent>
 ) {
     // let mesh: &Mesh =
     //     Assets::get(Assets, &ass.load("spacekit_2/Models/GLTF format/alien.glb#Scene0")).unwrap();
-    let mut rng = rand::thread_rng();
+    let rng = &mut rng.0;
 
-    let prob = get_probability_to_spawn_an_alien(
+    let mut prob = get_probability_to_spawn_an_alien(
         time.timer.elapsed(),
+        rules.grace_period(),
         grid.get_square_count() as u32,
         count.count,
-    );
-
-    if rng.gen::<f32>() > prob {
-        return;
+    ) * rules.spawn_rate_multiplier()
+        * multiplier.0;
+
+    // Every living nest adds its strength to the waves.
+    // At the start of the game all of them together spawn as many aliens as the function says
+    let nests = nests
+        .iter()
+        .filter(|(_, n, h)| !n.destroyed && h.hp > 0)
+        .collect::<Vec<_>>();
+    let strength: f32 = nests.iter().map(|(_, n, _)| n.strength).sum();
+    if !nests.is_empty() {
+        prob *= strength / NEST_COUNT as f32;
     }
 
-    let angle = angle.angle * rng.gen::<f32>() * angle.deviation;
-
-    let mut x = grid.base_center.x + grid.center_radius * f32::cos(angle);
-    let mut z = grid.base_center.z + grid.center_radius * f32::sin(angle);
-    x += rng.gen::<f32>() * 2.;
-    z += rng.gen::<f32>() * 2.;
-
-    // println!("Spawning an alien at {}, {}", x, z);
-    ev_w.send(AlienSpawnEvent { point: Vec3::new(x, 0.1, z) });
-    count.count += 1;
-    commands
-        .spawn((
-            Alien::default(),
-            RigidBody::Dynamic,
-            AudioType::Alien,
-            Health::new(200),
-            LockedAxes::ROTATION_LOCKED_X | LockedAxes::ROTATION_LOCKED_Z,
-            SpatialBundle {
-                transform: Transform::from_xyz(x, 0.5, z), //.with_scale(Vec3::new(2.0,2.0,2.0)),
-                ..default()
-            },
-            Collider::cylinder(0.4, 0.3),
-            Friction::default(),
-            CollisionGroups::new(Group::GROUP_10, Group::GROUP_1),
-            Velocity { ..default() },
-            TargetSelecting {
-                range: 2.5,
-                target: None,
-            },
-            DamageDealing {
-                cooldown: Timer::from_seconds(0.5, TimerMode::Repeating),
-                damage: 5,
-            },
-        ))
-        .with_children(|c| {
-            c.spawn((SceneBundle {
-                scene: model.0.clone().unwrap(),
-                transform: Transform::from_xyz(-2.0, -0.5, -1.5),
-                ..default()
-            },));
-        });
+    for _ in 0..aliens_to_spawn(prob, !rules.endless(), rng.gen()) {
+        // Pick a nest, the stronger ones more often
+        let mut roll = rng.gen::<f32>() * strength;
+        let nest = nests
+            .iter()
+            .find(|(_, n, _)| {
+                roll -= n.strength;
+                roll < 0.
+            })
+            .or(nests.last());
+
+        let (x, z) = match nest {
+            Some((t, ..)) => {
+                let angle = rng.gen::<f32>() * 2. * PI;
+                let distance = rng.gen::<f32>() * NEST_SPAWN_RADIUS;
+                (
+                    t.translation.x + distance * f32::cos(angle),
+                    t.translation.z + distance * f32::sin(angle),
+                )
+            }
+            // Without any nests they come from around the base
+            None => {
+                let angle = angle.angle * rng.gen::<f32>() * angle.deviation;
+
+                let x = grid.base_center.x + grid.center_radius * f32::cos(angle);
+                let z = grid.base_center.z + grid.center_radius * f32::sin(angle);
+                (x + rng.gen::<f32>() * 2., z + rng.gen::<f32>() * 2.)
+            }
+        };
+
+        let template = match templates.choose(time.timer.elapsed(), rng) {
+            Some(template) => template,
+            None => return,
+        };
+
+        // println!("Spawning an alien at {}, {}", x, z);
+        ev_w.send(AlienSpawnEvent { point: Vec3::new(x, 0.1, z) });
+        count.count += 1;
+        template.spawn(&mut commands, Vec3::new(x, 0.5, z));
+    }
 }
 
-// Makes the aliens target the nearest building
-// Todo consider AlienTarget.priority
+// Makes the aliens walk to their target, around any other buildings in the way.
+// The targets are picked in targeting
 pub fn alien_ai(
-    mut aliens: Query<(&mut Transform, &mut Velocity, &Alien, &mut TargetSelecting)>,
+    mut aliens: Query<(
+        &mut Transform,
+        &mut Velocity,
+        &Alien,
+        &mut TargetSelecting,
+        &AlienSpeed,
+        &AlienBehavior,
+        &mut AlienPath,
+    )>,
     targets: Query<(&Transform, &AlienTarget, Entity, &Health), Without<Alien>>,
+    grid: Res<Grid>,
+    flow_field: Res<FlowField>,
     _time: Res<Time>,
 ) {
     for mut alien in aliens.iter_mut() {
@@ -256,7 +357,7 @@ pub fn alien_ai(
         let alien_pos = alien.0.translation;
 
         // If the target exists turn and run towards it
-        if let Some((t, _, _, h)) = alien.3.target.and_then(|e| targets.get(e).ok()) {
+        if let Some((t, _, target, h)) = alien.3.target.and_then(|e| targets.get(e).ok()) {
             
             // Reset the target if it's dead
             // Entity can exist even if it's dead - just for the animation
@@ -264,68 +365,127 @@ pub fn alien_ai(
                 alien.3.target = None;
             }
 
-            // Turn towards target
-            alien.0.look_at(t.translation, Vec3::Y);
+            // Aliens going for the main base or a turret follow the shared flow field while it leads to their target.
+            // Aliens going for other buildings, for a goal further away than the closest one, or outside of the field,
+            // use their own A* path
+            let flow = match flow_field.is_goal(target) {
+                true => flow_field.sample(alien_pos).filter(|flow| flow.goal == target),
+                false => None,
+            };
+            let heading = match flow {
+                Some(flow) => {
+                    // Next to the goal we go for the building itself
+                    if flow.distance > 1.5 {
+                        alien_pos + flow.direction
+                    } else {
+                        t.translation
+                    }
+                }
+                None => path_heading(&mut alien.6, alien_pos, t.translation, target, &grid),
+            };
+
+            // Turn towards where we're going
+            alien.0.look_at(Vec3::new(heading.x, alien_pos.y, heading.z), Vec3::Y);
 
             // Extract only the rotation around the Y axis
             // Otherwise the alien will tilt slightly towards the ground
             let rot = alien.0.rotation.to_euler(EulerRot::YXZ).0;
             alien.0.rotation = Quat::from_axis_angle(Vec3::Y, rot);
 
-            // Set velocity towards target
+            // Ranged aliens stop a bit inside their range, so they don't step out of it while shooting
+            let in_range = alien_pos.distance(t.translation) < alien.3.range * 0.8;
+            let AlienSpeed(mut speed) = *alien.4;
+            if *alien.5 == AlienBehavior::Ranged && in_range {
+                speed = 0.;
+            }
+
+            // Set velocity towards the next waypoint
+            let direction = Vec3::new(heading.x - alien_pos.x, 0., heading.z - alien_pos.z);
             *alien.1 = Velocity {
-                linvel: (t.translation - alien.0.translation).normalize() * ALIEN_SPEED,
+                linvel: direction.normalize_or_zero() * speed,
                 angvel: Vec3::ZERO,
             };
             
         } else {
-            // Set target
-            // Find the closest entity with the AlienTarget component
-            let target = targets
-                .iter()
-                .min_by(|(transform_a, ..), (transform_b, ..)| {
-                    alien_pos
-                        .distance(transform_a.translation)
-                        .total_cmp(&alien_pos.distance(transform_b.translation))
-                });
-            alien.3.target = target.map(|(_, _, e, ..)| e);
+            // Nothing left to attack, select_alien_targets gives it a new target once there is one
+            *alien.1 = Velocity::zero();
         }
     }
 }
 
+// Where to walk next on the alien's own A* path to the target
+fn path_heading(
+    path: &mut AlienPath,
+    alien_pos: Vec3,
+    target_pos: Vec3,
+    target: Entity,
+    grid: &Grid,
+) -> Vec3 {
+    // Recalculate the path if the target changed or something was built or destroyed
+    if !path.is_valid_for(target, grid) {
+        let squares = find_path(
+            &grid.blocked_squares,
+            Grid::get_square_index(alien_pos),
+            Grid::get_square_index(target_pos),
+            Some(target),
+        );
+        // If there is no path, the waypoints stay empty and the alien goes straight for the target,
+        // fighting its way through like it used to
+        *path = AlienPath {
+            waypoints: squares.map(|p| path_to_waypoints(&p)).unwrap_or_default(),
+            target: Some(target),
+            grid_version: grid.version,
+        };
+    }
+
+    // Skip the waypoints we've reached.
+    // The last one is the target's square, from there we go for the target itself
+    while path.waypoints.len() > 1
+        && horizontal_distance(alien_pos, path.waypoints[0]) < WAYPOINT_REACHED_DISTANCE
+    {
+        path.waypoints.remove(0);
+    }
+    match path.waypoints.first() {
+        Some(waypoint) if path.waypoints.len() > 1 => *waypoint,
+        _ => target_pos,
+    }
+}
+
+fn horizontal_distance(a: Vec3, b: Vec3) -> f32 {
+    Vec2::new(a.x, a.z).distance(Vec2::new(b.x, b.z))
+}
+
 // Disables the alien's damage dealing, plays the dying animation and schedules its despawning
 pub fn alien_death(
-    mut aliens: Query<(
-        &mut Transform,
-        &mut Alien,
-        &mut Velocity,
-        &mut DamageDealing,
-    )>,
+    mut aliens: Query<(&mut Alien, &mut Velocity, &mut DamageDealing, &Health)>,
     mut ev: EventReader<DeathEvent>,
     mut count: ResMut<AlienCount>,
+    mut resources: ResMut<ResourceState>,
+    mut stats: ResMut<GameStats>,
+    rules: Res<GameRules>,
 ) {
     for e in ev.iter() {
-        if let Ok((mut t, mut a, mut vel, mut dmg)) = aliens.get_mut(e.entity) {
+        if let Ok((mut a, mut vel, mut dmg, health)) = aliens.get_mut(e.entity) {
             if a.alive {
+                // The death animation is played by alien_animation
                 count.count -= 1;
-                let r = t.rotation;
-                t.rotate_axis(r.mul_vec3(Vec3::X), PI / 2.0);
                 vel.linvel = Vec3::splat(0.);
                 dmg.damage = 0;
                 a.alive = false;
+                // Only the kills of the turrets drop loot, the kills themselves are counted in the stats
+                if e.killer.is_some() {
+                    let loot = rules.loot(health.max_hp);
+                    resources.add(ResourceType::Ore, loot);
+                    stats.resources_earned.add(ResourceType::Ore, loot as u32);
+                }
             }
         }
     }
 }
 
-// Cleans up the dead aliens
-pub fn alien_cleanup(
-    time: Res<Time>,
-    mut query: Query<(&mut Health, Entity), With<Alien>>,
-    mut commands: Commands,
-) {
-    for mut x in query.iter_mut() {
-        x.0.dead_for_timer.tick(time.delta());
+// Cleans up the dead aliens once their death timer, ticked by death_timers, runs out
+pub fn alien_cleanup(query: Query<(&Health, Entity), With<Alien>>, mut commands: Commands) {
+    for x in query.iter() {
         if x.0.dead_for_timer.finished() {
             if let Some(e) = commands.get_entity(x.1) {
                 e.despawn_recursive();
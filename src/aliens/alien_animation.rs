@@ -0,0 +1,333 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+
+use crate::{
+    buildings::defensive_buildings::{DamageDealing, TargetSelecting},
+    health::health::Health,
+    AppState,
+};
+
+use super::alien::{Alien, ALIEN_SPEED};
+
+// Animates the alien models with an AnimationPlayer and a small state machine: idle, run, attack and death.
+// The alien model doesn't come with any animation clips, but its limbs are separate named nodes
+// (armLeft, armRight, legLeft, legRight, head), so the clips are built here out of rotations of those nodes.
+// The player is put on the model's "alien" node, which every path below starts from.
+
+pub struct AlienAnimationPlugin;
+
+impl Plugin for AlienAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AlienAnimations>()
+            .add_startup_system(build_alien_animations)
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(attach_animation_players)
+                    .with_system(animate_aliens),
+            );
+    }
+}
+
+// The name of the model's root node, the animation player goes on it
+const MODEL_ROOT: &str = "alien";
+
+// The lengths of the clips in seconds, the player speed stretches them to match the game
+const IDLE_LENGTH: f32 = 2.;
+const RUN_LENGTH: f32 = 0.6;
+const ATTACK_LENGTH: f32 = 1.;
+const DEATH_LENGTH: f32 = 1.;
+// Aliens moving slower than this are standing
+const RUN_THRESHOLD: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum AlienAnimationState {
+    #[default]
+    Idle,
+    Run,
+    Attack,
+    Death,
+}
+
+#[derive(Component, Clone, Debug, Default)]
+pub struct AlienAnimator {
+    pub state: AlienAnimationState,
+    // The model's entity with the AnimationPlayer, found once the scene has spawned
+    pub player: Option<Entity>,
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct AlienAnimations {
+    idle: Handle<AnimationClip>,
+    run: Handle<AnimationClip>,
+    attack: Handle<AnimationClip>,
+    death: Handle<AnimationClip>,
+}
+
+// Death can't be left. Attacking wins over running, so aliens pushed around by the crowd still swing at their target
+pub fn next_animation_state(
+    current: AlienAnimationState,
+    alive: bool,
+    speed: f32,
+    attacking: bool,
+) -> AlienAnimationState {
+    if current == AlienAnimationState::Death || !alive {
+        return AlienAnimationState::Death;
+    }
+    if attacking {
+        return AlienAnimationState::Attack;
+    }
+    if speed > RUN_THRESHOLD {
+        return AlienAnimationState::Run;
+    }
+    return AlienAnimationState::Idle;
+}
+
+fn path(node: &str) -> EntityPath {
+    let mut parts = vec![Name::new(MODEL_ROOT)];
+    if node != MODEL_ROOT {
+        parts.push(Name::new(node.to_string()));
+    }
+    EntityPath { parts }
+}
+
+// Rotations around one axis, given in degrees at the given times
+fn rotation_curve(axis: Vec3, keys: &[(f32, f32)]) -> VariableCurve {
+    VariableCurve {
+        keyframe_timestamps: keys.iter().map(|(t, _)| *t).collect(),
+        keyframes: Keyframes::Rotation(
+            keys.iter()
+                .map(|(_, degrees)| Quat::from_axis_angle(axis, degrees * PI / 180.))
+                .collect(),
+        ),
+    }
+}
+
+fn idle_clip() -> AnimationClip {
+    let l = IDLE_LENGTH;
+    let mut clip = AnimationClip::default();
+    clip.add_curve_to_path(
+        path("head"),
+        rotation_curve(
+            Vec3::Y,
+            &[(0., 0.), (l * 0.25, 12.), (l * 0.75, -12.), (l, 0.)],
+        ),
+    );
+    for arm in ["armLeft", "armRight"] {
+        clip.add_curve_to_path(
+            path(arm),
+            rotation_curve(Vec3::X, &[(0., 0.), (l * 0.5, -6.), (l, 0.)]),
+        );
+    }
+    return clip;
+}
+
+fn run_clip() -> AnimationClip {
+    let l = RUN_LENGTH;
+    // Legs swing opposite to each other, the arms opposite to the legs
+    let swing = |degrees: f32| [(0., degrees), (l * 0.5, -degrees), (l, degrees)];
+    let mut clip = AnimationClip::default();
+    clip.add_curve_to_path(path("legLeft"), rotation_curve(Vec3::X, &swing(35.)));
+    clip.add_curve_to_path(path("legRight"), rotation_curve(Vec3::X, &swing(-35.)));
+    clip.add_curve_to_path(path("armLeft"), rotation_curve(Vec3::X, &swing(-25.)));
+    clip.add_curve_to_path(path("armRight"), rotation_curve(Vec3::X, &swing(25.)));
+    return clip;
+}
+
+// The arms are raised slowly and come down at the very end, when DamageDealing deals the damage
+fn attack_clip() -> AnimationClip {
+    let l = ATTACK_LENGTH;
+    let mut clip = AnimationClip::default();
+    for arm in ["armLeft", "armRight"] {
+        clip.add_curve_to_path(
+            path(arm),
+            rotation_curve(
+                Vec3::X,
+                &[(0., 0.), (l * 0.8, -150.), (l * 0.9, -40.), (l, 0.)],
+            ),
+        );
+    }
+    clip.add_curve_to_path(
+        path("head"),
+        rotation_curve(
+            Vec3::X,
+            &[(0., 0.), (l * 0.8, -10.), (l * 0.9, 15.), (l, 0.)],
+        ),
+    );
+    return clip;
+}
+
+// Falls over backwards and goes limp
+fn death_clip() -> AnimationClip {
+    let l = DEATH_LENGTH;
+    let mut clip = AnimationClip::default();
+    clip.add_curve_to_path(
+        path(MODEL_ROOT),
+        rotation_curve(Vec3::X, &[(0., 0.), (l * 0.3, -90.), (l, -90.)]),
+    );
+    for arm in ["armLeft", "armRight"] {
+        clip.add_curve_to_path(
+            path(arm),
+            rotation_curve(Vec3::X, &[(0., 0.), (l * 0.4, -60.), (l, -70.)]),
+        );
+    }
+    return clip;
+}
+
+pub fn build_alien_animations(
+    mut animations: ResMut<AlienAnimations>,
+    mut clips: ResMut<Assets<AnimationClip>>,
+) {
+    *animations = AlienAnimations {
+        idle: clips.add(idle_clip()),
+        run: clips.add(run_clip()),
+        attack: clips.add(attack_clip()),
+        death: clips.add(death_clip()),
+    };
+}
+
+// The scene is only spawned a few frames after the alien, so we keep looking until the model's root node is there
+pub fn attach_animation_players(
+    mut commands: Commands,
+    mut aliens: Query<(Entity, &mut AlienAnimator)>,
+    children: Query<&Children>,
+    names: Query<&Name>,
+) {
+    for (alien, mut animator) in aliens.iter_mut() {
+        if animator.player.is_some() {
+            continue;
+        }
+        let mut stack = vec![alien];
+        while let Some(e) = stack.pop() {
+            if names
+                .get(e)
+                .map_or(false, |name| name.as_str() == MODEL_ROOT)
+            {
+                commands.entity(e).insert(AnimationPlayer::default());
+                animator.player = Some(e);
+                break;
+            }
+            if let Ok(c) = children.get(e) {
+                stack.extend(c.iter());
+            }
+        }
+    }
+}
+
+pub fn animate_aliens(
+    mut aliens: Query<(
+        &mut AlienAnimator,
+        &Alien,
+        &Transform,
+        &Velocity,
+        &TargetSelecting,
+        &DamageDealing,
+        &Health,
+    )>,
+    targets: Query<&Transform, Without<Alien>>,
+    mut players: Query<&mut AnimationPlayer>,
+    animations: Res<AlienAnimations>,
+) {
+    for (mut animator, alien, transform, velocity, target_selecting, damage, health) in
+        aliens.iter_mut()
+    {
+        let mut player = match animator.player.and_then(|e| players.get_mut(e).ok()) {
+            Some(player) => player,
+            None => continue,
+        };
+
+        let speed = Vec3::new(velocity.linvel.x, 0., velocity.linvel.z).length();
+        // Same check as damage_dealing, exploders don't swing at anything
+        let attacking = damage.damage > 0
+            && target_selecting
+                .target
+                .and_then(|t| targets.get(t).ok())
+                .map_or(false, |t| {
+                    t.translation.distance(transform.translation) <= target_selecting.range
+                });
+
+        let state = next_animation_state(animator.state, alien.alive, speed, attacking);
+        if state != animator.state {
+            animator.state = state;
+            match state {
+                AlienAnimationState::Idle => {
+                    player.play(animations.idle.clone()).repeat();
+                }
+                AlienAnimationState::Run => {
+                    player.play(animations.run.clone()).repeat();
+                }
+                AlienAnimationState::Attack => {
+                    // Start where the cooldown is, so the arms come down when the damage is dealt
+                    player.play(animations.attack.clone()).repeat();
+                    player.set_elapsed(damage.cooldown.percent() * ATTACK_LENGTH);
+                }
+                AlienAnimationState::Death => {
+                    player.play(animations.death.clone());
+                }
+            }
+        }
+
+        // Keep the speed in line with the game, it can change while the state doesn't
+        let player_speed = match state {
+            AlienAnimationState::Idle => 1.,
+            // Matches the steps to the ground covered, so slow big aliens take slow big steps
+            AlienAnimationState::Run => speed / ALIEN_SPEED,
+            // One swing per hit
+            AlienAnimationState::Attack => {
+                ATTACK_LENGTH / damage.cooldown.duration().as_secs_f32().max(0.01)
+            }
+            // Lying on the ground until the body is cleaned up
+            AlienAnimationState::Death => {
+                DEATH_LENGTH / health.dead_for_timer.duration().as_secs_f32().max(0.01)
+            }
+        };
+        player.set_speed(player_speed);
+    }
+}
+
+#[cfg(test)]
+mod test_alien_animation {
+    use bevy::prelude::*;
+
+    use super::{
+        attack_clip, death_clip, idle_clip, next_animation_state, run_clip,
+        AlienAnimationState::{self, *},
+        ATTACK_LENGTH, DEATH_LENGTH, IDLE_LENGTH, RUN_LENGTH,
+    };
+
+    #[test]
+    fn state_machine() {
+        assert_eq!(next_animation_state(Idle, true, 0., false), Idle);
+        assert_eq!(next_animation_state(Idle, true, 3., false), Run);
+        assert_eq!(next_animation_state(Run, true, 3., true), Attack);
+        assert_eq!(next_animation_state(Attack, true, 0., false), Idle);
+        assert_eq!(next_animation_state(Attack, false, 0., true), Death);
+        // There's no coming back
+        let dead: AlienAnimationState = Death;
+        assert_eq!(next_animation_state(dead, true, 3., true), Death);
+    }
+
+    #[test]
+    fn clip_lengths() {
+        assert_eq!(idle_clip().duration(), IDLE_LENGTH);
+        assert_eq!(run_clip().duration(), RUN_LENGTH);
+        assert_eq!(attack_clip().duration(), ATTACK_LENGTH);
+        assert_eq!(death_clip().duration(), DEATH_LENGTH);
+    }
+
+    // The looping clips have to end where they start, or the limbs snap every loop
+    #[test]
+    fn loops_are_seamless() {
+        for clip in [idle_clip(), run_clip(), attack_clip()] {
+            for curves in clip.curves().values() {
+                for curve in curves.iter() {
+                    if let Keyframes::Rotation(keys) = &curve.keyframes {
+                        let (first, last) = (keys.first().unwrap(), keys.last().unwrap());
+                        assert!(first.angle_between(*last) < 0.001);
+                    }
+                }
+            }
+        }
+    }
+}
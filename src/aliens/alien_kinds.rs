@@ -0,0 +1,573 @@
+use std::time::Duration;
+
+use bevy::{asset::HandleId, prelude::*, utils::HashMap};
+use bevy_rapier3d::prelude::{
+    Collider, CollisionGroups, Friction, Group, LockedAxes, RigidBody, Velocity,
+};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{
+    audio::audio::AudioType,
+    buildings::defensive_buildings::{damage_dealing, AlienTarget, DamageDealing, TargetSelecting},
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayStage},
+    health::health::{DamageEvent, DeathEvent, Health},
+    AppState,
+};
+
+use super::{
+    alien::{Alien, ALIEN_SPEED},
+    alien_animation::AlienAnimator,
+    pathfinding::AlienPath,
+    targeting::TargetPreference,
+};
+
+// In this module we define all the kinds of aliens, the same way buildings are defined in building_bundles.
+// Every kind is a template that can be cloned and spawned into the world, the wave spawner picks which one.
+
+pub struct AlienKindsPlugin;
+
+impl Plugin for AlienKindsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(AlienTemplates {
+            templates: Vec::new(),
+        })
+        .add_startup_system(register_alien_kinds)
+        .add_system_set(SystemSet::on_update(AppState::InGame).with_system(tint_alien_models))
+        // Damage is dealt on the ticks, see damage_dealing
+        .add_system_set_to_stage(
+            GameplayStage,
+            on_gameplay_tick().with_system(exploder_detonation.after(damage_dealing)),
+        );
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlienKind {
+    Drone,
+    Swarmer,
+    Brute,
+    Spitter,
+    Exploder,
+    // Only spawned by the boss waves, see bosses
+    Boss,
+}
+
+// How an alien attacks its target
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub enum AlienBehavior {
+    // Runs into the target and hits it
+    Melee,
+    // Stops once the target is in range and shoots from there
+    Ranged,
+    // Blows up next to the target, damaging every building in the radius
+    Exploder { damage: i32, radius: f32 },
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AlienSpeed(pub f32);
+
+// The radius of the collider, for keeping the aliens apart in crowd
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AlienRadius(pub f32);
+
+// The colour the alien model is multiplied with, so the kinds can be told apart.
+// Removed once the model is tinted
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AlienTint(pub Color);
+
+#[derive(Clone, Debug)]
+pub struct AlienTemplate {
+    pub kind: AlienKind,
+    pub name: &'static str,
+    pub health: i32,
+    pub speed: f32,
+    pub damage: i32,
+    // In milliseconds, like in DamageDealing::new
+    pub cooldown: u32,
+    pub range: f32,
+    pub behavior: AlienBehavior,
+    // Which buildings this kind likes to go for, see targeting
+    pub preference: TargetPreference,
+    // The collider is a cylinder
+    pub half_height: f32,
+    pub radius: f32,
+    pub scene_handle: Handle<Scene>,
+    pub scene_offset: Transform,
+    pub tint: Color,
+    // How likely this kind is to be picked compared to the others
+    pub spawn_weight: f32,
+    // The kind only starts spawning after the game has run for this long
+    pub appears_after: Duration,
+}
+
+impl AlienTemplate {
+    // The model and its collider, which are scaled together. All the models are based on the kit's alien, with the same parts.
+    // The stats are the drone's, the kinds are built with struct update syntax on top of this
+    fn with_model(file: &str, scale: f32, ass: &Res<AssetServer>) -> Self {
+        // The collider of the original alien model
+        let half_height = 0.4 * scale;
+        AlienTemplate {
+            kind: AlienKind::Drone,
+            name: "Drone",
+            health: 200,
+            speed: ALIEN_SPEED,
+            damage: 5,
+            cooldown: 500,
+            range: 2.5,
+            behavior: AlienBehavior::Melee,
+            preference: TargetPreference::default(),
+            half_height,
+            radius: 0.3 * scale,
+            scene_handle: ass.load(format!("{}#Scene0", file)),
+            // The model's origin is in its corner, not its center
+            scene_offset: Transform {
+                translation: Vec3::new(-2. * scale, -(half_height + 0.1), -1.5 * scale),
+                scale: Vec3::splat(scale),
+                ..Default::default()
+            },
+            tint: Color::WHITE,
+            spawn_weight: 0.,
+            appears_after: Duration::ZERO,
+        }
+    }
+
+    // Spawns the alien at the given point and returns the entity
+    pub fn spawn(&self, commands: &mut Commands, point: Vec3) -> Entity {
+        // Bundles are only implemented for tuples of up to 15, so the components are grouped
+        let mut c = commands.spawn((
+            (
+                Alien::default(),
+                self.kind,
+                self.behavior,
+                self.preference,
+                AlienSpeed(self.speed),
+                AlienRadius(self.radius),
+                AlienPath::default(),
+                AlienAnimator::default(),
+                AudioType::Alien,
+                Health::new(self.health),
+                TargetSelecting::new(self.range),
+                DamageDealing::new(self.damage, self.cooldown),
+            ),
+            // The physics
+            (
+                RigidBody::Dynamic,
+                LockedAxes::ROTATION_LOCKED_X | LockedAxes::ROTATION_LOCKED_Z,
+                Collider::cylinder(self.half_height, self.radius),
+                Friction::default(),
+                CollisionGroups::new(Group::GROUP_10, Group::GROUP_1),
+                Velocity { ..default() },
+            ),
+            SpatialBundle {
+                transform: Transform::from_translation(point),
+                ..default()
+            },
+        ));
+
+        // White would leave the model unchanged, no need to tint it
+        if self.tint != Color::WHITE {
+            c.insert(AlienTint(self.tint));
+        }
+
+        c.with_children(|parent| {
+            parent.spawn(SceneBundle {
+                scene: self.scene_handle.clone(),
+                transform: self.scene_offset,
+                ..default()
+            });
+        });
+
+        return c.id();
+    }
+}
+
+// The global resource containing all the alien kinds
+#[derive(Resource)]
+pub struct AlienTemplates {
+    pub templates: Vec<AlienTemplate>,
+}
+
+impl AlienTemplates {
+    // Picks a random kind out of the ones that can appear at this point of the game, based on their weights
+    pub fn choose(&self, elapsed: Duration, rng: &mut impl Rng) -> Option<&AlienTemplate> {
+        let available = self
+            .templates
+            .iter()
+            .filter(|t| t.appears_after <= elapsed && t.spawn_weight > 0.)
+            .collect::<Vec<_>>();
+
+        let total: f32 = available.iter().map(|t| t.spawn_weight).sum();
+        if total <= 0. {
+            return None;
+        }
+
+        let mut roll = rng.gen::<f32>() * total;
+        for t in available.iter() {
+            if roll < t.spawn_weight {
+                return Some(t);
+            }
+            roll -= t.spawn_weight;
+        }
+        // Floating point rounding can leave a tiny bit of the roll
+        return available.last().copied();
+    }
+
+    pub fn get(&self, kind: AlienKind) -> Option<&AlienTemplate> {
+        self.templates.iter().find(|t| t.kind == kind)
+    }
+}
+
+pub fn register_alien_kinds(mut templates: ResMut<AlienTemplates>, ass: Res<AssetServer>) {
+    // The original alien, everything else is balanced around it
+    templates.templates.push(AlienTemplate {
+        kind: AlienKind::Drone,
+        name: "Drone",
+        health: 200,
+        speed: ALIEN_SPEED,
+        damage: 5,
+        cooldown: 500,
+        range: 2.5,
+        behavior: AlienBehavior::Melee,
+        preference: TargetPreference::default(),
+        tint: Color::WHITE,
+        spawn_weight: 10.,
+        appears_after: Duration::ZERO,
+        ..AlienTemplate::with_model("spacekit_2/Models/GLTF format/alien.glb", 1., &ass)
+    });
+    // Fast and fragile, comes in large numbers and raids the generators
+    templates.templates.push(AlienTemplate {
+        kind: AlienKind::Swarmer,
+        name: "Swarmer",
+        health: 80,
+        speed: ALIEN_SPEED * 1.6,
+        damage: 3,
+        cooldown: 300,
+        range: 2.,
+        behavior: AlienBehavior::Melee,
+        preference: TargetPreference {
+            other: 3.,
+            ..default()
+        },
+        tint: Color::rgb(0.6, 1., 0.5),
+        spawn_weight: 6.,
+        appears_after: Duration::from_secs(60),
+        ..AlienTemplate::with_model("aliens/swarmer.glb", 0.7, &ass)
+    });
+    // Ranged, outranges the machine guns and goes after them
+    templates.templates.push(AlienTemplate {
+        kind: AlienKind::Spitter,
+        name: "Spitter",
+        health: 150,
+        speed: ALIEN_SPEED * 0.8,
+        damage: 8,
+        cooldown: 1500,
+        range: 9.,
+        behavior: AlienBehavior::Ranged,
+        preference: TargetPreference {
+            turret: 2.,
+            ..default()
+        },
+        tint: Color::rgb(0.6, 0.6, 1.),
+        spawn_weight: 3.,
+        appears_after: Duration::from_secs(180),
+        ..AlienTemplate::with_model("aliens/spitter.glb", 0.9, &ass)
+    });
+    // Runs into the base and blows up
+    templates.templates.push(AlienTemplate {
+        kind: AlienKind::Exploder,
+        name: "Exploder",
+        health: 100,
+        speed: ALIEN_SPEED * 1.2,
+        damage: 0,
+        cooldown: 1000,
+        range: 2.,
+        behavior: AlienBehavior::Exploder {
+            damage: 60,
+            radius: 4.,
+        },
+        preference: TargetPreference {
+            main_base: 2.,
+            ..default()
+        },
+        tint: Color::rgb(1., 0.5, 0.3),
+        spawn_weight: 2.,
+        appears_after: Duration::from_secs(240),
+        ..AlienTemplate::with_model("aliens/exploder.glb", 0.85, &ass)
+    });
+    // Slow and armored, deals a lot of damage per hit
+    templates.templates.push(AlienTemplate {
+        kind: AlienKind::Brute,
+        name: "Brute",
+        health: 800,
+        speed: ALIEN_SPEED * 0.6,
+        damage: 20,
+        cooldown: 1200,
+        range: 3.,
+        behavior: AlienBehavior::Melee,
+        preference: TargetPreference {
+            main_base: 1.5,
+            ..default()
+        },
+        tint: Color::rgb(1., 0.35, 0.35),
+        spawn_weight: 2.,
+        appears_after: Duration::from_secs(300),
+        ..AlienTemplate::with_model("aliens/brute.glb", 1.5, &ass)
+    });
+    // Huge and slow, never picked by the wave spawner. The boss waves scale its health up
+    templates.templates.push(AlienTemplate {
+        kind: AlienKind::Boss,
+        name: "Hive Queen",
+        health: 5000,
+        speed: ALIEN_SPEED * 0.5,
+        damage: 60,
+        cooldown: 1500,
+        range: 5.,
+        behavior: AlienBehavior::Melee,
+        preference: TargetPreference {
+            main_base: 3.,
+            ..default()
+        },
+        tint: Color::rgb(0.8, 0.3, 1.),
+        spawn_weight: 0.,
+        appears_after: Duration::ZERO,
+        ..AlienTemplate::with_model("aliens/boss.glb", 3., &ass)
+    });
+}
+
+// Each kind has its own model, the tint sets them apart further and makes them easy to tell apart from far away.
+// The models share the kit's materials, so we multiply them with the kind's tint.
+// The scene is only spawned a few frames after the alien, so we keep trying until it has materials.
+// The tinted materials are shared between all aliens of a kind
+pub fn tint_alien_models(
+    mut commands: Commands,
+    aliens: Query<(Entity, &AlienKind, &AlienTint)>,
+    children: Query<&Children>,
+    mut mesh_materials: Query<&mut Handle<StandardMaterial>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut tinted: Local<HashMap<(HandleId, AlienKind), Handle<StandardMaterial>>>,
+) {
+    for (alien, kind, tint) in aliens.iter() {
+        let mut found = false;
+        let mut stack = vec![alien];
+        while let Some(e) = stack.pop() {
+            if let Ok(c) = children.get(e) {
+                stack.extend(c.iter());
+            }
+            let mut handle = match mesh_materials.get_mut(e) {
+                Ok(handle) => handle,
+                Err(_) => continue,
+            };
+
+            let key = (handle.id(), *kind);
+            if !tinted.contains_key(&key) {
+                let mut material = match materials.get(&handle) {
+                    Some(material) => material.clone(),
+                    None => continue,
+                };
+                let [r, g, b, a] = material.base_color.as_rgba_f32();
+                let [tr, tg, tb, _] = tint.0.as_rgba_f32();
+                material.base_color = Color::rgba(r * tr, g * tg, b * tb, a);
+                tinted.insert(key, materials.add(material));
+            }
+            *handle = tinted[&key].clone();
+            found = true;
+        }
+
+        if found {
+            commands.entity(alien).remove::<AlienTint>();
+        }
+    }
+}
+
+// Exploders don't use DamageDealing, once they reach their target they damage everything around them and die
+pub fn exploder_detonation(
+    mut exploders: Query<(
+        Entity,
+        &Transform,
+        &AlienBehavior,
+        &TargetSelecting,
+        &mut Health,
+        &Alien,
+    )>,
+    mut targets: Query<(Entity, &Transform, &mut Health), (With<AlienTarget>, Without<Alien>)>,
+    mut ev: EventWriter<DeathEvent>,
+    mut damage_ev: EventWriter<DamageEvent>,
+) {
+    for (alien, transform, behavior, target_selecting, mut health, a) in exploders.iter_mut() {
+        let (damage, radius) = match behavior {
+            AlienBehavior::Exploder { damage, radius } => (*damage, *radius),
+            _ => continue,
+        };
+        if !a.alive || health.hp <= 0 {
+            continue;
+        }
+
+        let target_pos = match target_selecting.target.and_then(|t| targets.get(t).ok()) {
+            Some((_, t, _)) => t.translation,
+            None => continue,
+        };
+        if transform.translation.distance(target_pos) > target_selecting.range {
+            continue;
+        }
+
+        for (e, t, mut h) in targets.iter_mut() {
+            if h.hp <= 0 || t.translation.distance(transform.translation) > radius {
+                continue;
+            }
+            h.hp -= damage;
+            damage_ev.send(DamageEvent {
+                entity: e,
+                attacker: alien,
+                damage,
+            });
+            if h.hp <= 0 {
+                ev.send(DeathEvent {
+                    entity: e,
+                    killer: Some(alien),
+                });
+            }
+        }
+
+        health.hp = 0;
+        ev.send(DeathEvent {
+            entity: alien,
+            killer: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_alien_kinds {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        aliens::alien::Alien,
+        buildings::defensive_buildings::{AlienTarget, TargetSelecting},
+        health::health::{DamageEvent, DeathEvent, Health},
+    };
+
+    use super::{
+        exploder_detonation, AlienBehavior, AlienKind, AlienTemplate, AlienTemplates,
+        TargetPreference,
+    };
+
+    fn template(kind: AlienKind, spawn_weight: f32, appears_after: u64) -> AlienTemplate {
+        AlienTemplate {
+            kind,
+            name: "",
+            health: 100,
+            speed: 1.,
+            damage: 1,
+            cooldown: 1000,
+            range: 1.,
+            behavior: AlienBehavior::Melee,
+            preference: TargetPreference::default(),
+            half_height: 0.4,
+            radius: 0.3,
+            scene_handle: Handle::default(),
+            scene_offset: Transform::default(),
+            tint: Color::WHITE,
+            spawn_weight,
+            appears_after: Duration::from_secs(appears_after),
+        }
+    }
+
+    #[test]
+    fn only_unlocked_kinds_spawn() {
+        let templates = AlienTemplates {
+            templates: vec![
+                template(AlienKind::Drone, 1., 0),
+                template(AlienKind::Brute, 100., 300),
+            ],
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let t = templates.choose(Duration::from_secs(10), &mut rng).unwrap();
+            assert_eq!(t.kind, AlienKind::Drone);
+        }
+
+        let brutes = (0..100)
+            .filter(|_| {
+                templates
+                    .choose(Duration::from_secs(400), &mut rng)
+                    .unwrap()
+                    .kind
+                    == AlienKind::Brute
+            })
+            .count();
+        assert!(brutes > 80);
+    }
+
+    #[test]
+    fn nothing_to_choose_from() {
+        let templates = AlienTemplates {
+            templates: vec![template(AlienKind::Drone, 0., 0)],
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!(templates.choose(Duration::ZERO, &mut rng).is_none());
+    }
+
+    #[test]
+    fn exploders_report_their_damage() {
+        let mut app = App::new();
+        app.add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .add_system(exploder_detonation);
+        let target = |app: &mut App, x: f32, hp: i32| {
+            app.world
+                .spawn((
+                    Transform::from_xyz(x, 0., 0.),
+                    AlienTarget::default(),
+                    Health::new(hp),
+                ))
+                .id()
+        };
+        let wall = target(&mut app, 0., 100);
+        let turret = target(&mut app, 2., 30);
+        let far = target(&mut app, 20., 30);
+        let mut target_selecting = TargetSelecting::new(1.5);
+        target_selecting.target = Some(wall);
+        let exploder = app
+            .world
+            .spawn((
+                Transform::from_xyz(1., 0., 0.),
+                AlienBehavior::Exploder {
+                    damage: 40,
+                    radius: 3.,
+                },
+                target_selecting,
+                Health::new(10),
+                Alien::default(),
+            ))
+            .id();
+        app.update();
+
+        let mut damaged: Vec<(Entity, Entity, i32)> = app
+            .world
+            .resource_mut::<Events<DamageEvent>>()
+            .drain()
+            .map(|ev| (ev.entity, ev.attacker, ev.damage))
+            .collect();
+        damaged.sort();
+        let mut expected = vec![(wall, exploder, 40), (turret, exploder, 40)];
+        expected.sort();
+        assert_eq!(damaged, expected);
+        assert_eq!(app.world.get::<Health>(far).unwrap().hp, 30);
+
+        // The turret is credited to the exploder, the exploder itself has no killer
+        let deaths: Vec<(Entity, Option<Entity>)> = app
+            .world
+            .resource_mut::<Events<DeathEvent>>()
+            .drain()
+            .map(|ev| (ev.entity, ev.killer))
+            .collect();
+        assert!(deaths.contains(&(turret, Some(exploder))));
+        assert!(deaths.contains(&(exploder, None)));
+        assert!(!deaths.iter().any(|(e, _)| *e == wall));
+    }
+}
@@ -0,0 +1,308 @@
+use std::{f32::consts::PI, time::Duration};
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2, Color32, RichText},
+    EguiContext,
+};
+use rand::Rng;
+
+use crate::{
+    buildings::{
+        defensive_buildings::{damage_dealing, DamageDealing},
+        grid::Grid,
+    },
+    game_timer::{
+        game_timer::InGameTime,
+        gameplay_schedule::{on_gameplay_tick, GameplayClock, GameplayStage},
+    },
+    health::health::Health,
+    menu::menu::make_window,
+    rules::game_rules::GameRng,
+    AppState,
+};
+
+use super::{
+    alien::{spawn_aliens, AlienCount, AlienSpawnEvent},
+    alien_kinds::{AlienKind, AlienSpeed, AlienTemplates},
+    nests::Nest,
+};
+
+// Boss waves are the climaxes of the game. At fixed times a boss alien spawns at one of the nests and walks to the base.
+// Every boss has two phases:
+// - at full health it's slow and tanky
+// - below ENRAGE_HEALTH it enrages: it moves and hits faster and keeps calling in swarmers to help it
+// A health bar at the top of the screen shows how the fight is going, the UIPlugin draws it.
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BossSchedule>()
+            .add_event::<BossEvent>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(reset_boss_schedule))
+            // After the wave spawner, they share the GameRng.
+            // The enraged bosses hit faster from the same tick on
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick()
+                    .with_system(spawn_bosses.after(spawn_aliens))
+                    .with_system(boss_phases.after(spawn_bosses).before(damage_dealing)),
+            );
+    }
+}
+
+// When the boss waves come, counted from the start of the game.
+// Each one is tougher than the one before
+pub const BOSS_WAVES: [Duration; 3] = [
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(10 * 60),
+    Duration::from_secs(14 * 60),
+];
+// Every wave adds this much of the base health
+const HEALTH_PER_WAVE: f32 = 0.75;
+// Fraction of the max health below which the boss enrages
+pub const ENRAGE_HEALTH: f32 = 0.5;
+const ENRAGED_SPEED: f32 = 1.6;
+const ENRAGED_COOLDOWN: f32 = 0.6;
+const ADDS_INTERVAL: Duration = Duration::from_secs(8);
+const ADDS_PER_CALL: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BossPhase {
+    Normal,
+    Enraged,
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct Boss {
+    // Which of the BOSS_WAVES it came with
+    pub wave: usize,
+    pub phase: BossPhase,
+    pub adds_timer: Timer,
+    // So the death is only announced once
+    pub dead: bool,
+}
+
+impl Boss {
+    pub fn new(wave: usize) -> Self {
+        Boss {
+            wave,
+            phase: BossPhase::Normal,
+            adds_timer: Timer::new(ADDS_INTERVAL, TimerMode::Repeating),
+            dead: false,
+        }
+    }
+}
+
+// Primarily exists for audio, same as AlienSpawnEvent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BossEvent {
+    Arrived(Vec3),
+    Enraged(Vec3),
+    Died(Vec3),
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BossSchedule {
+    // The index of the next wave in BOSS_WAVES
+    pub next: usize,
+}
+
+impl BossSchedule {
+    // The wave that should spawn now, if any
+    pub fn due(&self, elapsed: Duration) -> Option<usize> {
+        match BOSS_WAVES.get(self.next) {
+            Some(at) if elapsed >= *at => Some(self.next),
+            _ => None,
+        }
+    }
+}
+
+pub fn boss_health(base_health: i32, wave: usize) -> i32 {
+    return (base_health as f32 * (1. + HEALTH_PER_WAVE * wave as f32)) as i32;
+}
+
+// The phase the boss should be in with this much health left. Enraging can't be undone
+pub fn boss_phase(current: BossPhase, health: &Health) -> BossPhase {
+    if current == BossPhase::Enraged || (health.hp as f32) < health.max_hp as f32 * ENRAGE_HEALTH {
+        return BossPhase::Enraged;
+    }
+    return BossPhase::Normal;
+}
+
+pub fn reset_boss_schedule(mut schedule: ResMut<BossSchedule>) {
+    *schedule = BossSchedule::default();
+}
+
+pub fn spawn_bosses(
+    mut commands: Commands,
+    mut schedule: ResMut<BossSchedule>,
+    mut count: ResMut<AlienCount>,
+    time: Res<InGameTime>,
+    templates: Res<AlienTemplates>,
+    grid: Res<Grid>,
+    nests: Query<(&Transform, &Nest, &Health)>,
+    mut rng: ResMut<GameRng>,
+    mut ev: EventWriter<BossEvent>,
+) {
+    let wave = match schedule.due(time.timer.elapsed()) {
+        Some(wave) => wave,
+        None => return,
+    };
+    schedule.next += 1;
+
+    let template = match templates.get(AlienKind::Boss) {
+        Some(template) => template,
+        None => return,
+    };
+
+    // Out of the strongest nest, or from around the base if they're all gone
+    let nest = nests
+        .iter()
+        .filter(|(_, n, h)| !n.destroyed && h.hp > 0)
+        .max_by(|(_, a, _), (_, b, _)| a.strength.total_cmp(&b.strength));
+    let mut point = match nest {
+        Some((t, ..)) => t.translation,
+        None => {
+            let angle = rng.0.gen::<f32>() * 2. * PI;
+            grid.base_center + Vec3::new(angle.cos(), 0., angle.sin()) * (grid.center_radius + 10.)
+        }
+    };
+    // It's a lot taller than the other aliens
+    point.y = template.half_height + 0.1;
+
+    let e = template.spawn(&mut commands, point);
+    let health = boss_health(template.health, wave);
+    commands
+        .entity(e)
+        .insert((Boss::new(wave), Health::new(health)));
+    count.count += 1;
+    ev.send(BossEvent::Arrived(point));
+}
+
+pub fn boss_phases(
+    mut commands: Commands,
+    mut bosses: Query<(
+        &mut Boss,
+        &Health,
+        &Transform,
+        &mut AlienSpeed,
+        &mut DamageDealing,
+    )>,
+    mut count: ResMut<AlienCount>,
+    templates: Res<AlienTemplates>,
+    time: Res<GameplayClock>,
+    mut ev: EventWriter<BossEvent>,
+    mut spawn_ev: EventWriter<AlienSpawnEvent>,
+    mut rng: ResMut<GameRng>,
+) {
+    let rng = &mut rng.0;
+    for (mut boss, health, transform, mut speed, mut damage) in bosses.iter_mut() {
+        if boss.dead {
+            continue;
+        }
+        if health.hp <= 0 {
+            boss.dead = true;
+            ev.send(BossEvent::Died(transform.translation));
+            continue;
+        }
+
+        let phase = boss_phase(boss.phase, health);
+        if phase != boss.phase {
+            boss.phase = phase;
+            speed.0 *= ENRAGED_SPEED;
+            let cooldown = damage.cooldown.duration().mul_f32(ENRAGED_COOLDOWN);
+            damage.cooldown.set_duration(cooldown);
+            ev.send(BossEvent::Enraged(transform.translation));
+        }
+
+        if boss.phase != BossPhase::Enraged {
+            continue;
+        }
+        boss.adds_timer.tick(time.delta());
+        if !boss.adds_timer.just_finished() {
+            continue;
+        }
+        let swarmer = match templates.get(AlienKind::Swarmer) {
+            Some(template) => template,
+            None => continue,
+        };
+        for _ in 0..ADDS_PER_CALL {
+            let angle = rng.gen::<f32>() * 2. * PI;
+            let point = transform.translation + Vec3::new(angle.cos(), 0., angle.sin()) * 3.;
+            spawn_ev.send(AlienSpawnEvent {
+                point: Vec3::new(point.x, 0.1, point.z),
+            });
+            count.count += 1;
+            swarmer.spawn(&mut commands, Vec3::new(point.x, 0.5, point.z));
+        }
+    }
+}
+
+pub fn boss_health_bar(bosses: Query<(&Boss, &Health)>, mut ctx: ResMut<EguiContext>) {
+    let (boss, health) = match bosses.iter().find(|(b, _)| !b.dead) {
+        Some(boss) => boss,
+        None => return,
+    };
+
+    make_window(Align2::CENTER_TOP, Some((0., 10.))).show(ctx.ctx_mut(), |ui| {
+        ui.set_width(400.);
+        ui.vertical_centered(|ui| {
+            let title = match boss.phase {
+                BossPhase::Normal => format!("Hive Queen - wave {}", boss.wave + 1),
+                BossPhase::Enraged => format!("Hive Queen - wave {} - ENRAGED", boss.wave + 1),
+            };
+            let color = match boss.phase {
+                BossPhase::Normal => Color32::WHITE,
+                BossPhase::Enraged => Color32::from_rgb(255, 80, 80),
+            };
+            ui.label(RichText::new(title).size(16.).color(color));
+            let fraction = health.hp.max(0) as f32 / health.max_hp as f32;
+            ui.add(egui::ProgressBar::new(fraction).text(format!(
+                "{} / {}",
+                health.hp.max(0),
+                health.max_hp
+            )));
+        });
+    });
+}
+
+#[cfg(test)]
+mod test_bosses {
+    use std::time::Duration;
+
+    use crate::health::health::Health;
+
+    use super::{boss_health, boss_phase, BossPhase, BossSchedule, BOSS_WAVES};
+
+    #[test]
+    fn waves_come_in_order() {
+        let mut schedule = BossSchedule::default();
+        assert_eq!(schedule.due(Duration::from_secs(60)), None);
+        assert_eq!(schedule.due(BOSS_WAVES[0]), Some(0));
+        schedule.next += 1;
+        assert_eq!(schedule.due(BOSS_WAVES[0]), None);
+        // A long pause can't skip a wave
+        assert_eq!(schedule.due(Duration::from_secs(60 * 60)), Some(1));
+        schedule.next = BOSS_WAVES.len();
+        assert_eq!(schedule.due(Duration::from_secs(60 * 60)), None);
+    }
+
+    #[test]
+    fn enrages_at_half_health_and_stays_enraged() {
+        let mut health = Health::new(1000);
+        assert_eq!(boss_phase(BossPhase::Normal, &health), BossPhase::Normal);
+        health.hp = 499;
+        assert_eq!(boss_phase(BossPhase::Normal, &health), BossPhase::Enraged);
+        health.hp = 1000;
+        assert_eq!(boss_phase(BossPhase::Enraged, &health), BossPhase::Enraged);
+    }
+
+    #[test]
+    fn later_waves_are_tougher() {
+        assert_eq!(boss_health(1000, 0), 1000);
+        assert!(boss_health(1000, 1) > boss_health(1000, 0));
+        assert!(boss_health(1000, 2) > boss_health(1000, 1));
+    }
+}
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+
+use crate::{
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayStage},
+    spatial::spatial_index::SpatialIndex,
+};
+
+use super::{
+    alien::{alien_ai, Alien},
+    alien_kinds::{AlienRadius, AlienSpeed},
+};
+
+// Separation steering, so a wave spreads into a crowd instead of all the aliens walking inside each other.
+// Aliens don't collide with each other (that would make the physics a lot more expensive and they'd get stuck in doorways),
+// instead every alien is pushed away from the aliens right next to it, on top of the velocity alien_ai gave it.
+// Once they reach their target the push spreads them out around it.
+// The neighbours come from the spatial index and only the closest few count, so the cost per alien stays bounded.
+// How much room an alien wants depends on its size and the size of its neighbour, a boss shoves the drones aside from further away.
+
+pub struct CrowdPlugin;
+
+impl Plugin for CrowdPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            GameplayStage,
+            on_gameplay_tick().with_system(alien_separation.after(alien_ai)),
+        );
+    }
+}
+
+// Two aliens closer than this many times the sum of their radii push each other away.
+// Two drones start pushing at 1.5
+pub const SEPARATION_SPACING: f32 = 2.5;
+// How hard they push, relative to the alien's own speed
+const SEPARATION_STRENGTH: f32 = 1.2;
+// Only this many neighbours are looked at per alien, so a huge blob isn't quadratic
+const MAX_NEIGHBORS: usize = 8;
+
+// A neighbour as seen by separation
+#[derive(Clone, Copy, Debug)]
+pub struct Neighbor {
+    pub position: Vec3,
+    pub radius: f32,
+}
+
+// The direction to move in to get away from the neighbours, stronger the closer they are.
+// `seed` picks a direction when two aliens are exactly on top of each other
+pub fn separation(
+    position: Vec3,
+    radius: f32,
+    neighbors: impl Iterator<Item = Neighbor>,
+    seed: u32,
+) -> Vec3 {
+    let mut push = Vec3::ZERO;
+    for (i, other) in neighbors.enumerate() {
+        let spacing = (radius + other.radius) * SEPARATION_SPACING;
+        let mut away = Vec3::new(
+            position.x - other.position.x,
+            0.,
+            position.z - other.position.z,
+        );
+        let distance = away.length();
+        if distance >= spacing {
+            continue;
+        }
+        if distance < 0.001 {
+            // Spread evenly around, using the golden angle
+            let angle = (seed as f32 + i as f32) * 2.399;
+            away = Vec3::new(angle.cos(), 0., angle.sin());
+        } else {
+            away /= distance;
+        }
+        push += away * (1. - distance / spacing);
+    }
+    return push;
+}
+
+pub fn alien_separation(
+    mut aliens: Query<(
+        Entity,
+        &Transform,
+        &Alien,
+        &AlienSpeed,
+        &AlienRadius,
+        &mut Velocity,
+    )>,
+    radii: Query<&AlienRadius>,
+    index: Res<SpatialIndex>,
+    // Reused between the aliens and frames, so there's no allocation per alien
+    mut neighbors: Local<Vec<(f32, Neighbor)>>,
+) {
+    // The largest alien around decides how far we have to look
+    let largest = radii.iter().map(|r| r.0).fold(0., f32::max);
+
+    for (e, transform, alien, speed, radius, mut velocity) in aliens.iter_mut() {
+        if !alien.alive {
+            continue;
+        }
+        let position = transform.translation;
+
+        neighbors.clear();
+        let reach = (radius.0 + largest) * SEPARATION_SPACING;
+        for (other, p) in index.aliens.query_radius(position, reach) {
+            if other == e {
+                continue;
+            }
+            let other_radius = radii.get(other).map(|r| r.0).unwrap_or(radius.0);
+            let distance = Vec2::new(position.x - p.x, position.z - p.z).length();
+            if distance < (radius.0 + other_radius) * SEPARATION_SPACING {
+                let neighbor = Neighbor {
+                    position: p,
+                    radius: other_radius,
+                };
+                neighbors.push((distance, neighbor));
+            }
+        }
+        // The index returns them cell by cell, not by distance
+        neighbors.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let closest = neighbors.iter().take(MAX_NEIGHBORS).map(|(_, n)| *n);
+
+        let push = separation(position, radius.0, closest, e.index());
+        if push == Vec3::ZERO {
+            continue;
+        }
+        // Never faster than the alien can walk, so the crowd doesn't shoot aliens across the map
+        let horizontal = Vec3::new(velocity.linvel.x, 0., velocity.linvel.z)
+            + push * speed.0 * SEPARATION_STRENGTH;
+        let horizontal = horizontal.clamp_length_max(speed.0);
+        velocity.linvel = Vec3::new(horizontal.x, velocity.linvel.y, horizontal.z);
+    }
+}
+
+#[cfg(test)]
+mod test_crowd {
+    use bevy::prelude::*;
+
+    use super::{separation, Neighbor, SEPARATION_SPACING};
+
+    // The size of a drone
+    const RADIUS: f32 = 0.3;
+
+    fn drones(positions: &[Vec3]) -> impl Iterator<Item = Neighbor> + '_ {
+        positions.iter().map(|p| Neighbor {
+            position: *p,
+            radius: RADIUS,
+        })
+    }
+
+    #[test]
+    fn pushed_away_from_close_neighbors() {
+        let push = separation(Vec3::ZERO, RADIUS, drones(&[Vec3::new(0.5, 0., 0.)]), 0);
+        assert!(push.x < 0.);
+        assert_eq!(push.z, 0.);
+
+        // Closer pushes harder
+        let closer = separation(Vec3::ZERO, RADIUS, drones(&[Vec3::new(0.2, 0., 0.)]), 0);
+        assert!(closer.length() > push.length());
+    }
+
+    #[test]
+    fn far_neighbors_are_ignored() {
+        let far = Vec3::new(2. * RADIUS * SEPARATION_SPACING + 0.1, 0., 0.);
+        assert_eq!(
+            separation(Vec3::ZERO, RADIUS, drones(&[far]), 0),
+            Vec3::ZERO
+        );
+        // Surrounded evenly, the pushes cancel out
+        let around = [Vec3::X, -Vec3::X, Vec3::Z, -Vec3::Z];
+        assert!(separation(Vec3::ZERO, RADIUS, drones(&around), 0).length() < 0.001);
+    }
+
+    #[test]
+    fn stacked_aliens_split_up() {
+        let a = separation(Vec3::ZERO, RADIUS, drones(&[Vec3::ZERO]), 1);
+        let b = separation(Vec3::ZERO, RADIUS, drones(&[Vec3::ZERO]), 2);
+        assert!(a.length() > 0.9);
+        assert!(a.distance(b) > 0.1);
+    }
+
+    #[test]
+    fn big_aliens_need_more_room() {
+        let other = Vec3::new(2., 0., 0.);
+        assert_eq!(
+            separation(Vec3::ZERO, RADIUS, drones(&[other]), 0),
+            Vec3::ZERO
+        );
+
+        let boss = Neighbor {
+            position: other,
+            radius: 0.9,
+        };
+        assert!(separation(Vec3::ZERO, RADIUS, [boss].into_iter(), 0).x < 0.);
+    }
+}
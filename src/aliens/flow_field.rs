@@ -0,0 +1,706 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    buildings::{
+        defensive_buildings::{building_death, AlienTarget},
+        grid::{Grid, SQUARE_SIZE},
+    },
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayStage},
+};
+
+use super::nests::{NEST_MAX_DISTANCE, NEST_SPAWN_RADIUS};
+
+// A flow field over the building grid, leading to the closest important building (the main base and the turrets).
+// Instead of every alien searching for its own path, every square stores the distance to the closest goal
+// and which neighbour to walk to, so aliens only have to look up the square they're standing on.
+// When a building is built or destroyed only the squares whose paths went through it are recalculated.
+
+pub struct FlowFieldPlugin;
+
+impl Plugin for FlowFieldPlugin {
+    fn build(&self, app: &mut App) {
+        // After the buildings destroyed in the tick are off the grid
+        app.init_resource::<FlowField>().add_system_set_to_stage(
+            GameplayStage,
+            on_gameplay_tick().with_system(update_flow_field.after(building_death)),
+        );
+    }
+}
+
+// Buildings with at least this priority are goals of the flow field.
+// Aliens going for less important buildings use their own A* path
+pub const FLOW_FIELD_MIN_PRIORITY: i8 = 5;
+
+// How many squares the field reaches past the base, aliens without nests spawn just outside of center_radius
+const MARGIN_SQUARES: i32 = 12;
+// The field always reaches this far from the middle of the map, so the aliens coming out of the nests are on it
+const NEST_SQUARES: i32 = ((NEST_MAX_DISTANCE + NEST_SPAWN_RADIUS) / SQUARE_SIZE) as i32 + 2;
+// The field grows in steps of this many squares, so it doesn't have to be rebuilt every time the base grows a bit
+const GROW_STEP: i32 = 16;
+
+const DIAGONAL: f32 = std::f32::consts::SQRT_2;
+
+// The square range covered by the field, min and max included
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+}
+
+impl Bounds {
+    fn contains(&self, other: &Bounds) -> bool {
+        self.min.0 <= other.min.0
+            && self.min.1 <= other.min.1
+            && self.max.0 >= other.max.0
+            && self.max.1 >= other.max.1
+    }
+
+    fn width(&self) -> i32 {
+        self.max.0 - self.min.0 + 1
+    }
+
+    fn height(&self) -> i32 {
+        self.max.1 - self.min.1 + 1
+    }
+
+    // Rounds the bounds outwards to GROW_STEP and keeps them within the squares the grid can index
+    fn grown(&self) -> Bounds {
+        let down = |v: i32| (v.div_euclid(GROW_STEP) * GROW_STEP).max(i8::MIN as i32);
+        let up = |v: i32| ((v.div_euclid(GROW_STEP) + 1) * GROW_STEP - 1).min(i8::MAX as i32);
+        Bounds {
+            min: (down(self.min.0), down(self.min.1)),
+            max: (up(self.max.0), up(self.max.1)),
+        }
+    }
+}
+
+// A cell in the Dijkstra queue, ordered so the closest one is popped first.
+// Ties go to the lower index, so the field comes out the same in every run and replays stay in sync
+#[derive(Clone, Copy, PartialEq)]
+struct OpenCell {
+    distance: f32,
+    index: usize,
+}
+
+impl Eq for OpenCell {}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// What an alien gets from the flow field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlowSample {
+    // Horizontal and normalized
+    pub direction: Vec3,
+    // The building the field leads to
+    pub goal: Entity,
+    // In squares
+    pub distance: f32,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct FlowField {
+    bounds: Option<Bounds>,
+    // Per cell, row by row
+    distance: Vec<f32>,
+    // The next cell towards the goal
+    next: Vec<Option<usize>>,
+    // The goal square the cell leads to
+    goal: Vec<Option<(i8, i8)>>,
+    // The grid the field was built for
+    obstacles: HashMap<(i8, i8), Entity>,
+    goals: HashMap<(i8, i8), Entity>,
+    goal_entities: HashSet<Entity>,
+    grid_version: Option<u32>,
+}
+
+impl FlowField {
+    fn index(&self, square: (i32, i32)) -> Option<usize> {
+        let b = self.bounds?;
+        if square.0 < b.min.0 || square.1 < b.min.1 || square.0 > b.max.0 || square.1 > b.max.1 {
+            return None;
+        }
+        Some(((square.1 - b.min.1) * b.width() + (square.0 - b.min.0)) as usize)
+    }
+
+    fn square(&self, index: usize) -> (i32, i32) {
+        let b = self.bounds.unwrap();
+        let i = index as i32;
+        (b.min.0 + i % b.width(), b.min.1 + i / b.width())
+    }
+
+    fn is_walkable(&self, square: (i32, i32)) -> bool {
+        let square = (square.0 as i8, square.1 as i8);
+        !self.obstacles.contains_key(&square) || self.goals.contains_key(&square)
+    }
+
+    // The cells you can step to from this one, with the cost of the step.
+    // Diagonal steps can't cut the corner of a building, same as in find_path
+    fn neighbours(&self, index: usize) -> impl Iterator<Item = (usize, f32)> + '_ {
+        let (x, y) = self.square(index);
+        (-1..=1)
+            .flat_map(|dx| (-1..=1).map(move |dy| (dx, dy)))
+            .filter_map(move |(dx, dy)| {
+                if dx == 0 && dy == 0 {
+                    return None;
+                }
+                let n = self.index((x + dx, y + dy))?;
+                if !self.is_walkable((x + dx, y + dy)) {
+                    return None;
+                }
+                if dx != 0 && dy != 0 {
+                    if !self.is_walkable((x + dx, y)) || !self.is_walkable((x, y + dy)) {
+                        return None;
+                    }
+                    return Some((n, DIAGONAL));
+                }
+                Some((n, 1.))
+            })
+    }
+
+    // Builds the whole field from scratch
+    pub fn rebuild(
+        &mut self,
+        bounds: Bounds,
+        obstacles: HashMap<(i8, i8), Entity>,
+        goals: HashMap<(i8, i8), Entity>,
+    ) {
+        let cells = (bounds.width() * bounds.height()) as usize;
+        self.bounds = Some(bounds);
+        self.distance = vec![f32::INFINITY; cells];
+        self.next = vec![None; cells];
+        self.goal = vec![None; cells];
+        self.goal_entities = goals.values().copied().collect();
+        self.obstacles = obstacles;
+        self.goals = goals;
+
+        // The HashMap order changes between runs
+        let mut squares: Vec<_> = self.goals.keys().copied().collect();
+        squares.sort_unstable();
+        let mut open = BinaryHeap::new();
+        for square in squares {
+            if let Some(i) = self.index((square.0 as i32, square.1 as i32)) {
+                self.distance[i] = 0.;
+                self.goal[i] = Some(square);
+                open.push(OpenCell {
+                    distance: 0.,
+                    index: i,
+                });
+            }
+        }
+        self.propagate(open);
+    }
+
+    // Updates the field after buildings were added or removed, without rebuilding all of it.
+    // The bounds have to stay the same, use rebuild otherwise
+    pub fn update(
+        &mut self,
+        obstacles: HashMap<(i8, i8), Entity>,
+        goals: HashMap<(i8, i8), Entity>,
+    ) {
+        // The squares that changed, in either direction
+        let mut changed = Vec::new();
+        for (square, e) in obstacles.iter() {
+            if self.obstacles.get(square) != Some(e) || self.goals.get(square) != goals.get(square)
+            {
+                changed.push(*square);
+            }
+        }
+        for square in self.obstacles.keys() {
+            if !obstacles.contains_key(square) {
+                changed.push(*square);
+            }
+        }
+        changed.sort_unstable();
+        changed.dedup();
+        self.goal_entities = goals.values().copied().collect();
+        self.obstacles = obstacles;
+        self.goals = goals;
+
+        // Every cell whose path went through a changed square has to be recalculated.
+        // That is the changed squares, the cells that stepped diagonally past them,
+        // and everything whose path continues through any of those
+        let mut invalid = vec![false; self.distance.len()];
+        let mut queue = VecDeque::new();
+        for square in changed.iter() {
+            let (x, y) = (square.0 as i32, square.1 as i32);
+            let i = match self.index((x, y)) {
+                Some(i) => i,
+                None => continue,
+            };
+            queue.push_back(i);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    if let Some(n) = self.index((x + dx, y + dy)) {
+                        if let Some(next) = self.next[n] {
+                            let (nx, ny) = self.square(n);
+                            let (tx, ty) = self.square(next);
+                            let diagonal = nx != tx && ny != ty;
+                            if diagonal && ((nx, ty) == (x, y) || (tx, ny) == (x, y)) {
+                                queue.push_back(n);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        while let Some(i) = queue.pop_front() {
+            if invalid[i] {
+                continue;
+            }
+            invalid[i] = true;
+            let (x, y) = self.square(i);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    if let Some(n) = self.index((x + dx, y + dy)) {
+                        if !invalid[n] && self.next[n] == Some(i) {
+                            queue.push_back(n);
+                        }
+                    }
+                }
+            }
+        }
+
+        for i in 0..invalid.len() {
+            if invalid[i] {
+                self.distance[i] = f32::INFINITY;
+                self.next[i] = None;
+                self.goal[i] = None;
+            }
+        }
+
+        // Seed the invalid cells from their valid neighbours, and the new goals with 0
+        let mut open = BinaryHeap::new();
+        for i in 0..invalid.len() {
+            let (x, y) = self.square(i);
+            if self.goals.contains_key(&(x as i8, y as i8)) {
+                if self.distance[i] > 0. {
+                    self.distance[i] = 0.;
+                    self.next[i] = None;
+                    self.goal[i] = Some((x as i8, y as i8));
+                    open.push(OpenCell {
+                        distance: 0.,
+                        index: i,
+                    });
+                }
+                continue;
+            }
+            if !invalid[i] || !self.is_walkable((x, y)) {
+                continue;
+            }
+            let best = self
+                .neighbours(i)
+                .filter(|(n, _)| !invalid[*n] && self.distance[*n].is_finite())
+                .map(|(n, step)| (n, self.distance[n] + step))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((n, distance)) = best {
+                self.distance[i] = distance;
+                self.next[i] = Some(n);
+                self.goal[i] = self.goal[n];
+                open.push(OpenCell { distance, index: i });
+            }
+        }
+
+        // A new free square can also open up diagonal steps between its neighbours, which can then get shorter paths
+        for square in changed.iter() {
+            let (x, y) = (square.0 as i32, square.1 as i32);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    if let Some(n) = self.index((x + dx, y + dy)) {
+                        if !invalid[n] && self.distance[n].is_finite() {
+                            open.push(OpenCell {
+                                distance: self.distance[n],
+                                index: n,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        self.propagate(open);
+    }
+
+    // Dijkstra from the queued cells outwards
+    fn propagate(&mut self, mut open: BinaryHeap<OpenCell>) {
+        while let Some(OpenCell { distance, index }) = open.pop() {
+            if distance > self.distance[index] {
+                continue;
+            }
+            let neighbours = self.neighbours(index).collect::<Vec<_>>();
+            for (n, step) in neighbours {
+                let d = distance + step;
+                if d < self.distance[n] {
+                    self.distance[n] = d;
+                    self.next[n] = Some(index);
+                    self.goal[n] = self.goal[index];
+                    open.push(OpenCell {
+                        distance: d,
+                        index: n,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn is_goal(&self, entity: Entity) -> bool {
+        self.goal_entities.contains(&entity)
+    }
+
+    // The distance in squares to the closest goal
+    pub fn distance(&self, square: (i8, i8)) -> Option<f32> {
+        let d = self.distance[self.index((square.0 as i32, square.1 as i32))?];
+        d.is_finite().then_some(d)
+    }
+
+    // Where to go from this position.
+    // None outside of the field, on the goal itself, or if no goal can be reached from here
+    pub fn sample(&self, position: Vec3) -> Option<FlowSample> {
+        let square = Grid::get_square_index(position);
+        let i = self.index((square.0 as i32, square.1 as i32))?;
+        let next = self.next[i]?;
+        let goal = self.goals.get(&self.goal[i]?)?;
+
+        let (x, y) = self.square(next);
+        let target = Vec3::new(
+            (x as f32 + 0.5) * SQUARE_SIZE,
+            position.y,
+            (y as f32 + 0.5) * SQUARE_SIZE,
+        );
+        Some(FlowSample {
+            direction: (target - position).normalize_or_zero(),
+            goal: *goal,
+            distance: self.distance[i],
+        })
+    }
+}
+
+// The squares the field has to cover for the current base
+fn needed_bounds(grid: &Grid) -> Bounds {
+    let center = Grid::get_square_index(grid.base_center);
+    let reach = (grid.center_radius / SQUARE_SIZE).ceil() as i32 + MARGIN_SQUARES;
+    Bounds {
+        min: (
+            (center.0 as i32 - reach).min(-NEST_SQUARES),
+            (center.1 as i32 - reach).min(-NEST_SQUARES),
+        ),
+        max: (
+            (center.0 as i32 + reach).max(NEST_SQUARES),
+            (center.1 as i32 + reach).max(NEST_SQUARES),
+        ),
+    }
+}
+
+// Keeps the field in sync with the grid
+pub fn update_flow_field(
+    grid: Res<Grid>,
+    mut field: ResMut<FlowField>,
+    targets: Query<&AlienTarget>,
+) {
+    if field.grid_version == Some(grid.version) {
+        return;
+    }
+
+    let mut goals = HashMap::new();
+    for (square, e) in grid.blocked_squares.iter() {
+        match targets.get(*e) {
+            Ok(target) if target.priority >= FLOW_FIELD_MIN_PRIORITY => {
+                goals.insert(*square, *e);
+            }
+            Ok(_) => {}
+            // The building was only just spawned, its components are added at the end of the tick
+            Err(_) => return,
+        }
+    }
+
+    let needed = needed_bounds(&grid);
+    match field.bounds {
+        Some(bounds) if bounds.contains(&needed) => {
+            field.update(grid.blocked_squares.clone(), goals);
+        }
+        _ => {
+            field.rebuild(needed.grown(), grid.blocked_squares.clone(), goals);
+        }
+    }
+    field.grid_version = Some(grid.version);
+}
+
+#[cfg(test)]
+mod test_flow_field {
+    use std::time::{Duration, Instant};
+
+    use bevy::{prelude::*, utils::HashMap};
+    use bevy_rapier3d::prelude::Velocity;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{needed_bounds, Bounds, FlowField};
+    use crate::{
+        aliens::{
+            alien::{alien_ai, Alien},
+            alien_kinds::{AlienBehavior, AlienSpeed},
+            nests::{nest_positions, NEST_SPAWN_RADIUS},
+            pathfinding::AlienPath,
+            targeting::{select_alien_targets, TargetPreference},
+        },
+        buildings::{
+            defensive_buildings::{AlienTarget, TargetSelecting},
+            grid::Grid,
+        },
+        health::health::Health,
+        spatial::spatial_index::{update_spatial_index, SpatialIndex},
+    };
+
+    fn bounds() -> Bounds {
+        Bounds {
+            min: (-10, -10),
+            max: (10, 10),
+        }
+    }
+
+    fn assert_same_distances(a: &FlowField, b: &FlowField) {
+        for (i, (da, db)) in a.distance.iter().zip(b.distance.iter()).enumerate() {
+            assert!(
+                (da.is_infinite() && db.is_infinite()) || (da - db).abs() < 1e-3,
+                "cell {:?}: {} != {}",
+                a.square(i),
+                da,
+                db
+            );
+        }
+    }
+
+    #[test]
+    fn distances_around_a_wall() {
+        let base = Entity::from_raw(0);
+        let mut obstacles = HashMap::new();
+        obstacles.insert((0, 0), base);
+        for y in -3..=3 {
+            obstacles.insert((2, y), Entity::from_raw(1));
+        }
+        let mut goals = HashMap::new();
+        goals.insert((0, 0), base);
+
+        let mut field = FlowField::default();
+        field.rebuild(bounds(), obstacles, goals);
+
+        assert_eq!(field.distance((1, 0)), Some(1.));
+        assert_eq!(field.distance((2, 0)), None);
+        // Has to walk around the wall, so it's further than the 3 squares in a straight line
+        assert!(field.distance((3, 0)).unwrap() > 6.);
+
+        let sample = field
+            .sample(Vec3::new(-5. * 2. + 1., 0., 1.))
+            .expect("should lead to the base");
+        assert_eq!(sample.goal, base);
+        assert!(sample.direction.x > 0.);
+    }
+
+    // Goals the same distance away make ties everywhere, which have to be broken the same way every time
+    #[test]
+    fn ties_are_broken_the_same_way() {
+        let build = || {
+            let mut goals = HashMap::new();
+            for (i, square) in [(-4, -4), (4, -4), (-4, 4), (4, 4)].into_iter().enumerate() {
+                goals.insert(square, Entity::from_raw(i as u32));
+            }
+            let mut field = FlowField::default();
+            field.rebuild(bounds(), goals.clone(), goals);
+            field
+        };
+        let first = build();
+        for _ in 0..10 {
+            let field = build();
+            assert_eq!(field.next, first.next);
+            assert_eq!(field.goal, first.goal);
+        }
+    }
+
+    // Builds and destroys random buildings and checks the incremental update always matches a full rebuild
+    #[test]
+    fn incremental_update_matches_rebuild() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut obstacles: HashMap<(i8, i8), Entity> = HashMap::new();
+        let mut goals = HashMap::new();
+        obstacles.insert((0, 0), Entity::from_raw(0));
+        goals.insert((0, 0), Entity::from_raw(0));
+
+        let mut field = FlowField::default();
+        field.rebuild(bounds(), obstacles.clone(), goals.clone());
+
+        for step in 1..200u32 {
+            let square = (rng.gen_range(-9..=9), rng.gen_range(-9..=9));
+            if square == (0, 0) {
+                continue;
+            }
+            if obstacles.remove(&square).is_some() {
+                goals.remove(&square);
+            } else {
+                obstacles.insert(square, Entity::from_raw(step));
+                // Some of the buildings are turrets
+                if rng.gen::<f32>() < 0.2 {
+                    goals.insert(square, Entity::from_raw(step));
+                }
+            }
+
+            field.update(obstacles.clone(), goals.clone());
+            let mut rebuilt = FlowField::default();
+            rebuilt.rebuild(bounds(), obstacles.clone(), goals.clone());
+            assert_same_distances(&field, &rebuilt);
+        }
+    }
+
+    // Aliens spawned anywhere around a nest should be on the field, even before the base grows
+    #[test]
+    fn covers_the_nests() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let bounds = needed_bounds(&Grid::new()).grown();
+        for _ in 0..50 {
+            for nest in nest_positions(Vec3::ZERO, 4, &mut rng) {
+                for (dx, dz) in [(1., 0.), (-1., 0.), (0., 1.), (0., -1.)] {
+                    let spawn = nest + Vec3::new(dx, 0., dz) * NEST_SPAWN_RADIUS;
+                    let (x, y) = Grid::get_square_index(spawn);
+                    let square = Bounds {
+                        min: (x as i32, y as i32),
+                        max: (x as i32, y as i32),
+                    };
+                    assert!(bounds.contains(&square), "{:?} is off the field", spawn);
+                }
+            }
+        }
+    }
+
+    // Moves the aliens along their velocity, there is no physics in the benchmark
+    fn move_aliens(time: Res<Time>, mut aliens: Query<(&mut Transform, &Velocity)>) {
+        for (mut t, v) in aliens.iter_mut() {
+            t.translation += v.linvel * time.delta_seconds();
+        }
+    }
+
+    const ALIENS: usize = 2000;
+
+    // Runs alien_ai headless with a wall being built and torn down every few frames, and returns how long a frame took.
+    // Without the flow field every alien is going for a turret on its own A* path, which the wall invalidates
+    fn run_aliens(flow_field: bool) -> Duration {
+        const FRAMES: u32 = 60;
+        const WALL_EVERY: u32 = 5;
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(Grid::new())
+            .init_resource::<FlowField>()
+            .init_resource::<SpatialIndex>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_spatial_index)
+            .add_system(select_alien_targets.before(alien_ai))
+            .add_system(move_aliens.after(alien_ai));
+        if flow_field {
+            app.add_system(super::update_flow_field)
+                .add_system(alien_ai.after(super::update_flow_field));
+        } else {
+            app.add_system(alien_ai);
+        }
+
+        // A main base surrounded by a ring of turrets with a few gaps
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut buildings = vec![((0, 0), 8)];
+        for x in -6..=6i8 {
+            for y in -6..=6i8 {
+                let ring = x.abs().max(y.abs()) == 6;
+                if ring && rng.gen::<f32>() < 0.8 {
+                    buildings.push(((x, y), 5));
+                }
+            }
+        }
+        for (square, priority) in buildings {
+            let e = app
+                .world
+                .spawn((
+                    Transform::from_translation(Grid::get_square_pos(square)),
+                    AlienTarget { priority },
+                    Health::new(1000),
+                ))
+                .id();
+            app.world.resource_mut::<Grid>().block_square(square, e);
+        }
+        let wall = Grid::get_square_pos((10, 10));
+        let wall_entity = app
+            .world
+            .spawn((
+                Transform::from_translation(wall),
+                AlienTarget { priority: 1 },
+                Health::new(1000),
+            ))
+            .id();
+
+        for _ in 0..ALIENS {
+            let angle = rng.gen::<f32>() * std::f32::consts::TAU;
+            let distance = 40. + rng.gen::<f32>() * 20.;
+            app.world.spawn((
+                Transform::from_xyz(angle.cos() * distance, 0.5, angle.sin() * distance),
+                Velocity::default(),
+                Alien::default(),
+                TargetSelecting::new(2.5),
+                AlienSpeed(5.),
+                AlienBehavior::Melee,
+                AlienPath::default(),
+                Health::new(200),
+                // Going for the closest turret, so they all follow the field
+                TargetPreference {
+                    turret: 3.,
+                    ..default()
+                },
+            ));
+        }
+
+        // The first frames pick the targets and build the field
+        for _ in 0..3 {
+            app.update();
+        }
+
+        let start = Instant::now();
+        for frame in 0..FRAMES {
+            if frame % WALL_EVERY == 0 {
+                let mut grid = app.world.resource_mut::<Grid>();
+                if grid.unblock_square_vec3(wall).is_none() {
+                    grid.block_square_vec3(wall, wall_entity);
+                }
+            }
+            app.update();
+        }
+        return start.elapsed() / FRAMES;
+    }
+
+    // The reason for the flow field, a building going up or down used to make every alien search for a new path.
+    // Run with: cargo test --release flow_field_is_faster_than_a_star -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn flow_field_is_faster_than_a_star() {
+        let flow_field = run_aliens(true);
+        let a_star = run_aliens(false);
+        eprintln!(
+            "{} aliens: flow field {:?} per frame, A* {:?} per frame ({:.1}x faster)",
+            ALIENS,
+            flow_field,
+            a_star,
+            a_star.as_secs_f64() / flow_field.as_secs_f64()
+        );
+    }
+}
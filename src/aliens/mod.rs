@@ -1 +1,9 @@
-pub mod alien;
\ No newline at end of file
+pub mod alien;
+pub mod alien_animation;
+pub mod alien_kinds;
+pub mod bosses;
+pub mod crowd;
+pub mod flow_field;
+pub mod nests;
+pub mod pathfinding;
+pub mod targeting;
\ No newline at end of file
@@ -0,0 +1,290 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    audio::audio::AudioType,
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayClock, GameplayStage},
+    health::health::{death_timers, DeathEvent, Health},
+    rules::game_rules::GameRules,
+    AppState,
+};
+
+use super::alien::spawn_aliens;
+
+// Nests are where the aliens come from. They're placed around the base when the map is generated,
+// the wave spawner picks one of the living nests for every alien it spawns.
+// Nests grow stronger the longer they live, which makes them spawn more aliens,
+// so it pays off to push out and destroy them with long range turrets.
+// Destroying all of them wins the game, as an alternative to surviving until the win time of the GameRules.
+
+pub struct NestPlugin;
+
+impl Plugin for NestPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NestCount>()
+            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(nests_win_condition))
+            // The strength of the nests is part of the spawn rate.
+            // The nests destroyed in a tick stop growing and spawning from the next one
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick()
+                    .with_system(nest_death.after(death_timers))
+                    .with_system(nest_growth.before(spawn_aliens))
+                    .with_system(nest_cleanup.after(death_timers)),
+            );
+    }
+}
+
+pub const NEST_COUNT: u32 = 4;
+const NEST_HEALTH: i32 = 3000;
+// How far away from the main base the nests are placed.
+// The laser reaches 24, so the closest nests are in range of a laser next to the main base,
+// the furthest ones take building out towards them. The flow field covers all of them, see flow_field
+pub const NEST_MIN_DISTANCE: f32 = 32.;
+pub const NEST_MAX_DISTANCE: f32 = 44.;
+// Aliens spawn this far around the nest
+pub const NEST_SPAWN_RADIUS: f32 = 4.;
+// A nest starts at strength 1 and grows up to NEST_MAX_STRENGTH over NEST_GROWTH_TIME seconds
+const NEST_MAX_STRENGTH: f32 = 2.;
+const NEST_GROWTH_TIME: f32 = 600.;
+
+#[derive(Component, Debug, Clone)]
+pub struct Nest {
+    // Multiplies how many aliens the nest spawns
+    pub strength: f32,
+    // Death events can come multiple times for the same entity, the nest only counts as destroyed once
+    pub destroyed: bool,
+}
+
+impl Default for Nest {
+    fn default() -> Self {
+        Nest {
+            strength: 1.,
+            destroyed: false,
+        }
+    }
+}
+
+// How many nests this game started with and how many are gone.
+// Counted instead of queried, since the nests only exist a frame after they're spawned
+#[derive(Resource, Debug, Clone, Default)]
+pub struct NestCount {
+    pub total: u32,
+    pub destroyed: u32,
+}
+
+impl NestCount {
+    pub fn all_destroyed(&self) -> bool {
+        self.total > 0 && self.destroyed >= self.total
+    }
+}
+
+// The nests are spread evenly around the base, with a bit of randomness so every map is different
+pub fn nest_positions(center: Vec3, count: u32, rng: &mut impl Rng) -> Vec<Vec3> {
+    let start = rng.gen::<f32>() * 2. * PI;
+    let slice = 2. * PI / count as f32;
+    (0..count)
+        .map(|i| {
+            let angle = start + slice * (i as f32 + rng.gen_range(-0.25..0.25));
+            let distance = rng.gen_range(NEST_MIN_DISTANCE..NEST_MAX_DISTANCE);
+            center + Vec3::new(angle.cos() * distance, 0., angle.sin() * distance)
+        })
+        .collect()
+}
+
+// Part of the map generation, the main base is always in the middle of the map
+pub fn spawn_nests(
+    mut commands: Commands,
+    mut count: ResMut<NestCount>,
+    rules: Res<GameRules>,
+    ass: Res<AssetServer>,
+) {
+    // Placed by the map seed like the rest of the map, the stream is offset so they don't line up with the rocks
+    let mut rng = StdRng::seed_from_u64(rules.seed.wrapping_add(1));
+    let scene = ass.load("spacekit_2/Models/GLTF format/craterLarge.glb#Scene0");
+
+    *count = NestCount {
+        total: NEST_COUNT,
+        destroyed: 0,
+    };
+    for position in nest_positions(Vec3::ZERO, NEST_COUNT, &mut rng) {
+        commands
+            .spawn((
+                Nest::default(),
+                Health::new(NEST_HEALTH),
+                AudioType::Building,
+                SpatialBundle {
+                    transform: Transform::from_translation(position),
+                    ..default()
+                },
+            ))
+            .with_children(|parent| {
+                parent.spawn(SceneBundle {
+                    scene: scene.clone(),
+                    // The model's origin is in its corner
+                    transform: Transform::from_xyz(-2., 0., -2.).with_scale(Vec3::splat(2.)),
+                    ..default()
+                });
+            });
+    }
+}
+
+pub fn nest_growth(time: Res<GameplayClock>, mut nests: Query<&mut Nest>) {
+    let growth = (NEST_MAX_STRENGTH - 1.) / NEST_GROWTH_TIME * time.delta_seconds();
+    for mut nest in nests.iter_mut() {
+        if !nest.destroyed {
+            nest.strength = (nest.strength + growth).min(NEST_MAX_STRENGTH);
+        }
+    }
+}
+
+pub fn nest_death(
+    mut ev: EventReader<DeathEvent>,
+    mut nests: Query<&mut Nest>,
+    mut count: ResMut<NestCount>,
+) {
+    for ev in ev.iter() {
+        if let Ok(mut nest) = nests.get_mut(ev.entity) {
+            if !nest.destroyed {
+                nest.destroyed = true;
+                count.destroyed += 1;
+            }
+        }
+    }
+}
+
+// Despawn the nest once its death timer runs out, same as the aliens
+pub fn nest_cleanup(nests: Query<(&Health, Entity), With<Nest>>, mut commands: Commands) {
+    for (health, e) in nests.iter() {
+        if health.dead_for_timer.finished() {
+            if let Some(e) = commands.get_entity(e) {
+                e.despawn_recursive();
+            }
+        }
+    }
+}
+
+// There's no winning in endless mode, once the nests are gone the waves come from around the base
+pub fn nests_win_condition(
+    count: Res<NestCount>,
+    rules: Res<GameRules>,
+    mut game_state: ResMut<State<AppState>>,
+) {
+    if count.all_destroyed() && !rules.endless() {
+        game_state.set(AppState::Victory).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_nests {
+    use std::time::Duration;
+
+    use bevy::{prelude::*, utils::Instant};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        game_timer::gameplay_schedule::{
+            on_gameplay_tick, GameplayClock, GameplaySchedulePlugin, GameplayStage,
+        },
+        health::health::{death_timers, DeathEvent, Health},
+        rules::game_rules::GameRules,
+        AppState,
+    };
+
+    use super::{
+        nest_positions, Nest, NestCount, NestPlugin, NEST_MAX_DISTANCE, NEST_MIN_DISTANCE,
+    };
+
+    #[test]
+    fn nests_surround_the_base() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let positions = nest_positions(Vec3::ZERO, 4, &mut rng);
+        assert_eq!(positions.len(), 4);
+        for p in positions.iter() {
+            let d = p.length();
+            assert!(d >= NEST_MIN_DISTANCE && d <= NEST_MAX_DISTANCE);
+        }
+        // Not all bunched up on one side
+        let center = positions.iter().sum::<Vec3>() / 4.;
+        assert!(center.length() < NEST_MIN_DISTANCE);
+    }
+
+    #[test]
+    fn no_victory_before_the_nests_are_placed() {
+        assert!(!NestCount::default().all_destroyed());
+        assert!(!NestCount {
+            total: 4,
+            destroyed: 3
+        }
+        .all_destroyed());
+        assert!(NestCount {
+            total: 4,
+            destroyed: 4
+        }
+        .all_destroyed());
+    }
+
+    // Destroys every nest on the 10th tick, like the turrets would
+    fn destroy_nests(
+        clock: Res<GameplayClock>,
+        nests: Query<Entity, With<Nest>>,
+        mut deaths: EventWriter<DeathEvent>,
+    ) {
+        if clock.ticks == 10 {
+            for entity in nests.iter() {
+                deaths.send(DeathEvent {
+                    entity,
+                    killer: None,
+                });
+            }
+        }
+    }
+
+    fn destroyed_at(fps: u32) -> (NestCount, AppState) {
+        let frame = Duration::from_secs(1) / fps;
+        let mut now = Instant::now();
+        let mut time = Time::default();
+        time.update_with_instant(now);
+
+        let mut app = App::new();
+        app.add_state(AppState::InGame)
+            .insert_resource(time)
+            .add_plugin(GameplaySchedulePlugin)
+            .add_plugin(NestPlugin)
+            .init_resource::<GameRules>()
+            .add_event::<DeathEvent>()
+            .insert_resource(NestCount {
+                total: 2,
+                destroyed: 0,
+            })
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick()
+                    .with_system(destroy_nests.before(death_timers))
+                    .with_system(death_timers),
+            );
+        for _ in 0..2 {
+            app.world.spawn((Nest::default(), Health::new(100)));
+        }
+
+        // Half a second
+        for _ in 0..fps / 2 {
+            now += frame;
+            app.world.resource_mut::<Time>().update_with_instant(now);
+            app.update();
+        }
+        let state = app.world.resource::<State<AppState>>().current().clone();
+        (app.world.resource::<NestCount>().clone(), state)
+    }
+
+    #[test]
+    fn nests_are_counted_at_any_frame_rate() {
+        for fps in [30, 240] {
+            let (count, state) = destroyed_at(fps);
+            assert_eq!(count.destroyed, 2, "at {} FPS", fps);
+            assert_eq!(state, AppState::Victory, "at {} FPS", fps);
+        }
+    }
+}
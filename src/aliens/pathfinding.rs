@@ -0,0 +1,274 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap as StdHashMap},
+};
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::buildings::grid::{Grid, SQUARE_SIZE};
+
+// A* over the building grid, so aliens walk around buildings instead of piling up against them.
+// Every square with a building is an obstacle, apart from the square of the building the alien is attacking.
+// The grid is unbounded, the search is only limited by the number of squares it's allowed to visit.
+
+// Stops the search from exploring the whole map when the target is walled in
+const MAX_VISITED_SQUARES: usize = 20_000;
+
+// Aliens move on to the next waypoint once they're this close to it
+pub const WAYPOINT_REACHED_DISTANCE: f32 = SQUARE_SIZE * 0.5;
+
+// The path an alien is currently following
+#[derive(Component, Debug, Clone, Default)]
+pub struct AlienPath {
+    // The centers of the squares to walk through, the next one first
+    pub waypoints: Vec<Vec3>,
+    // What the path was calculated for, it's recalculated if either changes
+    pub target: Option<Entity>,
+    pub grid_version: u32,
+}
+
+impl AlienPath {
+    pub fn is_valid_for(&self, target: Entity, grid: &Grid) -> bool {
+        self.target == Some(target) && self.grid_version == grid.version
+    }
+}
+
+// An open square in the search. Ordered so the BinaryHeap pops the lowest estimated cost first
+#[derive(Clone, Copy, PartialEq)]
+struct OpenSquare {
+    estimate: f32,
+    square: (i8, i8),
+}
+
+impl Eq for OpenSquare {}
+
+impl Ord for OpenSquare {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for OpenSquare {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// The distance on a grid where diagonal moves are allowed
+fn octile_distance(a: (i8, i8), b: (i8, i8)) -> f32 {
+    let dx = (a.0 as f32 - b.0 as f32).abs();
+    let dy = (a.1 as f32 - b.1 as f32).abs();
+    return dx.max(dy) + (std::f32::consts::SQRT_2 - 1.) * dx.min(dy);
+}
+
+fn offset(square: (i8, i8), dx: i8, dy: i8) -> Option<(i8, i8)> {
+    Some((square.0.checked_add(dx)?, square.1.checked_add(dy)?))
+}
+
+// Finds the shortest path of squares from start to goal, both included.
+// `passable` is the entity whose square can be walked into, usually the target building.
+// The start square is always allowed, the alien might have been pushed into a building.
+// Returns None if there is no path or it's too far to find.
+pub fn find_path(
+    blocked: &HashMap<(i8, i8), Entity>,
+    start: (i8, i8),
+    goal: (i8, i8),
+    passable: Option<Entity>,
+) -> Option<Vec<(i8, i8)>> {
+    let is_free = |square: (i8, i8)| match blocked.get(&square) {
+        None => true,
+        Some(e) => Some(*e) == passable || square == goal,
+    };
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: StdHashMap<(i8, i8), (i8, i8)> = StdHashMap::new();
+    let mut cost: StdHashMap<(i8, i8), f32> = StdHashMap::new();
+
+    cost.insert(start, 0.);
+    open.push(OpenSquare {
+        estimate: octile_distance(start, goal),
+        square: start,
+    });
+
+    while let Some(OpenSquare { square, estimate }) = open.pop() {
+        if square == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(previous) = came_from.get(&current) {
+                path.push(*previous);
+                current = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let current_cost = cost[&square];
+        // This entry is outdated, the square was reached more cheaply since it was queued
+        if estimate > current_cost + octile_distance(square, goal) + 1e-4 {
+            continue;
+        }
+        if cost.len() > MAX_VISITED_SQUARES {
+            return None;
+        }
+
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let next = match offset(square, dx, dy) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                if !is_free(next) {
+                    continue;
+                }
+
+                // Don't cut corners, the collider would get stuck on the building
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal {
+                    let side_a = offset(square, dx, 0).map_or(false, is_free);
+                    let side_b = offset(square, 0, dy).map_or(false, is_free);
+                    if !side_a || !side_b {
+                        continue;
+                    }
+                }
+
+                let step = if diagonal {
+                    std::f32::consts::SQRT_2
+                } else {
+                    1.
+                };
+                let next_cost = current_cost + step;
+                if cost.get(&next).map_or(true, |c| next_cost < *c) {
+                    cost.insert(next, next_cost);
+                    came_from.insert(next, square);
+                    open.push(OpenSquare {
+                        estimate: next_cost + octile_distance(next, goal),
+                        square: next,
+                    });
+                }
+            }
+        }
+    }
+
+    return None;
+}
+
+// Turns the squares into world positions and drops the ones in the middle of straight lines,
+// so aliens walk straight instead of stopping at every square
+pub fn path_to_waypoints(path: &[(i8, i8)]) -> Vec<Vec3> {
+    let center = |s: (i8, i8)| {
+        Vec3::new(
+            (s.0 as f32 + 0.5) * SQUARE_SIZE,
+            0.,
+            (s.1 as f32 + 0.5) * SQUARE_SIZE,
+        )
+    };
+
+    let mut waypoints = Vec::new();
+    for (i, square) in path.iter().enumerate().skip(1) {
+        let is_last = i == path.len() - 1;
+        if !is_last {
+            let previous = path[i - 1];
+            let next = path[i + 1];
+            let direction_in = (square.0 - previous.0, square.1 - previous.1);
+            let direction_out = (next.0 - square.0, next.1 - square.1);
+            if direction_in == direction_out {
+                continue;
+            }
+        }
+        waypoints.push(center(*square));
+    }
+    return waypoints;
+}
+
+#[cfg(test)]
+mod test_pathfinding {
+    use bevy::{prelude::Entity, utils::HashMap};
+
+    use super::{find_path, path_to_waypoints};
+
+    // Builds a grid from a picture, # is a building, T is the target building
+    fn grid(rows: &[&str]) -> HashMap<(i8, i8), Entity> {
+        let mut blocked = HashMap::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                match c {
+                    '#' => blocked.insert((x as i8, y as i8), Entity::from_raw(1)),
+                    'T' => blocked.insert((x as i8, y as i8), Entity::from_raw(2)),
+                    _ => None,
+                };
+            }
+        }
+        return blocked;
+    }
+
+    #[test]
+    fn straight_line_on_an_empty_grid() {
+        let path = find_path(&HashMap::new(), (0, 0), (4, 0), None).unwrap();
+        assert_eq!(path, vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)]);
+    }
+
+    #[test]
+    fn walks_around_a_wall() {
+        let blocked = grid(&[
+            ".....", //
+            "..#..", //
+            "..#..", //
+            "..#..", //
+            ".....",
+        ]);
+        let path = find_path(&blocked, (0, 2), (4, 2), None).unwrap();
+        assert!(path.iter().all(|s| !blocked.contains_key(s)));
+        assert_eq!(path.first(), Some(&(0, 2)));
+        assert_eq!(path.last(), Some(&(4, 2)));
+        // Around the end of the wall and back
+        assert_eq!(path.len(), 7);
+    }
+
+    #[test]
+    fn target_building_is_walkable() {
+        let blocked = grid(&[
+            "...", //
+            "#T#", //
+            "...",
+        ]);
+        let path = find_path(&blocked, (1, 0), (1, 1), Some(Entity::from_raw(2))).unwrap();
+        assert_eq!(path, vec![(1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn does_not_cut_corners() {
+        let blocked = grid(&[
+            ".#", //
+            "#.",
+        ]);
+        // The diagonal step is blocked, so it has to go all the way around
+        let path = find_path(&blocked, (0, 0), (1, 1), None).unwrap();
+        assert!(path.len() > 2);
+    }
+
+    #[test]
+    fn walled_in_target_has_no_path() {
+        let blocked = grid(&[
+            ".......", //
+            ".#####.", //
+            ".#...#.", //
+            ".#...#.", //
+            ".#####.", //
+            ".......",
+        ]);
+        assert_eq!(find_path(&blocked, (0, 0), (3, 2), None), None);
+    }
+
+    #[test]
+    fn waypoints_skip_straight_segments() {
+        let path = vec![(0, 0), (1, 0), (2, 0), (2, 1), (2, 2)];
+        let waypoints = path_to_waypoints(&path);
+        // The corner and the end
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0].x, 5.);
+        assert_eq!(waypoints[1].z, 5.);
+    }
+}
@@ -0,0 +1,399 @@
+use bevy::prelude::*;
+
+use crate::{
+    buildings::defensive_buildings::{building_death, AlienTarget, TargetSelecting},
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayStage},
+    health::health::{DamageEvent, Health},
+    main_base::main_base::MainBaseComponent,
+    spatial::spatial_index::{horizontal_distance, SpatialIndex},
+};
+
+use super::alien::{alien_ai, alien_cleanup, Alien};
+
+// How aliens decide what to attack.
+//
+// Every living building with an AlienTarget gets a score and the alien goes for the highest one:
+//
+//     score = priority * preference / (1 + distance / DISTANCE_FALLOFF)
+//
+// - priority is AlienTarget.priority: 8 for the main base, 5 for turrets, 1 for everything else
+// - preference is how much the alien's kind likes that class of target (TargetPreference), 1 is neutral
+// - distance is the horizontal distance, a target DISTANCE_FALLOFF away is worth half as much as one next to the alien
+//
+// So with neutral preferences a generator right next to the alien (1 / 1 = 1) loses to a turret
+// 40 away (5 / 3 = 1.67), which loses to the main base 20 away (8 / 2 = 4).
+// Equal scores go to the closer target, then to the older entity, so the choice doesn't depend on iteration order.
+//
+// Once picked, the target is kept until it dies. The exception is aggro: an alien hit by a turret
+// within LEASH_RANGE turns on that turret, unless it's already attacking a turret that close.
+// The leash is shorter than the laser range, so lasers can pick off aliens without dragging them around the map.
+
+pub const DISTANCE_FALLOFF: f32 = 20.;
+pub const LEASH_RANGE: f32 = 15.;
+
+pub struct AlienTargetingPlugin;
+
+impl Plugin for AlienTargetingPlugin {
+    fn build(&self, app: &mut App) {
+        // Once the deaths of the tick are cleaned up, so nothing goes for a building that was just destroyed
+        app.add_system_set_to_stage(
+            GameplayStage,
+            on_gameplay_tick()
+                .with_system(
+                    select_alien_targets
+                        .after(alien_cleanup)
+                        .after(building_death)
+                        .before(alien_ai),
+                )
+                .with_system(alien_aggro.after(select_alien_targets).before(alien_ai)),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetClass {
+    MainBase,
+    // Anything that shoots back
+    Turret,
+    Other,
+}
+
+impl TargetClass {
+    fn of(main_base: Option<&MainBaseComponent>, guns: Option<&TargetSelecting>) -> Self {
+        match (main_base, guns) {
+            (Some(_), _) => TargetClass::MainBase,
+            (None, Some(_)) => TargetClass::Turret,
+            (None, None) => TargetClass::Other,
+        }
+    }
+}
+
+// Multiplies the priority of each class of target, set per alien kind in alien_kinds
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct TargetPreference {
+    pub main_base: f32,
+    pub turret: f32,
+    pub other: f32,
+}
+
+impl Default for TargetPreference {
+    fn default() -> Self {
+        TargetPreference {
+            main_base: 1.,
+            turret: 1.,
+            other: 1.,
+        }
+    }
+}
+
+impl TargetPreference {
+    fn of(&self, class: TargetClass) -> f32 {
+        match class {
+            TargetClass::MainBase => self.main_base,
+            TargetClass::Turret => self.turret,
+            TargetClass::Other => self.other,
+        }
+    }
+}
+
+pub fn target_score(
+    priority: i8,
+    class: TargetClass,
+    preference: &TargetPreference,
+    distance: f32,
+) -> f32 {
+    return priority as f32 * preference.of(class) / (1. + distance / DISTANCE_FALLOFF);
+}
+
+// A building an alien could go for
+#[derive(Clone, Copy, Debug)]
+pub struct TargetCandidate {
+    pub entity: Entity,
+    pub position: Vec3,
+    pub priority: i8,
+    pub class: TargetClass,
+}
+
+// The best scoring candidate, see the top of the module
+pub fn best_target(
+    position: Vec3,
+    preference: &TargetPreference,
+    candidates: impl Iterator<Item = TargetCandidate>,
+) -> Option<Entity> {
+    let mut best: Option<(Entity, f32, f32)> = None;
+    for c in candidates {
+        let distance = horizontal_distance(position, c.position);
+        let score = target_score(c.priority, c.class, preference, distance);
+        let better = match best {
+            None => true,
+            Some((e, best_score, best_distance)) => {
+                score > best_score
+                    || (score == best_score && distance < best_distance)
+                    || (score == best_score && distance == best_distance && c.entity < e)
+            }
+        };
+        if better {
+            best = Some((c.entity, score, distance));
+        }
+    }
+    return best.map(|(e, ..)| e);
+}
+
+// Gives every alien without a living target the best scoring building
+pub fn select_alien_targets(
+    mut aliens: Query<(&Transform, &Alien, &mut TargetSelecting, &TargetPreference)>,
+    targets: Query<
+        (
+            &AlienTarget,
+            &Health,
+            Option<&MainBaseComponent>,
+            Option<&TargetSelecting>,
+        ),
+        Without<Alien>,
+    >,
+    index: Res<SpatialIndex>,
+) {
+    let mut candidates: Option<Vec<TargetCandidate>> = None;
+
+    for (transform, alien, mut target_selecting, preference) in aliens.iter_mut() {
+        if !alien.alive {
+            continue;
+        }
+        let has_target = target_selecting
+            .target
+            .and_then(|t| targets.get(t).ok())
+            .map_or(false, |(_, h, ..)| h.hp > 0);
+        if has_target {
+            continue;
+        }
+
+        // Only collected on the frames where some alien actually needs a target
+        let candidates = candidates.get_or_insert_with(|| {
+            index
+                .targets
+                .iter()
+                .filter_map(|(entity, position)| {
+                    let (target, _, main_base, guns) = targets.get(entity).ok()?;
+                    Some(TargetCandidate {
+                        entity,
+                        position,
+                        priority: target.priority,
+                        class: TargetClass::of(main_base, guns),
+                    })
+                })
+                .collect()
+        });
+        target_selecting.target = best_target(
+            transform.translation,
+            preference,
+            candidates.iter().copied(),
+        );
+    }
+}
+
+// Aliens turn on the turrets that shoot them, if they're close enough
+pub fn alien_aggro(
+    mut ev: EventReader<DamageEvent>,
+    mut aliens: Query<(&Transform, &Alien, &mut TargetSelecting)>,
+    turrets: Query<
+        (&Transform, &Health),
+        (With<AlienTarget>, With<TargetSelecting>, Without<Alien>),
+    >,
+) {
+    for ev in ev.iter() {
+        let (transform, alien, mut target_selecting) = match aliens.get_mut(ev.entity) {
+            Ok(alien) => alien,
+            Err(_) => continue,
+        };
+        if !alien.alive {
+            continue;
+        }
+        let attacker = match turrets.get(ev.attacker) {
+            Ok((t, h)) if h.hp > 0 => t.translation,
+            _ => continue,
+        };
+        let position = transform.translation;
+        if horizontal_distance(position, attacker) > LEASH_RANGE {
+            continue;
+        }
+
+        // Don't keep switching between the turrets around it
+        let busy_with_turret = match target_selecting.target.and_then(|t| turrets.get(t).ok()) {
+            Some((t, h)) => h.hp > 0 && horizontal_distance(position, t.translation) <= LEASH_RANGE,
+            None => false,
+        };
+        if !busy_with_turret {
+            target_selecting.target = Some(ev.attacker);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_targeting {
+    use bevy::prelude::*;
+
+    use crate::{
+        aliens::alien::Alien,
+        buildings::defensive_buildings::{AlienTarget, TargetSelecting},
+        health::health::{DamageEvent, Health},
+        main_base::main_base::MainBaseComponent,
+        spatial::spatial_index::{update_spatial_index, SpatialIndex},
+    };
+
+    use super::{alien_aggro, select_alien_targets, TargetPreference};
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .init_resource::<SpatialIndex>()
+            .add_event::<DamageEvent>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_spatial_index)
+            .add_system(select_alien_targets)
+            .add_system(alien_aggro.after(select_alien_targets));
+        return app;
+    }
+
+    fn main_base(app: &mut App, x: f32) -> Entity {
+        app.world
+            .spawn((
+                Transform::from_xyz(x, 0., 0.),
+                AlienTarget { priority: 8 },
+                Health::new(1000),
+                MainBaseComponent,
+            ))
+            .id()
+    }
+
+    fn turret(app: &mut App, x: f32) -> Entity {
+        app.world
+            .spawn((
+                Transform::from_xyz(x, 0., 0.),
+                AlienTarget { priority: 5 },
+                Health::new(100),
+                TargetSelecting::new(8.),
+            ))
+            .id()
+    }
+
+    fn generator(app: &mut App, x: f32) -> Entity {
+        app.world
+            .spawn((
+                Transform::from_xyz(x, 0., 0.),
+                AlienTarget::default(),
+                Health::new(100),
+            ))
+            .id()
+    }
+
+    fn alien(app: &mut App, preference: TargetPreference) -> Entity {
+        app.world
+            .spawn((
+                Transform::default(),
+                Alien::default(),
+                TargetSelecting::new(2.5),
+                Health::new(200),
+                preference,
+            ))
+            .id()
+    }
+
+    fn hit(app: &mut App, alien: Entity, attacker: Entity) {
+        app.world
+            .resource_mut::<Events<DamageEvent>>()
+            .send(DamageEvent {
+                entity: alien,
+                attacker,
+                damage: 10,
+            });
+    }
+
+    fn target_of(app: &App, alien: Entity) -> Option<Entity> {
+        app.world.get::<TargetSelecting>(alien).unwrap().target
+    }
+
+    #[test]
+    fn priority_beats_distance() {
+        let mut app = app();
+        generator(&mut app, 3.);
+        let base = main_base(&mut app, 20.);
+        let alien = alien(&mut app, TargetPreference::default());
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(base));
+    }
+
+    #[test]
+    fn distance_beats_equal_priority() {
+        let mut app = app();
+        turret(&mut app, -30.);
+        let close = turret(&mut app, 10.);
+        let alien = alien(&mut app, TargetPreference::default());
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(close));
+    }
+
+    #[test]
+    fn kind_preference_changes_the_target() {
+        let mut app = app();
+        let base = main_base(&mut app, 20.);
+        let turret = turret(&mut app, -25.);
+        let neutral = alien(&mut app, TargetPreference::default());
+        let turret_hunter = alien(
+            &mut app,
+            TargetPreference {
+                turret: 2.,
+                ..default()
+            },
+        );
+        app.update();
+        assert_eq!(target_of(&app, neutral), Some(base));
+        assert_eq!(target_of(&app, turret_hunter), Some(turret));
+    }
+
+    #[test]
+    fn retargets_when_the_target_dies() {
+        let mut app = app();
+        let base = main_base(&mut app, 20.);
+        let generator = generator(&mut app, 5.);
+        let alien = alien(&mut app, TargetPreference::default());
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(base));
+
+        app.world.get_mut::<Health>(base).unwrap().hp = 0;
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(generator));
+    }
+
+    #[test]
+    fn aggro_within_the_leash_range() {
+        let mut app = app();
+        let base = main_base(&mut app, 20.);
+        let close = turret(&mut app, -10.);
+        let far = turret(&mut app, -24.);
+        let alien = alien(&mut app, TargetPreference::default());
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(base));
+
+        // Too far away to chase
+        hit(&mut app, alien, far);
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(base));
+
+        hit(&mut app, alien, close);
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(close));
+    }
+
+    #[test]
+    fn aggro_does_not_switch_between_close_turrets() {
+        let mut app = app();
+        let first = turret(&mut app, 6.);
+        let second = turret(&mut app, -8.);
+        let alien = alien(&mut app, TargetPreference::default());
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(first));
+
+        hit(&mut app, alien, second);
+        app.update();
+        assert_eq!(target_of(&app, alien), Some(first));
+    }
+}
@@ -1,14 +1,26 @@
 use bevy::{
     input::{mouse::MouseButtonInput, ButtonState},
     prelude::*,
+    utils::HashMap,
 };
 use bevy_egui::EguiContext;
 use bevy_kira_audio::{prelude::*, Audio};
+use serde::Deserialize;
 
+use super::{
+    channels::{
+        AlertsChannel, AliensChannel, AudioChannelsPlugin, SoundChannel, UiChannel, WeaponsChannel,
+    },
+    music::MusicPlugin,
+    sound_registry::{SoundEvent, SoundRegistryPlugin, Sounds},
+    spatial::{default_listener, distance_volume, stereo_panning},
+    voice_limiter::{LimitedSound, SoundRequest, VoiceLimiter},
+};
 use crate::{
-    aliens::alien::AlienSpawnEvent,
+    aliens::{alien::AlienSpawnEvent, bosses::BossEvent},
     effects::muzzleflash::GunFireEvent,
     health::health::{DeathEvent, Health},
+    settings::settings::GameSettings,
     ui::error_info::ErrorEvent,
     AppState,
 };
@@ -19,14 +31,17 @@ pub struct MyAudioPlugin;
 
 impl Plugin for MyAudioPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<AudioHandles>()
-            .add_startup_system(register_sounds)
+        app.init_resource::<VoiceLimiter>()
             .add_plugin(AudioPlugin)
+            .add_plugin(SoundRegistryPlugin)
+            .add_plugin(AudioChannelsPlugin)
+            .add_plugin(MusicPlugin)
             .add_system(ui_click)
             .add_system_set(
                 SystemSet::on_update(AppState::InGame)
                     .with_system(error_sound)
                     .with_system(alien_spawn_sound)
+                    .with_system(boss_sound)
                     .with_system(gun_fire_sound)
                     .with_system(explosion_on_death),
             )
@@ -37,94 +52,47 @@ impl Plugin for MyAudioPlugin {
     }
 }
 
-#[derive(Resource, Clone, Debug)]
-pub struct AudioHandles {
-    building_explosion: Option<Handle<AudioSource>>,
-    alien_death: Option<Handle<AudioSource>>,
-    alien_spawn: Option<Handle<AudioSource>>,
-    laser_fire: Option<Handle<AudioSource>>,
-    gun_fire: Option<Handle<AudioSource>>,
-    error: Option<Handle<AudioSource>>,
-    click: Option<Handle<AudioSource>>,
-    victory: Option<Handle<AudioSource>>,
-    game_over: Option<Handle<AudioSource>>,
-}
-
-impl Default for AudioHandles {
-    fn default() -> Self {
-        AudioHandles {
-            building_explosion: None,
-            alien_death: None,
-            alien_spawn: None,
-            gun_fire: None,
-            laser_fire: None,
-            error: None,
-            click: None,
-            game_over: None,
-            victory: None,
-        }
+pub fn victory_fanfare_sound(
+    audio: Res<AudioChannel<AlertsChannel>>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
+) {
+    if let Some(sound) = sounds.pick(SoundEvent::Victory) {
+        sound.play(&audio, settings.channel_volume(SoundChannel::Alerts));
     }
 }
 
-// Register sounds, so we only load the files once and store a reference in a resource
-pub fn register_sounds(ass: Res<AssetServer>, mut audio_handles: ResMut<AudioHandles>) {
-    *audio_handles = AudioHandles {
-        building_explosion: ass.load("sounds/explosion.wav").into(),
-        alien_death: ass.load("sounds/alien_death.wav").into(),
-        alien_spawn: ass.load("sounds/alien_spawn.wav").into(),
-        gun_fire: ass.load("sounds/machine_gun.mp3").into(),
-        laser_fire: ass.load("sounds/laser.wav").into(),
-        error: ass.load("sounds/error.mp3").into(),
-        click: ass.load("sounds/click.mp3").into(),
-        victory: ass.load("sounds/victory.mp3").into(),
-        game_over: ass.load("sounds/game_over.mp3").into(),
+pub fn game_over_sound(
+    audio: Res<AudioChannel<AlertsChannel>>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
+) {
+    if let Some(sound) = sounds.pick(SoundEvent::GameOver) {
+        sound.play(&audio, settings.channel_volume(SoundChannel::Alerts));
     }
 }
 
-pub fn victory_fanfare_sound(audio: Res<Audio>, audio_handles: Res<AudioHandles>) {
-    audio
-        .play(audio_handles.victory.clone().unwrap())
-        .with_volume(Volume::Amplitude(1.));
-}
-
-pub fn game_over_sound(audio: Res<Audio>, audio_handles: Res<AudioHandles>) {
-    audio
-        .play(audio_handles.game_over.clone().unwrap())
-        .with_volume(Volume::Amplitude(1.));
-}
-
-#[derive(Component, Clone, Copy, PartialEq, Debug)]
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize)]
 pub enum AudioType {
     Building,
     Alien,
 }
 
+// We get the camera to figure out where the listener is relative to the sound source
+pub fn camera_transform(camera: &Query<&Transform, With<Camera>>) -> Transform {
+    camera.get_single().copied().unwrap_or_else(|_| {
+        dbg!("Cannot find camera position");
+        default_listener()
+    })
+}
+
 pub fn audio_distance_volume(
     camera: &Query<&Transform, With<Camera>>,
     position: Vec3,
     max_dist: Option<f32>,
 ) -> f32 {
-    // We get the camera pos to figure out how far the listener is from the sound source
-    let camera_pos = camera
-        .get_single()
-        .and_then(|q| Ok(q.translation))
-        .unwrap_or_else(|_| {
-            dbg!("Cannot find camera position");
-            Vec3::new(0., 15., 0.)
-        });
-
-    let distance = position.distance(camera_pos);
-
-    // Decrease faster than in the real world
-    let mut volume = 1. / (f32::log2(distance * 5.));
-
-    if let Some(max_dist) = max_dist {
-        if (distance > max_dist) {
-            return 0.;
-        };
-    }
-
-    return volume;
+    let distance = position.distance(camera_transform(camera).translation);
+    return distance_volume(distance, max_dist);
 }
 
 // Plays a spatially edited sound for any dying entity
@@ -133,28 +101,36 @@ pub fn audio_distance_volume(
 // As I only need the volume to decrease anyway, I wrote a custom function for that
 // This also allows me to tweak how fast sound decreases based on distance
 pub fn explosion_on_death(
-    audio: Res<Audio>,
+    aliens_channel: Res<AudioChannel<AliensChannel>>,
+    alerts_channel: Res<AudioChannel<AlertsChannel>>,
     // ass: Res<AssetServer>,
     mut events: EventReader<DeathEvent>,
-    audio_handles: Res<AudioHandles>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+    mut limiter: ResMut<VoiceLimiter>,
 
     camera: Query<&Transform, With<Camera>>,
     mut dying_entity: Query<(&Transform, Option<&AudioType>, &mut Health, Entity), Without<Camera>>,
 ) {
+    let listener = camera_transform(&camera);
+    let mut alien_deaths = Vec::new();
+    let mut explosions = Vec::new();
+
     for ev in events.iter() {
         if let Ok((transform, sound, mut health, _)) = dying_entity.get_mut(ev.entity) {
             // Only play sound once
             // The death event can be triggered multiple times for the same entity
             if health.death_sound_played {
-                return;
+                continue;
             }
             health.death_sound_played = true;
 
             // Only play if there is audio
-            if let None = sound {
-                return;
-            }
-            let sound = sound.unwrap();
+            let sound = match sound {
+                Some(sound) => sound,
+                None => continue,
+            };
 
             // Dont play sounds if the camera is more than x units away from the source
             // We can get a different max_dist based on the soudn type.
@@ -167,89 +143,170 @@ pub fn explosion_on_death(
                 }),
             );
 
-            // Decrease the total volume for this effect
-            // volume modifiers based on the specific sound
+            // Buildings are a bit louder than their distance alone would make them
+            // The rest of the volume is set per sound in the registry
+            if *sound == AudioType::Building {
+                volume += 0.05;
+            }
+
+            let request = SoundRequest::new(transform.translation, volume, &listener);
             match sound {
-                AudioType::Alien => volume *= 0.01,
-                AudioType::Building => volume += 0.05,
+                AudioType::Alien => alien_deaths.push(request),
+                AudioType::Building => explosions.push(request),
             };
+        }
+    }
 
-            // play sound with the calculated volume
-            audio
+    // play sound with the calculated volume
+    // Losing a building is an alert, dying aliens go to their own channel
+    for r in limiter.select(LimitedSound::AlienDeath, time.elapsed(), alien_deaths) {
+        if let Some(sound) = sounds.pick(SoundEvent::Death(AudioType::Alien)) {
+            sound
+                .play(
+                    &aliens_channel,
+                    r.volume as f64 * settings.channel_volume(SoundChannel::Aliens),
+                )
+                .with_panning(r.pan as f64);
+        }
+    }
+    for r in limiter.select(LimitedSound::BuildingExplosion, time.elapsed(), explosions) {
+        if let Some(sound) = sounds.pick(SoundEvent::Death(AudioType::Building)) {
+            sound
                 .play(
-                    match sound {
-                        AudioType::Building => audio_handles.building_explosion.clone(),
-                        AudioType::Alien => audio_handles.alien_death.clone(),
-                    }
-                    .unwrap(),
+                    &alerts_channel,
+                    r.volume as f64 * settings.channel_volume(SoundChannel::Alerts),
                 )
-                .with_volume(Volume::Amplitude(volume.into()));
+                .with_panning(r.pan as f64);
         }
     }
 }
 
 pub fn alien_spawn_sound(
     mut ev: EventReader<AlienSpawnEvent>,
-    audio_handles: Res<AudioHandles>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+    mut limiter: ResMut<VoiceLimiter>,
 
-    audio: Res<Audio>,
+    audio: Res<AudioChannel<AliensChannel>>,
     camera: Query<&Transform, With<Camera>>,
 ) {
-    for e in ev.iter() {
-        let mut volume = audio_distance_volume(&camera, e.point, None);
-        volume *= 0.4;
+    let listener = camera_transform(&camera);
+    let requests = ev
+        .iter()
+        .map(|e| {
+            let volume = audio_distance_volume(&camera, e.point, None);
+            SoundRequest::new(e.point, volume, &listener)
+        })
+        .collect::<Vec<_>>();
 
-        audio
-            .play(audio_handles.alien_spawn.clone().unwrap())
-            .with_volume(Volume::Amplitude(volume.into()));
+    for r in limiter.select(LimitedSound::AlienSpawn, time.elapsed(), requests) {
+        if let Some(sound) = sounds.pick(SoundEvent::AlienSpawn) {
+            sound
+                .play(
+                    &audio,
+                    r.volume as f64 * settings.channel_volume(SoundChannel::Aliens),
+                )
+                .with_panning(r.pan as f64);
+        }
+    }
+}
+
+// Bosses are announced wherever the camera is, so they aren't faded with the distance.
+// They're still panned, so the player can hear which side the boss is on
+pub fn boss_sound(
+    mut ev: EventReader<BossEvent>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
+
+    audio: Res<AudioChannel<AlertsChannel>>,
+    camera: Query<&Transform, With<Camera>>,
+) {
+    let listener = camera_transform(&camera);
+    for e in ev.iter() {
+        let (event, point) = match *e {
+            BossEvent::Arrived(point) => (SoundEvent::BossArrival, point),
+            BossEvent::Enraged(point) => (SoundEvent::BossEnrage, point),
+            BossEvent::Died(point) => (SoundEvent::BossDeath, point),
+        };
+        if let Some(sound) = sounds.pick(event) {
+            sound
+                .play(&audio, settings.channel_volume(SoundChannel::Alerts))
+                .with_panning(stereo_panning(&listener, point) as f64);
+        }
     }
 }
 
 // Plays a sound every time a gun fires
+// Goes through the voice limiter, as big bases fire many times per frame
 pub fn gun_fire_sound(
     mut ev: EventReader<GunFireEvent>,
-    audio_handles: Res<AudioHandles>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+    mut limiter: ResMut<VoiceLimiter>,
 
-    audio: Res<Audio>,
+    audio: Res<AudioChannel<WeaponsChannel>>,
     camera: Query<&Transform, With<Camera>>,
 ) {
+    use crate::effects::muzzleflash::GunType::{self, *};
+
+    let listener = camera_transform(&camera);
+    let mut requests: HashMap<GunType, Vec<SoundRequest>> = HashMap::new();
+
     for e in ev.iter() {
-        let mut volume = audio_distance_volume(&camera, e.transform.translation, None);
-        volume *= 0.3;
+        let volume = audio_distance_volume(&camera, e.transform.translation, None);
+        requests
+            .entry(e.gun_type)
+            .or_default()
+            .push(SoundRequest::new(
+                e.transform.translation,
+                volume,
+                &listener,
+            ));
+    }
 
-        use crate::effects::muzzleflash::GunType::*;
-        audio
-            .play(
-                match e.gun_type {
-                    LaserGun => audio_handles.laser_fire.clone(),
-                    MachineGun | MachineGunMk2 => audio_handles.gun_fire.clone(),
-                }
-                .unwrap(),
-            )
-            .with_volume(Volume::Amplitude(volume.into()));
+    for (gun_type, requests) in requests {
+        let limited = match gun_type {
+            MachineGun => LimitedSound::MachineGun,
+            MachineGunMk2 => LimitedSound::MachineGunMk2,
+            LaserGun => LimitedSound::Laser,
+        };
+        for r in limiter.select(limited, time.elapsed(), requests) {
+            if let Some(sound) = sounds.pick(SoundEvent::GunFire(gun_type)) {
+                sound
+                    .play(
+                        &audio,
+                        r.volume as f64 * settings.channel_volume(SoundChannel::Weapons),
+                    )
+                    .with_panning(r.pan as f64);
+            }
+        }
     }
 }
 
 // Plays a sound on every error
 pub fn error_sound(
     mut ev: EventReader<ErrorEvent>,
-    audio_handles: Res<AudioHandles>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
 
-    audio: Res<Audio>,
+    audio: Res<AudioChannel<UiChannel>>,
 ) {
     for _ in ev.iter() {
-        audio
-            .play(audio_handles.error.clone().unwrap())
-            .with_volume(Volume::Amplitude(1.));
+        if let Some(sound) = sounds.pick(SoundEvent::Error) {
+            sound.play(&audio, settings.channel_volume(SoundChannel::Ui));
+        }
     }
 }
 
 // Plays a sound on every click in a menu
 // We dont wanna play sounds on every click on a map
 pub fn ui_click(
-    audio_handles: Res<AudioHandles>,
+    sounds: Sounds,
+    settings: Res<GameSettings>,
 
-    audio: Res<Audio>,
+    audio: Res<AudioChannel<UiChannel>>,
     mut ctx: ResMut<EguiContext>,
     mut ev: EventReader<MouseButtonInput>,
 ) {
@@ -259,9 +316,9 @@ pub fn ui_click(
             // If the mouse is currently over any egui menu
             // Aka if the user clicked on a menu
             if ctx.ctx_mut().is_pointer_over_area() {
-                audio
-                    .play(audio_handles.click.clone().unwrap())
-                    .with_volume(Volume::Amplitude(0.5));
+                if let Some(sound) = sounds.pick(SoundEvent::UiClick) {
+                    sound.play(&audio, settings.channel_volume(SoundChannel::Ui));
+                }
             }
         }
     }
@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use bevy_kira_audio::{prelude::*, AudioApp};
+
+use crate::{
+    controls::controls::{Action, ActionInput},
+    settings::settings::GameSettings,
+};
+
+// Every sound plays on one of these channels, each with its own volume in the settings.
+// A sound's final volume is its own base volume * distance attenuation * the channel volume, worked out when it's played.
+// The kira channel volume is never set, it would overwrite the volume of every playing sound, attenuation included.
+// Sound effects are short, so a changed volume is simply picked up by the next ones.
+
+pub struct AudioChannelsPlugin;
+
+impl Plugin for AudioChannelsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_audio_channel::<MusicChannel>()
+            .add_audio_channel::<WeaponsChannel>()
+            .add_audio_channel::<AliensChannel>()
+            .add_audio_channel::<UiChannel>()
+            .add_audio_channel::<AlertsChannel>()
+            .add_system(stop_muted_sounds)
+            .add_system(toggle_mute);
+    }
+}
+
+// The marker types for the bevy_kira_audio channels
+#[derive(Resource)]
+pub struct MusicChannel;
+#[derive(Resource)]
+pub struct WeaponsChannel;
+#[derive(Resource)]
+pub struct AliensChannel;
+#[derive(Resource)]
+pub struct UiChannel;
+#[derive(Resource)]
+pub struct AlertsChannel;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SoundChannel {
+    Music,
+    Weapons,
+    Aliens,
+    Ui,
+    Alerts,
+}
+
+impl SoundChannel {
+    pub const ALL: [SoundChannel; 5] = [
+        SoundChannel::Music,
+        SoundChannel::Weapons,
+        SoundChannel::Aliens,
+        SoundChannel::Ui,
+        SoundChannel::Alerts,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoundChannel::Music => "Music",
+            SoundChannel::Weapons => "Weapons",
+            SoundChannel::Aliens => "Aliens",
+            SoundChannel::Ui => "Interface",
+            SoundChannel::Alerts => "Alerts",
+        }
+    }
+}
+
+// Makes the global mute silence the sound effects immediately instead of once they finish
+// Music is left out, update_music_volumes fades it out itself
+pub fn stop_muted_sounds(
+    settings: Res<GameSettings>,
+    weapons: Res<AudioChannel<WeaponsChannel>>,
+    aliens: Res<AudioChannel<AliensChannel>>,
+    ui: Res<AudioChannel<UiChannel>>,
+    alerts: Res<AudioChannel<AlertsChannel>>,
+) {
+    if !settings.is_changed() || !settings.muted {
+        return;
+    }
+
+    weapons.stop();
+    aliens.stop();
+    ui.stop();
+    alerts.stop();
+}
+
+// The global mute works everywhere, including the menus
+pub fn toggle_mute(actions: Res<ActionInput>, mut settings: ResMut<GameSettings>) {
+    if actions.just_pressed(Action::ToggleMute) {
+        settings.muted = !settings.muted;
+        settings.save();
+    }
+}
@@ -1 +1,6 @@
-pub mod audio;
\ No newline at end of file
+pub mod audio;
+pub mod channels;
+pub mod music;
+pub mod sound_registry;
+pub mod spatial;
+pub mod voice_limiter;
\ No newline at end of file
@@ -0,0 +1,339 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+use super::channels::{MusicChannel, SoundChannel};
+use crate::{
+    aliens::{
+        alien::{Alien, AlienCount},
+        bosses::Boss,
+    },
+    buildings::grid::Grid,
+    health::health::Health,
+    main_base::main_base::MainBaseComponent,
+    settings::settings::GameSettings,
+    AppState,
+};
+
+// Background music.
+// In the menus a single track loops, the victory and game over screens play theirs once.
+// In game three stems (calm, tension and combat) play at the same time and are crossfaded based on how threatened the base is.
+// All stems have the same length and tempo, so they stay in sync and the mix can change at any time.
+
+pub struct MusicPlugin;
+
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MusicHandles>()
+            .init_resource::<MusicPlayer>()
+            .add_startup_system(register_music)
+            .add_system(select_music)
+            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(update_threat))
+            .add_system(update_music_volumes.after(select_music));
+    }
+}
+
+// How long tracks take to fade in and out when the music changes
+const TRACK_FADE: Duration = Duration::from_millis(1500);
+// How long a volume change between the in game stems takes
+const STEM_FADE: Duration = Duration::from_millis(250);
+
+// Aliens closer than this to the edge of the base count as attacking it
+const NEAR_BASE_DISTANCE: f32 = 15.;
+// This many aliens on the map is the most threatening on its own
+const MAX_ALIEN_COUNT: f32 = 60.;
+// This many aliens close to the base means full combat
+const MAX_NEAR_ALIENS: f32 = 12.;
+
+// How fast the threat level follows the target, per second
+// It rises quickly so combat music starts with the fight, and falls slowly so it doesn't stop between waves
+const THREAT_RISE_SPEED: f32 = 0.5;
+const THREAT_FALL_SPEED: f32 = 0.08;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MusicTrack {
+    Calm,
+    Tension,
+    Combat,
+    Menu,
+    Victory,
+    GameOver,
+}
+
+impl MusicTrack {
+    pub const ALL: [MusicTrack; 6] = [
+        MusicTrack::Calm,
+        MusicTrack::Tension,
+        MusicTrack::Combat,
+        MusicTrack::Menu,
+        MusicTrack::Victory,
+        MusicTrack::GameOver,
+    ];
+
+    // Relative to the assets folder
+    pub fn file(&self) -> &'static str {
+        match self {
+            MusicTrack::Calm => "music/calm.wav",
+            MusicTrack::Tension => "music/tension.wav",
+            MusicTrack::Combat => "music/combat.wav",
+            MusicTrack::Menu => "music/menu.wav",
+            MusicTrack::Victory => "music/victory.wav",
+            MusicTrack::GameOver => "music/game_over.wav",
+        }
+    }
+}
+
+// What should be playing right now
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MusicSelection {
+    Menu,
+    InGame,
+    Victory,
+    GameOver,
+}
+
+impl MusicSelection {
+    fn tracks(&self) -> &'static [MusicTrack] {
+        match self {
+            MusicSelection::Menu => &[MusicTrack::Menu],
+            MusicSelection::InGame => &[MusicTrack::Calm, MusicTrack::Tension, MusicTrack::Combat],
+            MusicSelection::Victory => &[MusicTrack::Victory],
+            MusicSelection::GameOver => &[MusicTrack::GameOver],
+        }
+    }
+
+    // The end of game tracks are stingers, not loops
+    fn loops(&self) -> bool {
+        !matches!(self, MusicSelection::Victory | MusicSelection::GameOver)
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct MusicHandles {
+    calm: Handle<AudioSource>,
+    tension: Handle<AudioSource>,
+    combat: Handle<AudioSource>,
+    menu: Handle<AudioSource>,
+    victory: Handle<AudioSource>,
+    game_over: Handle<AudioSource>,
+}
+
+impl MusicHandles {
+    fn get(&self, track: MusicTrack) -> Handle<AudioSource> {
+        match track {
+            MusicTrack::Calm => self.calm.clone(),
+            MusicTrack::Tension => self.tension.clone(),
+            MusicTrack::Combat => self.combat.clone(),
+            MusicTrack::Menu => self.menu.clone(),
+            MusicTrack::Victory => self.victory.clone(),
+            MusicTrack::GameOver => self.game_over.clone(),
+        }
+    }
+}
+
+pub fn register_music(ass: Res<AssetServer>, mut handles: ResMut<MusicHandles>) {
+    *handles = MusicHandles {
+        calm: ass.load(MusicTrack::Calm.file()),
+        tension: ass.load(MusicTrack::Tension.file()),
+        combat: ass.load(MusicTrack::Combat.file()),
+        menu: ass.load(MusicTrack::Menu.file()),
+        victory: ass.load(MusicTrack::Victory.file()),
+        game_over: ass.load(MusicTrack::GameOver.file()),
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct MusicPlayer {
+    selection: Option<MusicSelection>,
+    // The instances that are currently playing, with the volume last sent to them
+    playing: Vec<(MusicTrack, Handle<AudioInstance>, f64)>,
+    // 0 is peaceful, 1 is the base being overrun
+    pub threat: f32,
+}
+
+// The volume of each track for a given threat level.
+// Calm fades out on the way to 0.5, where tension is loudest, combat takes over from there.
+pub fn track_mix(track: MusicTrack, threat: f32) -> f32 {
+    let threat = threat.clamp(0., 1.);
+    match track {
+        MusicTrack::Calm => (1. - 2. * threat).max(0.),
+        MusicTrack::Tension => 1. - (2. * threat - 1.).abs(),
+        MusicTrack::Combat => (2. * threat - 1.).max(0.),
+        _ => 1.,
+    }
+}
+
+// How threatened the base is, between 0 and 1.
+// The aliens near the base and the damage to the main base matter the most, the total number of aliens only builds tension
+pub fn threat_level(alien_count: u32, aliens_near_base: u32, main_base_health: f32) -> f32 {
+    let count = (alien_count as f32 / MAX_ALIEN_COUNT).min(1.) * 0.5;
+    let near = (aliens_near_base as f32 / MAX_NEAR_ALIENS).min(1.);
+    let damage = 1. - main_base_health.clamp(0., 1.);
+
+    return count.max(near).max(damage);
+}
+
+// Picks the music based on the app state and switches tracks when it changes
+pub fn select_music(
+    state: Res<State<AppState>>,
+    audio: Res<AudioChannel<MusicChannel>>,
+    handles: Res<MusicHandles>,
+    mut player: ResMut<MusicPlayer>,
+    mut instances: ResMut<Assets<AudioInstance>>,
+) {
+    // The pause menu, and the settings opened from it, sit on top of the game and keep its music
+    let in_game =
+        *state.current() == AppState::InGame || state.inactives().contains(&AppState::InGame);
+    let selection = match state.current() {
+        _ if in_game => MusicSelection::InGame,
+        AppState::Victory => MusicSelection::Victory,
+        AppState::GameOver => MusicSelection::GameOver,
+        _ => MusicSelection::Menu,
+    };
+
+    if player.selection == Some(selection) {
+        return;
+    }
+
+    for (_, instance, _) in player.playing.drain(..) {
+        if let Some(instance) = instances.get_mut(&instance) {
+            instance.stop(AudioTween::linear(TRACK_FADE));
+        }
+    }
+
+    // Start silent, update_music_volumes fades the tracks in
+    player.playing = selection
+        .tracks()
+        .iter()
+        .map(|track| {
+            let mut play = audio.play(handles.get(*track));
+            if selection.loops() {
+                play.looped();
+            }
+            let instance = play.with_volume(Volume::Amplitude(0.)).handle();
+            (*track, instance, 0.)
+        })
+        .collect();
+    player.selection = Some(selection);
+
+    // Every game starts calm
+    if selection == MusicSelection::InGame {
+        player.threat = 0.;
+    }
+}
+
+pub fn update_threat(
+    time: Res<Time>,
+    count: Res<AlienCount>,
+    grid: Res<Grid>,
+    aliens: Query<(&Transform, &Alien)>,
+    main_base: Query<&Health, With<MainBaseComponent>>,
+    bosses: Query<&Boss>,
+    mut player: ResMut<MusicPlayer>,
+) {
+    let near_distance = grid.center_radius + NEAR_BASE_DISTANCE;
+    let aliens_near_base = aliens
+        .iter()
+        .filter(|(transform, alien)| {
+            alien.alive && transform.translation.distance(grid.base_center) < near_distance
+        })
+        .count() as u32;
+
+    let main_base_health = main_base
+        .get_single()
+        .map(|health| health.hp as f32 / health.max_hp as f32)
+        .unwrap_or(1.);
+
+    let mut target = threat_level(count.count, aliens_near_base, main_base_health);
+    // A boss fight is always a fight
+    if bosses.iter().any(|boss| !boss.dead) {
+        target = 1.;
+    }
+    let speed = if target > player.threat {
+        THREAT_RISE_SPEED
+    } else {
+        THREAT_FALL_SPEED
+    };
+    let step = speed * time.delta_seconds();
+    player.threat += (target - player.threat).clamp(-step, step);
+}
+
+// Applies the crossfade and the music volume setting to the playing tracks
+// Like the other channels, the kira channel volume isn't used as it would overwrite the mix
+pub fn update_music_volumes(
+    settings: Res<GameSettings>,
+    mut player: ResMut<MusicPlayer>,
+    mut instances: ResMut<Assets<AudioInstance>>,
+) {
+    let threat = player.threat;
+    let music_volume = settings.channel_volume(SoundChannel::Music);
+
+    for (track, instance, last_volume) in player.playing.iter_mut() {
+        let volume = track_mix(*track, threat) as f64 * music_volume;
+        // Don't send a command every frame
+        if (volume - *last_volume).abs() < 0.01 {
+            continue;
+        }
+
+        // The instance only exists once the sound started playing, try again next frame until then
+        if let Some(instance) = instances.get_mut(instance) {
+            let fade = if *last_volume == 0. {
+                TRACK_FADE
+            } else {
+                STEM_FADE
+            };
+            instance.set_volume(volume, AudioTween::linear(fade));
+            *last_volume = volume;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_music {
+    use std::{fs, path::Path};
+
+    use super::{threat_level, track_mix, MusicSelection, MusicTrack};
+
+    #[test]
+    fn stems_crossfade_with_threat() {
+        assert_eq!(track_mix(MusicTrack::Calm, 0.), 1.);
+        assert_eq!(track_mix(MusicTrack::Combat, 0.), 0.);
+        assert_eq!(track_mix(MusicTrack::Tension, 0.5), 1.);
+        assert_eq!(track_mix(MusicTrack::Calm, 1.), 0.);
+        assert_eq!(track_mix(MusicTrack::Combat, 1.), 1.);
+    }
+
+    #[test]
+    fn damaged_base_is_a_threat() {
+        assert_eq!(threat_level(0, 0, 1.), 0.);
+        assert_eq!(threat_level(0, 0, 0.25), 0.75);
+        // Lots of aliens far away only build tension
+        assert_eq!(threat_level(1000, 0, 1.), 0.5);
+        assert_eq!(threat_level(20, 100, 1.), 1.);
+    }
+
+    #[test]
+    fn end_of_game_tracks_play_once() {
+        assert!(MusicSelection::Menu.loops());
+        assert!(MusicSelection::InGame.loops());
+        assert!(!MusicSelection::Victory.loops());
+        assert!(!MusicSelection::GameOver.loops());
+    }
+
+    #[test]
+    fn every_track_is_in_the_assets() {
+        let size = |track: MusicTrack| {
+            let path = Path::new("assets").join(track.file());
+            fs::metadata(&path)
+                .unwrap_or_else(|_| panic!("{} is missing", path.display()))
+                .len()
+        };
+        for track in MusicTrack::ALL {
+            size(track);
+        }
+        // Same format, so the same size means the same length and the stems stay in sync
+        assert_eq!(size(MusicTrack::Calm), size(MusicTrack::Tension));
+        assert_eq!(size(MusicTrack::Calm), size(MusicTrack::Combat));
+    }
+}
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, marker::PhantomData};
+
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    ecs::system::SystemParam,
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use bevy_kira_audio::{prelude::*, PlayAudioCommand};
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use super::audio::AudioType;
+use crate::effects::muzzleflash::GunType;
+
+// Maps the things that make a sound in the game to the sound files, see assets/sounds/registry.sounds.
+// Adding a variant of a sound or tweaking its volume only needs a change to that file.
+// Systems ask the `Sounds` system param for a sound, which picks one of the variants at random.
+
+pub struct SoundRegistryPlugin;
+
+impl Plugin for SoundRegistryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<SoundRegistryAsset>()
+            .init_asset_loader::<SoundRegistryLoader>()
+            .init_resource::<SoundRegistry>()
+            .add_startup_system(load_sound_registry);
+    }
+}
+
+// The file is ron, but with its own extension. The asset server picks loaders by the part after the last dot,
+// so a .ron file would never be handed to this loader
+const REGISTRY_PATH: &str = "sounds/registry.sounds";
+
+// The keys of the registry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum SoundEvent {
+    GunFire(GunType),
+    Death(AudioType),
+    AlienSpawn,
+    Error,
+    UiClick,
+    Victory,
+    GameOver,
+    BossArrival,
+    BossEnrage,
+    BossDeath,
+}
+
+// An entry as it's written in the file
+#[derive(Debug, Deserialize)]
+struct SoundDefinition {
+    variants: Vec<String>,
+    #[serde(default = "default_volume")]
+    volume: f32,
+    #[serde(default = "default_pitch")]
+    pitch: f32,
+    #[serde(default)]
+    pitch_variation: f32,
+}
+
+fn default_volume() -> f32 {
+    1.
+}
+
+fn default_pitch() -> f32 {
+    1.
+}
+
+#[derive(Debug, Deserialize)]
+struct SoundRegistryFile {
+    sounds: HashMap<SoundEvent, SoundDefinition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SoundEntry {
+    pub variants: Vec<Handle<AudioSource>>,
+    pub volume: f32,
+    pub pitch: f32,
+    pub pitch_variation: f32,
+}
+
+#[derive(Debug, TypeUuid)]
+#[uuid = "6b1f4d7e-2c3a-4f0e-9a51-8d2e7c4b9f13"]
+pub struct SoundRegistryAsset {
+    pub sounds: HashMap<SoundEvent, SoundEntry>,
+}
+
+// Loads the registry file and all the sound files it references as dependencies
+#[derive(Default)]
+pub struct SoundRegistryLoader;
+
+impl AssetLoader for SoundRegistryLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let file: SoundRegistryFile = ron::de::from_bytes(bytes)?;
+
+            let mut dependencies = Vec::new();
+            let mut sounds = HashMap::new();
+            for (event, definition) in file.sounds {
+                let variants = definition
+                    .variants
+                    .into_iter()
+                    .map(|path| {
+                        let path = AssetPath::from(path);
+                        dependencies.push(path.clone());
+                        load_context.get_handle(path)
+                    })
+                    .collect();
+
+                sounds.insert(
+                    event,
+                    SoundEntry {
+                        variants,
+                        volume: definition.volume,
+                        pitch: definition.pitch,
+                        pitch_variation: definition.pitch_variation,
+                    },
+                );
+            }
+
+            load_context.set_default_asset(
+                LoadedAsset::new(SoundRegistryAsset { sounds }).with_dependencies(dependencies),
+            );
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["sounds"]
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct SoundRegistry {
+    pub handle: Handle<SoundRegistryAsset>,
+}
+
+pub fn load_sound_registry(ass: Res<AssetServer>, mut registry: ResMut<SoundRegistry>) {
+    registry.handle = ass.load(REGISTRY_PATH);
+}
+
+// A variant picked from the registry, ready to be played
+#[derive(Debug, Clone)]
+pub struct PickedSound {
+    pub handle: Handle<AudioSource>,
+    pub volume: f64,
+    pub playback_rate: f64,
+}
+
+impl PickedSound {
+    // Plays the sound with the given volume on top of its base volume
+    // The returned command can be modified further, e.g. with panning. The sound plays once it's dropped
+    pub fn play<'a, T: Resource>(
+        &self,
+        channel: &'a AudioChannel<T>,
+        volume: f64,
+    ) -> PlayAudioCommand<'a> {
+        let mut command = channel.play(self.handle.clone());
+        command
+            .with_volume(Volume::Amplitude(self.volume * volume))
+            .with_playback_rate(self.playback_rate);
+        return command;
+    }
+}
+
+#[derive(SystemParam)]
+pub struct Sounds<'w, 's> {
+    registry: Res<'w, SoundRegistry>,
+    registries: Res<'w, Assets<SoundRegistryAsset>>,
+    sources: Res<'w, Assets<AudioSource>>,
+    #[system_param(ignore)]
+    _marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Sounds<'w, 's> {
+    // Picks a random variant for the event.
+    // Returns None if there is nothing to play: the registry isn't loaded yet, the event has no entry,
+    // or the picked file failed to load (or is still loading). The game just stays silent in those cases
+    pub fn pick(&self, event: SoundEvent) -> Option<PickedSound> {
+        let entry = self
+            .registries
+            .get(&self.registry.handle)?
+            .sounds
+            .get(&event)?;
+
+        let mut rng = rand::thread_rng();
+        let handle = entry.variants.choose(&mut rng)?;
+        if !self.sources.contains(handle) {
+            return None;
+        }
+
+        let pitch_variation = entry.pitch_variation.abs();
+        let playback_rate = if pitch_variation > 0. {
+            1. + rng.gen_range(-pitch_variation..=pitch_variation)
+        } else {
+            1.
+        } * entry.pitch;
+
+        Some(PickedSound {
+            handle: handle.clone(),
+            volume: entry.volume as f64,
+            playback_rate: playback_rate as f64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_sound_registry {
+    use std::{thread, time::Duration};
+
+    use bevy::{asset::AssetPlugin, prelude::*};
+
+    use super::{
+        SoundEvent, SoundRegistry, SoundRegistryAsset, SoundRegistryFile, SoundRegistryPlugin,
+    };
+
+    // Makes sure the registry shipped with the game parses
+    #[test]
+    fn registry_file_parses() {
+        let file = include_str!("../../assets/sounds/registry.sounds");
+        let registry: SoundRegistryFile = ron::from_str(file).unwrap();
+        assert!(!registry.sounds.is_empty());
+        assert!(registry
+            .sounds
+            .values()
+            .all(|sound| !sound.variants.is_empty()));
+    }
+
+    // The game only sees the registry if the asset server picks our loader for it
+    #[test]
+    fn registry_loads_through_the_asset_server() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_plugin(SoundRegistryPlugin);
+
+        // The loading happens on another thread
+        let loaded = |app: &App| {
+            let registry = app.world.resource::<SoundRegistry>();
+            let assets = app.world.resource::<Assets<SoundRegistryAsset>>();
+            assets.get(&registry.handle).map(|r| r.sounds.clone())
+        };
+        for _ in 0..500 {
+            app.update();
+            if loaded(&app).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let sounds = loaded(&app).expect("the sound registry didn't load");
+        assert!(!sounds[&SoundEvent::Error].variants.is_empty());
+    }
+}
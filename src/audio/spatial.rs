@@ -0,0 +1,93 @@
+use bevy::prelude::*;
+
+// The math behind our positional sounds: how loud a sound is at a distance and where it sits in the stereo field.
+// bevy_kira_audio has its own spatial audio, but we want control over the falloff (see explosion_on_death).
+// These are plain functions so they can be tested without an App.
+
+// How far a sound can be panned to one side. 1 would put sounds fully into one speaker,
+// which sounds odd for things that are right next to the camera
+pub const PAN_WIDTH: f32 = 0.8;
+
+// Used when there is no camera to listen from, roughly where the game camera starts
+pub fn default_listener() -> Transform {
+    Transform::from_xyz(0., 15., 0.)
+}
+
+// The volume of a sound at the given distance from the listener
+// Decreases faster than in the real world
+pub fn distance_volume(distance: f32, max_dist: Option<f32>) -> f32 {
+    if let Some(max_dist) = max_dist {
+        if distance > max_dist {
+            return 0.;
+        }
+    }
+
+    return 1. / (f32::log2(distance * 5.));
+}
+
+// Where the sound sits between the left (0) and right (1) speaker, 0.5 is centered.
+// This is the panning value bevy_kira_audio expects.
+// Only the horizontal direction matters, the top down camera looks almost straight down,
+// so the height of the camera would otherwise pull every sound towards the center
+pub fn stereo_panning(listener: &Transform, position: Vec3) -> f32 {
+    let right = listener.right();
+    let right = Vec3::new(right.x, 0., right.z).normalize_or_zero();
+    let offset = position - listener.translation;
+    let offset = Vec3::new(offset.x, 0., offset.z).normalize_or_zero();
+
+    // Sounds exactly below the camera, or a camera without a usable right vector, stay centered
+    let side = offset.dot(right);
+    return 0.5 + 0.5 * side * PAN_WIDTH;
+}
+
+#[cfg(test)]
+mod test_spatial_audio {
+    use bevy::prelude::*;
+
+    use super::{distance_volume, stereo_panning, PAN_WIDTH};
+
+    fn looking_down_north() -> Transform {
+        // Camera above the origin looking at the ground, with -z being "up" on screen
+        Transform::from_xyz(0., 20., 0.).looking_at(Vec3::ZERO, Vec3::NEG_Z)
+    }
+
+    #[test]
+    fn falloff_curve_is_unchanged() {
+        assert_eq!(distance_volume(10., None), 1. / f32::log2(50.));
+        assert!(distance_volume(5., None) > distance_volume(20., None));
+    }
+
+    #[test]
+    fn sounds_past_max_distance_are_silent() {
+        assert_eq!(distance_volume(46., Some(45.)), 0.);
+        assert!(distance_volume(44., Some(45.)) > 0.);
+    }
+
+    #[test]
+    fn sound_on_the_right_pans_right() {
+        let camera = looking_down_north();
+        let pan = stereo_panning(&camera, Vec3::new(10., 0., 0.));
+        assert!((pan - (0.5 + 0.5 * PAN_WIDTH)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sound_on_the_left_pans_left() {
+        let camera = looking_down_north();
+        let pan = stereo_panning(&camera, Vec3::new(-10., 0., 0.));
+        assert!((pan - (0.5 - 0.5 * PAN_WIDTH)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sound_in_front_or_below_is_centered() {
+        let camera = looking_down_north();
+        assert!((stereo_panning(&camera, Vec3::new(0., 0., -10.)) - 0.5).abs() < 1e-5);
+        assert!((stereo_panning(&camera, Vec3::ZERO) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn panning_follows_camera_rotation() {
+        // Rotate the camera by 180 degrees around the vertical axis, left and right swap
+        let camera = Transform::from_xyz(0., 20., 0.).looking_at(Vec3::ZERO, Vec3::Z);
+        assert!(stereo_panning(&camera, Vec3::new(10., 0., 0.)) < 0.5);
+    }
+}
@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use super::spatial::stereo_panning;
+
+// Limits how many copies of the same sound can play at once.
+// With dozens of turrets firing at a horde, playing every GunFireEvent makes hundreds of overlapping sounds that clip.
+// Instead each system collects all the sounds it wants to play this frame and asks the limiter which ones to actually play.
+// The sounds closest to the camera win, the rest are merged into the played ones, making them a bit louder.
+
+// The sounds that go through the limiter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LimitedSound {
+    MachineGun,
+    MachineGunMk2,
+    Laser,
+    AlienSpawn,
+    AlienDeath,
+    BuildingExplosion,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VoiceLimit {
+    // How many instances of the sound can play at the same time
+    pub max_voices: usize,
+    // The minimum time between two triggers of the sound
+    pub min_interval: Duration,
+    // We don't track the actual audio instances, so we assume every voice plays for this long
+    pub voice_length: Duration,
+}
+
+impl VoiceLimit {
+    pub fn new(max_voices: usize, min_interval_ms: u64, voice_length_ms: u64) -> Self {
+        Self {
+            max_voices,
+            min_interval: Duration::from_millis(min_interval_ms),
+            voice_length: Duration::from_millis(voice_length_ms),
+        }
+    }
+}
+
+// A sound some system wants to play this frame
+#[derive(Clone, Copy, Debug)]
+pub struct SoundRequest {
+    pub position: Vec3,
+    // The volume after distance attenuation
+    pub volume: f32,
+    // Distance to the camera, used as the priority
+    pub distance: f32,
+    // Stereo position, 0 is left and 1 is right
+    pub pan: f32,
+}
+
+impl SoundRequest {
+    pub fn new(position: Vec3, volume: f32, listener: &Transform) -> Self {
+        Self {
+            position,
+            volume,
+            distance: position.distance(listener.translation),
+            pan: stereo_panning(listener, position),
+        }
+    }
+}
+
+// Every dropped sound adds this much to the volume of the sound it's merged into, up to MAX_MERGE_GAIN
+const MERGE_GAIN: f32 = 0.15;
+const MAX_MERGE_GAIN: f32 = 2.;
+
+#[derive(Default, Debug)]
+struct VoiceState {
+    // When the currently playing voices started
+    started: Vec<Duration>,
+    last_trigger: Option<Duration>,
+}
+
+#[derive(Resource, Debug)]
+pub struct VoiceLimiter {
+    limits: HashMap<LimitedSound, VoiceLimit>,
+    state: HashMap<LimitedSound, VoiceState>,
+}
+
+impl Default for VoiceLimiter {
+    fn default() -> Self {
+        use LimitedSound::*;
+        let mut limits = HashMap::new();
+        limits.insert(MachineGun, VoiceLimit::new(6, 60, 400));
+        limits.insert(MachineGunMk2, VoiceLimit::new(4, 60, 400));
+        limits.insert(Laser, VoiceLimit::new(4, 80, 600));
+        limits.insert(AlienSpawn, VoiceLimit::new(4, 100, 800));
+        limits.insert(AlienDeath, VoiceLimit::new(6, 50, 600));
+        limits.insert(BuildingExplosion, VoiceLimit::new(3, 150, 1500));
+
+        Self {
+            limits,
+            state: HashMap::new(),
+        }
+    }
+}
+
+impl VoiceLimiter {
+    // Returns the requests which should actually be played, with their volumes adjusted for the merged ones.
+    // `now` is any monotonic time, we use the app's elapsed time
+    pub fn select(
+        &mut self,
+        sound: LimitedSound,
+        now: Duration,
+        mut requests: Vec<SoundRequest>,
+    ) -> Vec<SoundRequest> {
+        if requests.is_empty() {
+            return requests;
+        }
+        let limit = match self.limits.get(&sound) {
+            Some(limit) => *limit,
+            // Sounds without a limit are played as is
+            None => return requests,
+        };
+        let state = self.state.entry(sound).or_default();
+
+        // Forget the voices that finished playing
+        state
+            .started
+            .retain(|started| *started + limit.voice_length > now);
+
+        if let Some(last) = state.last_trigger {
+            if now < last + limit.min_interval {
+                return Vec::new();
+            }
+        }
+
+        let free_voices = limit.max_voices.saturating_sub(state.started.len());
+        if free_voices == 0 {
+            return Vec::new();
+        }
+
+        // The closest sounds have priority
+        requests.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        let merged = requests.len().saturating_sub(free_voices);
+        requests.truncate(free_voices);
+
+        // Instead of playing the dropped sounds, make the closest one louder
+        let gain = (1. + MERGE_GAIN * merged as f32).min(MAX_MERGE_GAIN);
+        requests[0].volume *= gain;
+
+        for _ in requests.iter() {
+            state.started.push(now);
+        }
+        state.last_trigger = Some(now);
+
+        return requests;
+    }
+}
+
+#[cfg(test)]
+mod test_voice_limiter {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use super::{LimitedSound, SoundRequest, VoiceLimiter, MAX_MERGE_GAIN, MERGE_GAIN};
+
+    fn request(distance: f32) -> SoundRequest {
+        SoundRequest {
+            position: Vec3::new(distance, 0., 0.),
+            volume: 1.,
+            distance,
+            pan: 0.5,
+        }
+    }
+
+    fn requests(count: usize) -> Vec<SoundRequest> {
+        (0..count).map(|i| request(i as f32)).collect()
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn plays_at_most_max_voices() {
+        let mut limiter = VoiceLimiter::default();
+        // The machine gun has 6 voices that last 400ms
+        let played = limiter.select(LimitedSound::MachineGun, ms(1000), requests(10));
+        assert_eq!(played.len(), 6);
+        // All voices are still playing
+        let played = limiter.select(LimitedSound::MachineGun, ms(1100), requests(10));
+        assert!(played.is_empty());
+        // And free again once they're done
+        let played = limiter.select(LimitedSound::MachineGun, ms(1400), requests(10));
+        assert_eq!(played.len(), 6);
+    }
+
+    #[test]
+    fn waits_for_min_interval() {
+        let mut limiter = VoiceLimiter::default();
+        // The machine gun can trigger every 60ms
+        assert_eq!(
+            limiter
+                .select(LimitedSound::MachineGun, ms(1000), requests(1))
+                .len(),
+            1
+        );
+        assert!(limiter
+            .select(LimitedSound::MachineGun, ms(1030), requests(1))
+            .is_empty());
+        assert_eq!(
+            limiter
+                .select(LimitedSound::MachineGun, ms(1060), requests(1))
+                .len(),
+            1
+        );
+        // The sounds have their own intervals
+        assert_eq!(
+            limiter
+                .select(LimitedSound::Laser, ms(1060), requests(1))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn nearest_sounds_win() {
+        let mut limiter = VoiceLimiter::default();
+        let mut far_first = requests(10);
+        far_first.reverse();
+        let played = limiter.select(LimitedSound::MachineGun, ms(1000), far_first);
+        let distances: Vec<f32> = played.iter().map(|r| r.distance).collect();
+        assert_eq!(distances, vec![0., 1., 2., 3., 4., 5.]);
+    }
+
+    #[test]
+    fn dropped_sounds_make_the_nearest_louder() {
+        let mut limiter = VoiceLimiter::default();
+        let played = limiter.select(LimitedSound::MachineGun, ms(1000), requests(10));
+        // 4 of the 10 are merged into the nearest one
+        assert!((played[0].volume - (1. + 4. * MERGE_GAIN)).abs() < 1e-5);
+        assert!(played[1..].iter().all(|r| r.volume == 1.));
+
+        // Nothing dropped, nothing louder
+        let played = limiter.select(LimitedSound::Laser, ms(1000), requests(2));
+        assert!(played.iter().all(|r| r.volume == 1.));
+
+        // A huge horde doesn't get arbitrarily loud
+        let played = limiter.select(LimitedSound::AlienDeath, ms(1000), requests(200));
+        assert_eq!(played[0].volume, MAX_MERGE_GAIN);
+    }
+}
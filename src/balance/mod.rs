@@ -0,0 +1,2 @@
+pub mod simulation;
+pub mod sweep;
\ No newline at end of file
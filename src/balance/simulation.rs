@@ -0,0 +1,259 @@
+use std::time::Duration;
+
+use bevy::{
+    hierarchy::HierarchyPlugin, prelude::*, scene::ScenePlugin, time::TimePlugin,
+    transform::TransformPlugin, utils::Instant,
+};
+use bevy_rapier3d::prelude::*;
+use bevy_tweening::TweenCompleted;
+
+use crate::{
+    aliens::{
+        alien::{AlienPlugin, SpawnRateMultiplier},
+        alien_kinds::AlienTemplates,
+    },
+    bot::ai_player::{AiPlayer, AiPlayerPlugin},
+    buildings::{
+        building_bundles::{BuildingBundle, BuildingTemplates, BuildingTemplatesPlugin},
+        defensive_buildings::DefensiveBuildingPlugin,
+        grid::Grid,
+        player_commands::PlayerCommandsPlugin,
+        resources::{ResourcePlugin, ResourceState},
+    },
+    effects::muzzleflash::GunFireEvent,
+    game_timer::{
+        game_timer::{GameTimerPlugin, InGameTime},
+        gameplay_schedule::{GameplayPhysicsPlugin, GameplaySchedulePlugin, GAMEPLAY_STEP},
+    },
+    health::health::HealthPlugin,
+    main_base::main_base::MainBasePlugin,
+    map::map::MapPlugin,
+    replay::playback::ReplayPlayback,
+    rules::game_rules::{GameRules, GameRulesPlugin},
+    spatial::spatial_index::SpatialIndexPlugin,
+    stats::game_stats::{GameStats, ResourceTally, StatsPlugin},
+    ui::error_info::ErrorEvent,
+    AppState,
+};
+
+// Plays whole games headless, so the balance numbers can be swept without playing by hand.
+// It's the game without the window: the same plugins as main.rs other than the rendering, audio, effects and UI,
+// with the AI player at the controls. The in game time moves on one gameplay tick per frame, as fast as the computer can go.
+// Nothing is drawn, so the models never load and the aliens walk into the buildings' colliders like they do in the game.
+
+// Multipliers on top of the game's numbers and the difficulty
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct SimParams {
+    pub alien_hp: f32,
+    pub spawn_rate: f32,
+    pub turret_damage: f32,
+    pub turret_cost: f32,
+}
+
+impl Default for SimParams {
+    fn default() -> Self {
+        SimParams {
+            alien_hp: 1.,
+            spawn_rate: 1.,
+            turret_damage: 1.,
+            turret_cost: 1.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimResult {
+    // In game seconds until the base fell, the game was won or the time ran out
+    pub survived: f32,
+    pub won: bool,
+    pub peak_aliens: u32,
+    pub kills: u32,
+    pub buildings: u32,
+    // What was left in the bank
+    pub resources: ResourceTally,
+}
+
+// Scales the templates once they're registered, before the game starts
+fn apply_params(
+    params: Res<SimParams>,
+    mut aliens: ResMut<AlienTemplates>,
+    mut buildings: ResMut<BuildingTemplates>,
+    mut spawn_rate: ResMut<SpawnRateMultiplier>,
+) {
+    for t in aliens.templates.iter_mut() {
+        t.health = ((t.health as f32 * params.alien_hp).round() as i32).max(1);
+    }
+    for b in buildings.templates.iter_mut() {
+        if let BuildingBundle::DEFENSIVE(d) = &mut b.bundle {
+            d.damage_dealing.damage =
+                (d.damage_dealing.damage as f32 * params.turret_damage).round() as i32;
+            b.cost = b.cost.scale(params.turret_cost);
+        }
+    }
+    spawn_rate.0 = params.spawn_rate;
+}
+
+// The game as it's set up in main.rs, headless and with the AI player turned on
+pub fn headless_game(rules: GameRules, params: SimParams) -> App {
+    let mut app = App::new();
+    // The time is moved on by hand, a tick every frame
+    app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>())
+        .insert_resource(Time::default())
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(ScenePlugin)
+        // What the map and the aliens' models would be drawn with
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .add_asset::<AnimationClip>()
+        .add_state(AppState::InGame)
+        .add_plugin(GameplaySchedulePlugin)
+        .add_plugin(GameplayPhysicsPlugin)
+        .add_event::<CollisionEvent>()
+        .insert_resource(Grid::new())
+        .add_plugin(BuildingTemplatesPlugin)
+        .add_plugin(DefensiveBuildingPlugin)
+        .add_plugin(PlayerCommandsPlugin)
+        .add_plugin(AiPlayerPlugin)
+        .add_plugin(AlienPlugin)
+        .add_plugin(SpatialIndexPlugin)
+        .add_plugin(ResourcePlugin)
+        .add_plugin(GameTimerPlugin)
+        .add_plugin(GameRulesPlugin)
+        .add_plugin(StatsPlugin)
+        .add_plugin(HealthPlugin)
+        .add_plugin(MainBasePlugin)
+        .add_plugin(MapPlugin)
+        // Sent by the gameplay for the effects and the UI, which aren't here
+        .add_event::<GunFireEvent>()
+        .add_event::<TweenCompleted>()
+        .add_event::<ErrorEvent>()
+        .init_resource::<ReplayPlayback>()
+        .insert_resource(rules)
+        .insert_resource(params)
+        .add_startup_system_to_stage(StartupStage::PostStartup, apply_params);
+    app.world.resource_mut::<AiPlayer>().enabled = true;
+    return app;
+}
+
+// Plays one game until the main base falls, the game is won or `max_time` runs out
+pub fn simulate(rules: &GameRules, params: &SimParams, seed: u64, max_time: Duration) -> SimResult {
+    let mut app = headless_game(GameRules { seed, ..*rules }, *params);
+
+    let mut now = Instant::now();
+    app.world.resource_mut::<Time>().update_with_instant(now);
+    loop {
+        now += GAMEPLAY_STEP;
+        app.world.resource_mut::<Time>().update_with_instant(now);
+        app.update();
+
+        let elapsed = app.world.resource::<InGameTime>().timer.elapsed();
+        if app.world.resource::<State<AppState>>().current() != &AppState::InGame
+            || elapsed >= max_time
+        {
+            break;
+        }
+    }
+
+    let stats = app.world.resource::<GameStats>();
+    let mut resources = ResourceTally::default();
+    resources.add_set(&app.world.resource::<ResourceState>().resources);
+    return SimResult {
+        survived: app.world.resource::<InGameTime>().timer.elapsed_secs(),
+        won: app.world.resource::<State<AppState>>().current() == &AppState::Victory,
+        peak_aliens: stats.peak_aliens,
+        kills: stats.kills,
+        buildings: stats.buildings_built,
+        resources,
+    };
+}
+
+#[cfg(test)]
+mod test_simulation {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use crate::{
+        aliens::alien_kinds::AlienTemplates,
+        buildings::building_bundles::{Building, BuildingBundle, BuildingTemplates},
+        rules::game_rules::{Difficulty, GameMode, GameRules},
+    };
+
+    use super::{headless_game, simulate, SimParams};
+
+    fn endless() -> GameRules {
+        GameRules {
+            mode: GameMode::Endless,
+            difficulty: Difficulty::Nightmare,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let max = Duration::from_secs(3 * 60);
+        let a = simulate(&endless(), &SimParams::default(), 7, max);
+        let b = simulate(&endless(), &SimParams::default(), 7, max);
+        assert_eq!(a, b);
+        assert!(a.peak_aliens > 0);
+        // The AI player built something
+        assert!(a.buildings > 0);
+    }
+
+    #[test]
+    fn params_scale_the_templates() {
+        let params = SimParams {
+            alien_hp: 2.,
+            turret_damage: 0.5,
+            turret_cost: 2.,
+            ..Default::default()
+        };
+        // The first frame registers the templates
+        let mut normal = headless_game(endless(), SimParams::default());
+        let mut scaled = headless_game(endless(), params);
+        normal.update();
+        scaled.update();
+
+        let health = |app: &App| app.world.resource::<AlienTemplates>().templates[0].health;
+        assert_eq!(health(&scaled), 2 * health(&normal));
+
+        let gun = |app: &App| {
+            let templates = app.world.resource::<BuildingTemplates>();
+            templates.get("Machine gun mk1").unwrap().clone()
+        };
+        let damage = |b: &Building| match &b.bundle {
+            BuildingBundle::DEFENSIVE(d) => d.damage_dealing.damage,
+            BuildingBundle::GENERATOR(_) => 0,
+        };
+        assert_eq!(damage(&gun(&scaled)) * 2, damage(&gun(&normal)));
+        assert_eq!(gun(&scaled).cost, gun(&normal).cost.scale(2.));
+
+        // Only the turrets cost more
+        let mine = |app: &App| {
+            let templates = app.world.resource::<BuildingTemplates>();
+            templates.get("Mine tier 1").unwrap().cost.clone()
+        };
+        assert_eq!(mine(&scaled), mine(&normal));
+    }
+
+    #[test]
+    fn stronger_turrets_last_longer() {
+        let max = Duration::from_secs(6 * 60);
+        let weak = SimParams {
+            turret_damage: 0.05,
+            ..Default::default()
+        };
+        let strong = SimParams {
+            turret_damage: 5.,
+            ..Default::default()
+        };
+        let weak = simulate(&endless(), &weak, 3, max);
+        let strong = simulate(&endless(), &strong, 3, max);
+        assert!(strong.survived >= weak.survived);
+        assert!(strong.kills > weak.kills);
+        // Endless games can't be won
+        assert!(!strong.won);
+    }
+}
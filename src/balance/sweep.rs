@@ -0,0 +1,227 @@
+use std::{
+    fs,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use itertools::iproduct;
+use serde::Deserialize;
+
+use crate::rules::game_rules::{Difficulty, GameMode, GameRules};
+
+use super::simulation::{simulate, SimParams, SimResult};
+
+// A grid of balance parameters to try. Every combination of the values is played `runs` times, each run with its own seed.
+// Loaded from a RON file, see balance_sweep.ron for an example. Anything missing from the file keeps its default
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SweepConfig {
+    pub mode: GameMode,
+    pub difficulty: Difficulty,
+    pub runs: u32,
+    // The seed of the first run, the rest count up from it
+    pub seed: u64,
+    // Endless games and standard games that aren't won yet stop here
+    pub max_minutes: f32,
+    // 0 uses every core
+    pub threads: usize,
+    // Multipliers, see SimParams
+    pub alien_hp: Vec<f32>,
+    pub spawn_rate: Vec<f32>,
+    pub turret_damage: Vec<f32>,
+    pub turret_cost: Vec<f32>,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        SweepConfig {
+            mode: GameMode::Standard,
+            difficulty: Difficulty::Normal,
+            runs: 4,
+            seed: 0,
+            max_minutes: 30.,
+            threads: 0,
+            alien_hp: vec![0.75, 1., 1.25],
+            spawn_rate: vec![0.75, 1., 1.25],
+            turret_damage: vec![0.75, 1., 1.25],
+            turret_cost: vec![1.],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepJob {
+    pub run: usize,
+    pub params: SimParams,
+    pub seed: u64,
+}
+
+impl SweepConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    pub fn rules(&self) -> GameRules {
+        GameRules {
+            mode: self.mode,
+            difficulty: self.difficulty,
+            ..Default::default()
+        }
+    }
+
+    // Every combination of the parameters, `runs` times over
+    pub fn jobs(&self) -> Vec<SweepJob> {
+        let combinations = iproduct!(
+            self.alien_hp.iter(),
+            self.spawn_rate.iter(),
+            self.turret_damage.iter(),
+            self.turret_cost.iter()
+        );
+        let mut jobs = Vec::new();
+        for (alien_hp, spawn_rate, turret_damage, turret_cost) in combinations {
+            let params = SimParams {
+                alien_hp: *alien_hp,
+                spawn_rate: *spawn_rate,
+                turret_damage: *turret_damage,
+                turret_cost: *turret_cost,
+            };
+            for i in 0..self.runs {
+                jobs.push(SweepJob {
+                    run: jobs.len(),
+                    params,
+                    seed: self.seed.wrapping_add(i as u64),
+                });
+            }
+        }
+        return jobs;
+    }
+}
+
+// Plays all the jobs, spread over the threads. The results come back in the order of the jobs
+pub fn run_sweep(config: &SweepConfig, jobs: &[SweepJob]) -> Vec<SimResult> {
+    let rules = config.rules();
+    let max_time = Duration::from_secs_f32(config.max_minutes.max(0.) * 60.);
+    let threads = match config.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+
+    // Every thread takes the next job that's left until there are none
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, SimResult)> = thread::scope(|s| {
+        let workers = (0..threads.min(jobs.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let job = match jobs.get(i) {
+                            Some(job) => job,
+                            None => return done,
+                        };
+                        done.push((i, simulate(&rules, &job.params, job.seed, max_time)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("A simulation thread panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    return results.into_iter().map(|(_, r)| r).collect();
+}
+
+pub const CSV_HEADER: &str = "run,seed,alien_hp,spawn_rate,turret_damage,turret_cost,survived_secs,won,peak_aliens,kills,buildings,ore,gas,crystal";
+
+pub fn csv_row(job: &SweepJob, result: &SimResult) -> String {
+    format!(
+        "{},{},{},{},{},{},{:.1},{},{},{},{},{},{},{}",
+        job.run,
+        job.seed,
+        job.params.alien_hp,
+        job.params.spawn_rate,
+        job.params.turret_damage,
+        job.params.turret_cost,
+        result.survived,
+        result.won,
+        result.peak_aliens,
+        result.kills,
+        result.buildings,
+        result.resources.ore,
+        result.resources.gas,
+        result.resources.crystal,
+    )
+}
+
+#[cfg(test)]
+mod test_sweep {
+    use crate::{balance::simulation::simulate, rules::game_rules::GameMode};
+
+    use super::{csv_row, run_sweep, SweepConfig, CSV_HEADER};
+
+    fn small() -> SweepConfig {
+        SweepConfig {
+            mode: GameMode::Endless,
+            runs: 2,
+            max_minutes: 2.,
+            threads: 3,
+            alien_hp: vec![1., 2.],
+            spawn_rate: vec![1.],
+            turret_damage: vec![0.5, 1., 2.],
+            turret_cost: vec![1.],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn every_combination_is_run() {
+        let jobs = small().jobs();
+        assert_eq!(jobs.len(), 2 * 3 * 2);
+        assert_eq!(jobs[0].seed, 0);
+        assert_eq!(jobs[1].seed, 1);
+        assert_eq!(jobs[2].seed, 0);
+        assert!(jobs.iter().enumerate().all(|(i, j)| j.run == i));
+    }
+
+    #[test]
+    fn threads_give_the_same_results_in_order() {
+        // Every game is played twice here, keep them short
+        let config = SweepConfig {
+            max_minutes: 0.5,
+            alien_hp: vec![1.],
+            ..small()
+        };
+        let jobs = config.jobs();
+        let results = run_sweep(&config, &jobs);
+        assert_eq!(results.len(), jobs.len());
+        let rules = config.rules();
+        let max = std::time::Duration::from_secs(30);
+        for (job, result) in jobs.iter().zip(results.iter()) {
+            assert_eq!(*result, simulate(&rules, &job.params, job.seed, max));
+        }
+    }
+
+    #[test]
+    fn rows_match_the_header() {
+        let config = small();
+        let jobs = config.jobs();
+        let results = run_sweep(&config, &jobs[..1]);
+        let row = csv_row(&jobs[0], &results[0]);
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+        assert!(row.starts_with("0,0,1,1,0.5,1,"));
+    }
+
+    #[test]
+    fn partial_config_files() {
+        let config: SweepConfig = ron::from_str("(runs: 10, turret_cost: [0.5, 1.0])").unwrap();
+        assert_eq!(config.runs, 10);
+        assert_eq!(config.turret_cost, vec![0.5, 1.]);
+        assert_eq!(config.alien_hp, SweepConfig::default().alien_hp);
+    }
+}
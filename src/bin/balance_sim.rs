@@ -0,0 +1,58 @@
+// The balance simulator: plays headless games over a grid of balance parameters with the AI player
+// and writes a CSV row per game, see balance::simulation for how the games are run.
+//
+//     cargo run --release --bin balance_sim -- [config.ron] [--out results.csv]
+//
+// Without a config the default grid of SweepConfig is used, without --out the CSV goes to stdout.
+use std::{fs, path::PathBuf, process, time::Instant};
+
+use bevy_game::balance::sweep::{csv_row, run_sweep, SweepConfig, CSV_HEADER};
+
+fn main() {
+    let mut config_path: Option<PathBuf> = None;
+    let mut out: Option<PathBuf> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--out" => out = args.next().map(PathBuf::from),
+            "-h" | "--help" => {
+                println!("Usage: balance_sim [config.ron] [--out results.csv]");
+                return;
+            }
+            _ => config_path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let config = match &config_path {
+        Some(path) => SweepConfig::load(path).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", path.display(), e);
+            process::exit(1);
+        }),
+        None => SweepConfig::default(),
+    };
+
+    // The progress goes to stderr so stdout is only the CSV
+    let jobs = config.jobs();
+    eprintln!("Playing {} games...", jobs.len());
+    let start = Instant::now();
+    let results = run_sweep(&config, &jobs);
+    eprintln!("Done in {:.1}s", start.elapsed().as_secs_f32());
+
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for (job, result) in jobs.iter().zip(results.iter()) {
+        csv.push_str(&csv_row(job, result));
+        csv.push('\n');
+    }
+
+    match out {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, csv) {
+                eprintln!("Could not write {}: {}", path.display(), e);
+                process::exit(1);
+            }
+            eprintln!("Saved to {}", path.display());
+        }
+        None => print!("{}", csv),
+    }
+}
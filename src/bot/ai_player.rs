@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::{
+    aliens::alien::AlienSpawnAngle,
+    buildings::{
+        building_bundles::{BuildingBundle, BuildingInfoComponent, BuildingTemplates},
+        grid::Grid,
+        player_commands::{CommandEvent, CommandResult, CommandSource},
+        resources::ResourceState,
+    },
+    game_timer::{
+        game_timer::InGameTime,
+        gameplay_schedule::{GameplayClock, GameplayStage, GameplayTick},
+    },
+    main_base::main_base::MainBaseComponent,
+    AppState,
+};
+
+use super::strategy::{
+    BalancedStrategy, BotAction, BotStrategy, BotView, BuildOption, BuildingRole, OwnedBuilding,
+};
+
+// The AI player, for demos and for testing whole games without anyone at the controls.
+// It's turned on from the game setup screen and sends the same PlayerCommands as the mouse.
+// What it does is up to its BotStrategy.
+
+pub struct AiPlayerPlugin;
+
+impl Plugin for AiPlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AiPlayer>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(reset_ai_player))
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame).with_system(log_ai_player_errors),
+            )
+            // Its commands are applied in the same tick
+            .add_system_to_stage(
+                GameplayStage,
+                ai_player
+                    .after(GameplayTick::Start)
+                    .before(GameplayTick::Commands),
+            );
+    }
+}
+
+// How often the bot gets to act, about as fast as a player clicks
+const THINK_INTERVAL: Duration = Duration::from_millis(1000);
+
+#[derive(Resource)]
+pub struct AiPlayer {
+    pub enabled: bool,
+    strategy: Box<dyn BotStrategy>,
+    timer: Timer,
+}
+
+impl Default for AiPlayer {
+    fn default() -> Self {
+        AiPlayer {
+            enabled: false,
+            strategy: Box::new(BalancedStrategy::default()),
+            timer: Timer::new(THINK_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
+
+impl AiPlayer {
+    pub fn set_strategy(&mut self, strategy: impl BotStrategy) {
+        self.strategy = Box::new(strategy);
+    }
+
+    pub fn strategy_name(&self) -> &'static str {
+        self.strategy.name()
+    }
+}
+
+// Everything from the templates that shows up in the build menu
+pub fn build_options(templates: &BuildingTemplates) -> Vec<BuildOption> {
+    templates
+        .templates
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.show_in_menu)
+        .map(|(i, b)| BuildOption {
+            template: i,
+            name: b.building_info.name,
+            role: match &b.bundle {
+                BuildingBundle::DEFENSIVE(d) => BuildingRole::Turret {
+                    range: d.target_selecting.range,
+                },
+                BuildingBundle::GENERATOR(g) => BuildingRole::Generator(g.generator.resource_type),
+            },
+            cost: b.cost.clone(),
+        })
+        .collect()
+}
+
+pub fn reset_ai_player(mut bot: ResMut<AiPlayer>) {
+    bot.timer.reset();
+    bot.strategy.reset();
+}
+
+pub fn ai_player(
+    mut bot: ResMut<AiPlayer>,
+    time: Res<GameplayClock>,
+    game_time: Res<InGameTime>,
+    templates: Res<BuildingTemplates>,
+    angle: Res<AlienSpawnAngle>,
+    grid: Res<Grid>,
+    resources: Res<ResourceState>,
+    main_base: Query<&Transform, With<MainBaseComponent>>,
+    buildings: Query<(Entity, &BuildingInfoComponent, &Transform), Without<MainBaseComponent>>,
+    mut commands: EventWriter<CommandEvent>,
+) {
+    if !bot.enabled {
+        return;
+    }
+    // Everything is built around it
+    let main_base = match main_base.get_single() {
+        Ok(t) => t.translation,
+        Err(_) => return,
+    };
+    bot.timer.tick(time.delta());
+    if !bot.timer.just_finished() {
+        return;
+    }
+
+    let options = build_options(&templates);
+    // Buildings that are being demolished or destroyed are already off the grid
+    let owned = buildings
+        .iter()
+        .filter(|(e, _, t)| grid.get_entity(t.translation) == Some(e))
+        .map(|(_, info, t)| OwnedBuilding {
+            name: info.name,
+            square: Grid::get_square_index(t.translation),
+        })
+        .collect::<Vec<_>>();
+    let view = BotView {
+        elapsed: game_time.timer.elapsed(),
+        resources: &resources.resources,
+        grid: &grid,
+        options: &options,
+        buildings: &owned,
+        main_base,
+        spawn_angle: angle.angle(),
+    };
+
+    if let Some(action) = bot.strategy.decide(&view) {
+        commands.send(CommandEvent {
+            command: action.command(),
+            source: CommandSource::AiPlayer,
+        });
+    }
+}
+
+// The errors are the bot's own mistakes, the player doesn't need to see them
+pub fn log_ai_player_errors(bot: Res<AiPlayer>, mut results: EventReader<CommandResult>) {
+    for r in results.iter() {
+        if let (CommandSource::AiPlayer, Err(e)) = (r.source, r.result) {
+            warn!(
+                "The {} AI player could not {:?}: {}",
+                bot.strategy_name(),
+                r.command,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod ai_player;
+pub mod strategy;
\ No newline at end of file
@@ -0,0 +1,399 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::buildings::{
+    grid::{Grid, SQUARE_SIZE},
+    player_commands::PlayerCommand,
+    resources::{ResourceSet, ResourceType},
+};
+
+// The decisions of the AI player, see ai_player for the part that acts on them.
+// A strategy only sees a snapshot of the game and returns what it wants to do next, so it can be swapped out and tested without a world.
+
+// A building from BuildingTemplates the bot can build
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildOption {
+    // The index in BuildingTemplates
+    pub template: usize,
+    pub name: &'static str,
+    pub role: BuildingRole,
+    pub cost: ResourceSet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildingRole {
+    Turret { range: f32 },
+    Generator(ResourceType),
+}
+
+// One of the bot's buildings, other than the main base
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OwnedBuilding {
+    pub name: &'static str,
+    pub square: (i8, i8),
+}
+
+pub struct BotView<'a> {
+    pub elapsed: Duration,
+    pub resources: &'a ResourceSet,
+    pub grid: &'a Grid,
+    pub options: &'a [BuildOption],
+    pub buildings: &'a [OwnedBuilding],
+    // Where the middle of the main base is
+    pub main_base: Vec3,
+    // Where the aliens are coming from, in radians around the base. See AlienSpawnAngle
+    pub spawn_angle: f32,
+}
+
+impl<'a> BotView<'a> {
+    pub fn option(&self, name: &str) -> Option<&'a BuildOption> {
+        self.options.iter().find(|o| o.name == name)
+    }
+
+    pub fn can_afford(&self, option: &BuildOption) -> bool {
+        option.cost <= *self.resources
+    }
+
+    fn owned_roles(&self) -> impl Iterator<Item = BuildingRole> + '_ {
+        self.buildings
+            .iter()
+            .filter_map(|b| self.option(b.name))
+            .map(|o| o.role)
+    }
+}
+
+// The same actions the player can take with the mouse in building_system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotAction {
+    Build { template: usize, square: (i8, i8) },
+    Demolish { square: (i8, i8) },
+}
+
+impl BotAction {
+    pub fn command(self) -> PlayerCommand {
+        match self {
+            BotAction::Build { template, square } => PlayerCommand::Build { template, square },
+            BotAction::Demolish { square } => PlayerCommand::Demolish { square },
+        }
+    }
+}
+
+pub trait BotStrategy: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    // Called every time the bot gets to act. None waits, e.g. to save up resources
+    fn decide(&mut self, view: &BotView) -> Option<BotAction>;
+
+    // Called at the start of every game
+    fn reset(&mut self) {}
+}
+
+fn total(set: &ResourceSet) -> u32 {
+    ResourceType::ALL
+        .iter()
+        .map(|r| set.get(*r).unwrap_or(0) as u32)
+        .sum()
+}
+
+// Builds a couple of generators first, then keeps a fixed number of turrets per generator.
+// Turrets go on the side of the base the aliens are coming from, generators behind it.
+// Once the base is big enough the cheaper generators get demolished to make room for better ones
+#[derive(Debug, Clone)]
+pub struct BalancedStrategy {
+    pub opening_generators: usize,
+    pub turrets_per_generator: f32,
+    // How far from the main base it builds, in squares
+    pub base_radius: i8,
+    // Generators are only upgraded once the base has this many buildings
+    pub upgrade_after: usize,
+}
+
+impl Default for BalancedStrategy {
+    fn default() -> Self {
+        BalancedStrategy {
+            opening_generators: 2,
+            turrets_per_generator: 2.,
+            base_radius: 8,
+            upgrade_after: 12,
+        }
+    }
+}
+
+impl BalancedStrategy {
+    fn wants_turret(&self, view: &BotView) -> bool {
+        let (mut turrets, mut generators) = (0, 0);
+        for role in view.owned_roles() {
+            match role {
+                BuildingRole::Turret { .. } => turrets += 1,
+                BuildingRole::Generator(_) => generators += 1,
+            }
+        }
+        if generators < self.opening_generators {
+            return false;
+        }
+        return (turrets as f32) < generators as f32 * self.turrets_per_generator;
+    }
+
+    // The most expensive turret it can afford
+    fn pick_turret<'a>(&self, view: &BotView<'a>) -> Option<&'a BuildOption> {
+        view.options
+            .iter()
+            .filter(|o| matches!(o.role, BuildingRole::Turret { .. }) && view.can_afford(o))
+            .max_by_key(|o| total(&o.cost))
+    }
+
+    // A generator of the resource it has the fewest generators of, the most expensive one it can afford
+    fn pick_generator<'a>(&self, view: &BotView<'a>) -> Option<&'a BuildOption> {
+        let owned = |r: ResourceType| {
+            view.owned_roles()
+                .filter(|role| *role == BuildingRole::Generator(r))
+                .count()
+        };
+        view.options
+            .iter()
+            .filter(|o| view.can_afford(o))
+            .filter_map(|o| match o.role {
+                BuildingRole::Generator(r) => Some((o, owned(r))),
+                _ => None,
+            })
+            .min_by_key(|(o, count)| (*count, u32::MAX - total(&o.cost)))
+            .map(|(o, _)| o)
+    }
+
+    // Demolishes a generator when a better one for the same resource is affordable twice over
+    fn upgrade(&self, view: &BotView) -> Option<BotAction> {
+        if view.buildings.len() < self.upgrade_after {
+            return None;
+        }
+        for building in view.buildings {
+            let current = match view.option(building.name) {
+                Some(o) => o,
+                None => continue,
+            };
+            let better = view.options.iter().any(|o| {
+                o.role == current.role
+                    && matches!(o.role, BuildingRole::Generator(_))
+                    && total(&o.cost) > total(&current.cost)
+                    && {
+                        let mut twice = o.cost.clone();
+                        twice.add_set(&o.cost);
+                        twice <= *view.resources
+                    }
+            });
+            if better {
+                return Some(BotAction::Demolish {
+                    square: building.square,
+                });
+            }
+        }
+        return None;
+    }
+}
+
+// The free square for the role with the best score, within `radius` squares of the main base
+pub fn best_square(view: &BotView, role: &BuildingRole, radius: i8) -> Option<(i8, i8)> {
+    let towards_aliens = Vec2::new(view.spawn_angle.cos(), view.spawn_angle.sin());
+    // In squares, the main base covers the four squares around its middle
+    let base = Vec2::new(view.main_base.x, view.main_base.z) / SQUARE_SIZE;
+    let (base_x, base_y) = Grid::get_square_index(view.main_base);
+    let mut best = None;
+    let mut best_score = f32::MIN;
+    for x in base_x.saturating_sub(radius)..=base_x.saturating_add(radius) {
+        for y in base_y.saturating_sub(radius)..=base_y.saturating_add(radius) {
+            if view.grid.blocked_squares.contains_key(&(x, y)) {
+                continue;
+            }
+            let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - base;
+            let distance = offset.length();
+            let facing = offset.normalize_or_zero().dot(towards_aliens);
+            // Towards or away from the aliens, and as close to the main base as possible
+            let score = match role {
+                BuildingRole::Turret { .. } => facing * 2.,
+                BuildingRole::Generator(_) => -facing * 2.,
+            } - distance / radius as f32;
+            if score > best_score {
+                best_score = score;
+                best = Some((x, y));
+            }
+        }
+    }
+    return best;
+}
+
+impl BotStrategy for BalancedStrategy {
+    fn name(&self) -> &'static str {
+        "Balanced"
+    }
+
+    fn decide(&mut self, view: &BotView) -> Option<BotAction> {
+        if let Some(action) = self.upgrade(view) {
+            return Some(action);
+        }
+        let option = if self.wants_turret(view) {
+            self.pick_turret(view)
+        } else {
+            self.pick_generator(view)
+        }?;
+        let square = best_square(view, &option.role, self.base_radius)?;
+        Some(BotAction::Build {
+            template: option.template,
+            square,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_strategy {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use crate::buildings::{
+        grid::Grid,
+        resources::{ResourceSet, ResourceType},
+    };
+
+    use super::{
+        BalancedStrategy, BotAction, BotStrategy, BotView, BuildOption, BuildingRole, OwnedBuilding,
+    };
+
+    fn options() -> Vec<BuildOption> {
+        let option = |template, name, role, cost| BuildOption {
+            template,
+            name,
+            role,
+            cost,
+        };
+        vec![
+            option(
+                0,
+                "Gun",
+                BuildingRole::Turret { range: 8. },
+                ResourceSet::new(50, 0, 0),
+            ),
+            option(
+                1,
+                "Mine",
+                BuildingRole::Generator(ResourceType::Ore),
+                ResourceSet::new(25, 0, 0),
+            ),
+            option(
+                2,
+                "Big mine",
+                BuildingRole::Generator(ResourceType::Ore),
+                ResourceSet::new(100, 0, 0),
+            ),
+        ]
+    }
+
+    // The grid with the main base on the four squares around the point
+    fn main_base(at: Vec3) -> Grid {
+        let (x0, y0) = Grid::get_square_index(at);
+        let mut grid = Grid::new();
+        for x in x0 - 1..=x0 {
+            for y in y0 - 1..=y0 {
+                grid.block_square((x, y), Entity::from_raw(0));
+            }
+        }
+        grid
+    }
+
+    fn decide_at(
+        base: Vec3,
+        resources: ResourceSet,
+        buildings: &[OwnedBuilding],
+    ) -> Option<BotAction> {
+        let grid = main_base(base);
+        let options = options();
+        let view = BotView {
+            elapsed: Duration::ZERO,
+            resources: &resources,
+            grid: &grid,
+            options: &options,
+            buildings,
+            main_base: base,
+            // The aliens come from +x
+            spawn_angle: 0.,
+        };
+        BalancedStrategy::default().decide(&view)
+    }
+
+    fn decide(resources: ResourceSet, buildings: &[OwnedBuilding]) -> Option<BotAction> {
+        decide_at(Vec3::ZERO, resources, buildings)
+    }
+
+    fn owned(name: &'static str, n: usize) -> Vec<OwnedBuilding> {
+        (0..n)
+            .map(|i| OwnedBuilding {
+                name,
+                square: (5, i as i8),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generators_first_behind_the_base() {
+        match decide(ResourceSet::new(60, 0, 0), &[]) {
+            Some(BotAction::Build { template, square }) => {
+                // Only the small mine is affordable
+                assert_eq!(template, 1);
+                assert!(square.0 < -1);
+            }
+            other => panic!("Expected a build, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn turrets_face_the_aliens() {
+        match decide(ResourceSet::new(1000, 0, 0), &owned("Mine", 2)) {
+            Some(BotAction::Build { template, square }) => {
+                assert_eq!(template, 0);
+                assert!(square.0 > 0);
+            }
+            other => panic!("Expected a build, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn builds_around_the_main_base() {
+        let base = Vec3::new(40., 0., -20.);
+        let radius = BalancedStrategy::default().base_radius;
+        match decide_at(base, ResourceSet::new(60, 0, 0), &[]) {
+            Some(BotAction::Build { square, .. }) => {
+                // Next to the base, on the side away from the aliens
+                assert!(square.0 < 19 && square.0 >= 20 - radius);
+                assert!((square.1 + 10).abs() <= radius);
+            }
+            other => panic!("Expected a build, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn waits_when_broke() {
+        assert_eq!(decide(ResourceSet::new(10, 0, 0), &[]), None);
+        assert_eq!(decide(ResourceSet::new(40, 0, 0), &owned("Mine", 2)), None);
+    }
+
+    #[test]
+    fn upgrades_generators_when_rich() {
+        let mut buildings = owned("Gun", 10);
+        buildings.push(OwnedBuilding {
+            name: "Mine",
+            square: (-3, 0),
+        });
+        buildings.push(OwnedBuilding {
+            name: "Big mine",
+            square: (-3, 1),
+        });
+        assert_eq!(
+            decide(ResourceSet::new(500, 0, 0), &buildings),
+            Some(BotAction::Demolish { square: (-3, 0) })
+        );
+        // Not while it can't afford the replacement twice
+        assert_ne!(
+            decide(ResourceSet::new(150, 0, 0), &buildings),
+            Some(BotAction::Demolish { square: (-3, 0) })
+        );
+    }
+}
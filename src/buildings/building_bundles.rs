@@ -1,15 +1,13 @@
 use std::f32::consts::PI;
 
 use bevy::prelude::*;
-use bevy_egui::{
-    egui::{Context, TextureId},
-    EguiContext,
-};
+use bevy_egui::egui::{Context, TextureId};
 use bevy_rapier3d::prelude::{Collider, CollisionGroups, Group, RigidBody};
 
 use crate::{
     audio::audio::AudioType,
     effects::{gun_idle_animations::get_laser_gun_hover_animator, muzzleflash::GunType},
+    game_timer::gameplay_schedule::InterpolatedRotation,
     health::{self, health::Health},
     main_base::main_base::register_main_base,
     ui::building_info,
@@ -56,6 +54,9 @@ pub struct DefensiveBuildingBundle {
     pub damage_dealing: DamageDealing,
     pub target_selecting: TargetSelecting,
     pub gun_type: GunType,
+    pub targeting: TargetingMode,
+    // The turning to the targets happens on the gameplay ticks
+    pub rotation: InterpolatedRotation,
     pub collider: Collider,
 }
 #[derive(Clone, Debug)]
@@ -74,6 +75,8 @@ pub struct Building {
     pub cost: ResourceSet,
     pub scene_handle: Handle<Scene>,
     pub scene_offset: Transform,
+    // The name of the building this one can be upgraded to, if any
+    pub upgrades_to: Option<&'static str>,
 }
 
 // A struct containing non-game info about the buildings. All UI stuff should go here.
@@ -82,6 +85,8 @@ pub struct BuildingInfoComponent {
     // A static reference, because we want the names and descriptions to be on the stack during the whole runtime of the game
     pub name: &'static str,
     pub description: &'static str,
+    pub icon: Handle<Image>,
+    // The icon as an egui texture, the UI fills it in. See ui::register_building_images
     pub image: TextureId,
 }
 
@@ -101,16 +106,16 @@ impl Building {
         name: &'static str,
         description: &'static str,
         ass: &Res<AssetServer>,
-        ctx: &mut ResMut<EguiContext>,
     ) -> Self {
         Building {
             show_in_menu: true,
             building_info: BuildingInfoComponent {
                 name,
-                image: ctx.add_image(ass.load(format!(
+                icon: ass.load(format!(
                     "spacekit_2/Isometric_trimmed/{}_SE.png",
                     model_name
-                ))),
+                )),
+                image: TextureId::default(),
                 description,
             },
             bundle: BuildingBundle::DEFENSIVE(DefensiveBuildingBundle {
@@ -122,6 +127,8 @@ impl Building {
                     range,
                 },
                 gun_type,
+                targeting: TargetingMode::default(),
+                rotation: InterpolatedRotation::default(),
                 collider: Collider::cylinder(1.0, collider_radius.unwrap_or(0.5)),
             }),
             cost,
@@ -134,7 +141,69 @@ impl Building {
                 translation: Vec3::new(-2., 0.0, -1.5) * scale,
                 ..Default::default()
             },
+            upgrades_to: None,
+        }
+    }
+}
+
+impl Building {
+    // The scene needs to be inserted as a child so it can be displaced
+    fn scene(&self) -> SceneBundle {
+        SceneBundle {
+            scene: self.scene_handle.clone(),
+            transform: self.scene_offset,
+            ..default()
+        }
+    }
+
+    // Turns an existing building into this one, e.g. for upgrades.
+    // It stays the same entity, so everything that points to it (the grid, the aliens attacking it, the kill stats) still does.
+    // The health is kept as a fraction of the max health, turrets keep their targeting mode if they had one.
+    // The laser's hover animation is left alone, nothing turns into or out of a laser
+    pub fn replace(
+        self,
+        commands: &mut Commands,
+        entity: Entity,
+        health_ratio: f32,
+        targeting: Option<TargetingMode>,
+    ) {
+        let scene = self.scene();
+        let keep_health = |h: Health| {
+            let mut new = Health::new(h.max_hp);
+            new.hp = ((h.max_hp as f32 * health_ratio).round() as i32).clamp(1, h.max_hp);
+            new
+        };
+
+        let mut c = commands.entity(entity);
+        // The model of the old building
+        c.despawn_descendants();
+        c.insert((self.cost, self.building_info));
+        match self.bundle {
+            BuildingBundle::DEFENSIVE(b) => {
+                c.remove::<ResourceGenerator>();
+                c.insert(DefensiveBuildingBundle {
+                    health: keep_health(b.health),
+                    targeting: targeting.unwrap_or(b.targeting),
+                    ..b
+                });
+            }
+            BuildingBundle::GENERATOR(b) => {
+                c.remove::<(
+                    DamageDealing,
+                    TargetSelecting,
+                    GunType,
+                    TargetingMode,
+                    InterpolatedRotation,
+                )>();
+                c.insert(GeneratorBuildingBundle {
+                    health: keep_health(b.health),
+                    ..b
+                });
+            }
         }
+        c.with_children(|parent| {
+            parent.spawn(scene);
+        });
     }
 }
 
@@ -150,12 +219,7 @@ impl Building {
     // The commands need to be passed in. We can't hold a reference to them for longer than a game tick
     // Returns the entity built.
     pub fn build(self, commands: &mut Commands, point: Vec3) -> Option<Entity> {
-        // The scene needs to be inserted as a child so it can be displaced
-        let scene = SceneBundle {
-            scene: self.scene_handle,
-            transform: self.scene_offset,
-            ..default()
-        };
+        let scene = self.scene();
 
         // The bundle to be inserted into every building
         // The default components all bundles should have.
@@ -213,12 +277,22 @@ pub struct BuildingTemplates {
     pub templates: Vec<Building>,
 }
 
+impl BuildingTemplates {
+    pub fn get(&self, name: &str) -> Option<&Building> {
+        self.templates.iter().find(|b| b.building_info.name == name)
+    }
+
+    // The index of the template, which is how the player commands refer to them
+    pub fn index_of(&self, building: &Building) -> Option<usize> {
+        self.templates.iter().position(|b| b == building)
+    }
+}
+
 // The base range of a machine gun is defined here, which means all the other ranges can be defined relative to it
-const MACHINE_GUN_RANGE: f32 = 8.0;
+pub const MACHINE_GUN_RANGE: f32 = 8.0;
 pub fn register_defensive(
     mut templates: ResMut<BuildingTemplates>,
     ass: Res<AssetServer>,
-    mut ctx: ResMut<EguiContext>,
 ) {
     templates.templates.push(Building::new_defensive(
         100,
@@ -233,23 +307,24 @@ pub fn register_defensive(
         "Laser speeder",
         "",
         &ass,
-        &mut ctx,
-    ));
-    templates.templates.push(Building::new_defensive(
-        100,
-        ResourceSet::new(50, 0, 0),
-        30,
-        1000,
-        MACHINE_GUN_RANGE,
-        GunType::MachineGun,
-        (0.5 * 1.15).into(),
-        1.,
-        "turret_single",
-        "Machine gun mk1",
-        "",
-        &ass,
-        &mut ctx,
     ));
+    templates.templates.push(Building {
+        upgrades_to: Some("Machine gun mk2"),
+        ..Building::new_defensive(
+            100,
+            ResourceSet::new(50, 0, 0),
+            30,
+            1000,
+            MACHINE_GUN_RANGE,
+            GunType::MachineGun,
+            (0.5 * 1.15).into(),
+            1.,
+            "turret_single",
+            "Machine gun mk1",
+            "",
+            &ass,
+        )
+    });
 
     templates.templates.push(Building::new_defensive(
         100,
@@ -264,7 +339,6 @@ pub fn register_defensive(
         "Machine gun mk2",
         "",
         &ass,
-        &mut ctx,
     ));
     // TODO Add more buildings
     // TODO Tie this to buttons
@@ -282,16 +356,16 @@ impl Building {
         model_name: &str,
         scale: f32,
         ass: &Res<AssetServer>,
-        ctx: &mut ResMut<EguiContext>,
     ) -> Self {
         Building {
             show_in_menu: true,
             building_info: BuildingInfoComponent {
                 name,
-                image: ctx.add_image(ass.load(format!(
+                icon: ass.load(format!(
                     "spacekit_2/Isometric_trimmed/{}_SE.png",
                     model_name
-                ))),
+                )),
+                image: TextureId::default(),
                 description,
             },
             bundle: BuildingBundle::GENERATOR(GeneratorBuildingBundle {
@@ -310,6 +384,7 @@ impl Building {
                 translation: Vec3::new(-2., 0.0, -1.5) * scale,
                 ..Default::default()
             },
+            upgrades_to: None,
         }
     }
 }
@@ -317,19 +392,20 @@ impl Building {
 pub fn register_resources(
     mut templates: ResMut<BuildingTemplates>,
     ass: Res<AssetServer>,
-    mut ctx: ResMut<EguiContext>,
 ) {
-    templates.templates.push(Building::new_resource(
-        "Mine tier 1",
-        "",
-        ResourceGenerator::new(super::resources::ResourceType::Ore, 1, 2_000),
-        100,
-        ResourceSet::new(25, 0, 0),
-        "monorail_trainCargo",
-        1.,
-        &ass,
-        &mut ctx,
-    ));
+    templates.templates.push(Building {
+        upgrades_to: Some("Mine tier 2"),
+        ..Building::new_resource(
+            "Mine tier 1",
+            "",
+            ResourceGenerator::new(super::resources::ResourceType::Ore, 1, 2_000),
+            100,
+            ResourceSet::new(25, 0, 0),
+            "monorail_trainCargo",
+            1.,
+            &ass,
+        )
+    });
 
     templates.templates.push(Building::new_resource(
         "Mine tier 2",
@@ -340,7 +416,6 @@ pub fn register_resources(
         "monorail_trainCargo",
         1.5,
         &ass,
-        &mut ctx,
     ));
 
     templates.templates.push(Building::new_resource(
@@ -352,7 +427,6 @@ pub fn register_resources(
         "machine_wirelessCable",
         1.,
         &ass,
-        &mut ctx,
     ));
 
     templates.templates.push(Building::new_resource(
@@ -364,6 +438,5 @@ pub fn register_resources(
         "satelliteDish_detailed",
         1.,
         &ass,
-        &mut ctx,
     ));
 }
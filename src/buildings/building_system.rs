@@ -1,4 +1,5 @@
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     utils::{HashMap, HashSet},
 };
@@ -9,15 +10,19 @@ use crate::{
         get_world_point_from_screen::get_plane_point_from_mouse_pos,
         pan_camera::{get_primary_window_size, PanOrbitCamera},
     },
-    health::health::DeathEvent,
+    health::health::{DeathEvent, Health},
+    main_base::main_base::MainBaseComponent,
+    rules::game_rules::GameRules,
     ui::{
         error_info::ErrorEvent,
         ui::{UIMode, UIState},
-    }, main_base::main_base::MainBaseComponent,
+    },
 };
 
 use super::{
-    building_bundles::Building,
+    building_bundles::{Building, BuildingInfoComponent, BuildingTemplates},
+    defensive_buildings::TargetingMode,
+    player_commands::{CommandEvent, PlayerCommand},
     resources::{ResourceSet, ResourceState},
 };
 
@@ -25,6 +30,155 @@ use super::grid::{Grid, SQUARE_SIZE};
 
 // This modules handles the user actions related to construction/demolishing of buildings
 
+// Everything that can be done to a building, checked the same way no matter who asked. See player_commands for the system that uses it.
+// Returns why it couldn't be done, it's up to the caller whether to show the error
+#[derive(SystemParam)]
+pub struct BuildingActions<'w, 's> {
+    pub resources: ResMut<'w, ResourceState>,
+    pub grid: ResMut<'w, Grid>,
+    pub commands: Commands<'w, 's>,
+    // Whether demolishing refunds anything
+    rules: Res<'w, GameRules>,
+    // So that we know how many resources to refund for the destruction of the building
+    costs: Query<'w, 's, &'static ResourceSet, Without<MainBaseComponent>>,
+    // So that the main base can't be destroyed
+    main_base: Query<'w, 's, (), With<MainBaseComponent>>,
+    death_events: EventWriter<'w, 's, DeathEvent>,
+    repairable:
+        Query<'w, 's, (&'static mut Health, &'static ResourceSet), Without<MainBaseComponent>>,
+    infos: Query<'w, 's, &'static BuildingInfoComponent, Without<MainBaseComponent>>,
+    targeting: Query<'w, 's, &'static mut TargetingMode>,
+}
+
+// Repairs are half the price of building, for the fraction of health that's missing
+pub fn repair_cost(cost: &ResourceSet, health: &Health) -> ResourceSet {
+    if health.max_hp <= 0 || health.hp >= health.max_hp {
+        return ResourceSet::new(0, 0, 0);
+    }
+    let missing = (health.max_hp - health.hp.max(0)) as f32 / health.max_hp as f32;
+    cost.scale(missing / 2.)
+}
+
+// Upgrades cost the difference between the two buildings
+pub fn upgrade_cost(from: &Building, to: &Building) -> ResourceSet {
+    to.cost.saturating_sub(&from.cost)
+}
+
+impl<'w, 's> BuildingActions<'w, 's> {
+    // Builds on the square the point is in
+    pub fn build(&mut self, building: &Building, point: Vec3) -> Result<Entity, ErrorEvent> {
+        if self.grid.is_square_blocked(point) {
+            return Err(ErrorEvent::SpaceOccupied);
+        }
+        if !(building.cost <= self.resources.resources) {
+            return Err(ErrorEvent::NotEnoughResources);
+        }
+        self.resources.resources.sub(&building.cost);
+        let e = building
+            .clone()
+            .build(&mut self.commands, Grid::get_plane_pos(point))
+            .unwrap();
+        self.grid.block_square_vec3(point, e);
+        return Ok(e);
+    }
+
+    // Demolishes the building on the square the point is in and refunds part of its cost
+    pub fn demolish(&mut self, point: Vec3) -> Result<Entity, ErrorEvent> {
+        let entity = match self.grid.get_entity(point) {
+            Some(entity) => *entity,
+            None => return Err(ErrorEvent::NothingToDestroy),
+        };
+        if self.main_base.contains(entity) {
+            return Err(ErrorEvent::CantDestroyYourOwnBase);
+        }
+
+        if let Ok(cost) = self.costs.get(entity) {
+            self.resources.resources.add_set(&self.rules.refund(cost));
+        }
+        // Free the square right away, building_death only gets to it later in the tick
+        // and another demolish before that would be refunded again
+        self.grid.unblock_square_vec3(point);
+        self.death_events.send(DeathEvent {
+            entity,
+            killer: None,
+        });
+        return Ok(entity);
+    }
+
+    fn building_at(&self, point: Vec3) -> Option<Entity> {
+        self.grid
+            .get_entity(point)
+            .copied()
+            .filter(|e| !self.main_base.contains(*e))
+    }
+
+    // Heals the building on the square back to full health
+    pub fn repair(&mut self, point: Vec3) -> Result<Entity, ErrorEvent> {
+        let entity = self.building_at(point).ok_or(ErrorEvent::NothingToRepair)?;
+        let (mut health, cost) = match self.repairable.get_mut(entity) {
+            // Dead buildings are about to be cleaned up, there's nothing to save
+            Ok(x) if x.0.hp > 0 && x.0.hp < x.0.max_hp => x,
+            _ => return Err(ErrorEvent::NothingToRepair),
+        };
+        let price = repair_cost(cost, &health);
+        if !(price <= self.resources.resources) {
+            return Err(ErrorEvent::NotEnoughResources);
+        }
+        self.resources.resources.sub(&price);
+        health.hp = health.max_hp;
+        return Ok(entity);
+    }
+
+    // Turns the building on the square into the one it upgrades to, it stays the same entity.
+    // Returns the building and what the upgrade cost
+    pub fn upgrade(
+        &mut self,
+        templates: &BuildingTemplates,
+        point: Vec3,
+    ) -> Result<(Entity, ResourceSet), ErrorEvent> {
+        let entity = self
+            .building_at(point)
+            .ok_or(ErrorEvent::NothingToUpgrade)?;
+        let health_ratio = match self.repairable.get(entity) {
+            // Dead buildings are about to be cleaned up
+            Ok((health, _)) if health.hp > 0 => health.hp as f32 / health.max_hp as f32,
+            _ => return Err(ErrorEvent::NothingToUpgrade),
+        };
+        let info = self
+            .infos
+            .get(entity)
+            .map_err(|_| ErrorEvent::NothingToUpgrade)?;
+        let current = templates
+            .get(info.name)
+            .ok_or(ErrorEvent::UnknownBuilding)?;
+        let next = current.upgrades_to.ok_or(ErrorEvent::NothingToUpgrade)?;
+        let next = templates.get(next).ok_or(ErrorEvent::UnknownBuilding)?;
+        let price = upgrade_cost(current, next);
+        if !(price <= self.resources.resources) {
+            return Err(ErrorEvent::NotEnoughResources);
+        }
+        self.resources.resources.sub(&price);
+        let targeting = self.targeting.get(entity).ok().copied();
+        next.clone()
+            .replace(&mut self.commands, entity, health_ratio, targeting);
+        return Ok((entity, price));
+    }
+
+    pub fn set_targeting(
+        &mut self,
+        point: Vec3,
+        mode: TargetingMode,
+    ) -> Result<Entity, ErrorEvent> {
+        let entity = self.building_at(point).ok_or(ErrorEvent::NotATurret)?;
+        let mut targeting = self
+            .targeting
+            .get_mut(entity)
+            .map_err(|_| ErrorEvent::NotATurret)?;
+        *targeting = mode;
+        return Ok(entity);
+    }
+}
+
 // This is the square which highlights the currently hovered spot
 // It is either green or red
 // Marker component struct, no info specific to it.
@@ -51,10 +205,9 @@ pub fn hide_highlight_square(
 }
 
 // We can't use the worldclick event here because we need the hover position as well
-// This system handles positioning of the highlight square on the board as well as actually clicking to buid/destroy any building
+// This system handles positioning of the highlight square on the board as well as sending the commands to buid/destroy any building
 pub fn building_system(
     mut ctx: ResMut<EguiContext>,
-    mut resources: ResMut<ResourceState>,
     // The info necessary to get the world positio from mouse position
     mbutton: Res<Input<MouseButton>>,
     windows: Res<Windows>,
@@ -62,25 +215,15 @@ pub fn building_system(
         Query<(&PanOrbitCamera, &Transform, &Projection)>,
         Query<(&mut Transform, &mut Handle<StandardMaterial>), With<HighlightSquare>>,
     )>,
-    // So that we know how many resources to refund for the destruction of the building
-    resource_cost_query: Query<
-        (&ResourceSet, Entity),
-        (Without<PanOrbitCamera>, Without<HighlightSquare>, Without<MainBaseComponent>),
-    >,
-    // Query for the main base so that we can't destroy it
-    // We need to exclude the other components to prevent query overlap
-    main_base: Query<&MainBaseComponent, (Without<HighlightSquare>, Without<PanOrbitCamera>)>,
     ui_state: Res<UIState>,
     // Technically we could predefine both the mesh and the material for the square, but we don't recreate the square often enough for this to be a significant memory leak
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-
-    mut grid: ResMut<Grid>,
+    grid: Res<Grid>,
+    templates: Res<BuildingTemplates>,
     mut commands: Commands,
-    mut death_events: EventWriter<DeathEvent>,
-
-    // Notify the player that they cannot build
-    mut error_events: EventWriter<ErrorEvent>,
+    // Carried out by apply_player_commands, which also tells the player when they cannot build
+    mut command_events: EventWriter<CommandEvent>,
 ) {
     let cam_query = query_set.p0();
     let (cam, transform, proj) = cam_query.single();
@@ -137,46 +280,19 @@ pub fn building_system(
 
     // Handle building/destroying
     if mbutton.just_pressed(MouseButton::Left) {
-        match &ui_state.mode {
+        let square = Grid::get_square_index(point);
+        let command = match &ui_state.mode {
             UIMode::BuildingDefensive(Some(b)) | UIMode::BuildingResources(Some(b)) => {
-                if !grid.is_square_blocked(point) {
-                    if b.cost <= resources.resources {
-                        resources.resources.sub(&b.cost);
-                        let e = b.clone().build(&mut commands, Grid::get_plane_pos(point));
-                        if let Some(e) = e {
-                            grid.block_square_vec3(point, e);
-                        }
-                    } else {
-                        error_events.send(ErrorEvent::NotEnoughResources);
-                    }
-                } else {
-                    error_events.send(ErrorEvent::SpaceOccupied);
-                }
-            }
-            UIMode::Destroying => {
-                let entity = grid.get_entity(point);
-                if let Some(entity) = entity {
-                    // If the main base query can find the entity this means the entity is the main base
-                    // We want to prevent deleting that
-                    if main_base.get(*entity).is_ok() {
-                        error_events.send(ErrorEvent::CantDestroyYourOwnBase);
-                        return;
-                    }
-
-                    if let Ok((cost, ..)) = resource_cost_query.get(*entity) {
-                        resources.resources.add_set(&cost.div(2));
-                    }
-                    death_events.send(DeathEvent {
-                        entity: *entity,
-                        killer: None,
-                    })
-                } else {
-                    error_events.send(ErrorEvent::NothingToDestroy)
+                match templates.index_of(b) {
+                    Some(template) => PlayerCommand::Build { template, square },
+                    None => return,
                 }
             }
+            UIMode::Destroying => PlayerCommand::Demolish { square },
             _ => {
                 return;
             }
         };
+        command_events.send(CommandEvent::player(command));
     };
 }
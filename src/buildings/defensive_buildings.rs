@@ -6,14 +6,17 @@ use bevy::{
 };
 use bevy_rapier3d::prelude::{Collider, CollisionGroups, Group, RigidBody};
 use bevy_tweening::{Animator, EaseFunction, Tween, TweenCompleted};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    aliens::alien::Alien,
+    aliens::{alien::Alien, nests::Nest},
     effects::{
         muzzleflash::{GunFireEvent, GunType},
         relative_lenses::RelativeTransformPositionLens,
     },
-    health::health::{DeathEvent, Health},
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayClock, GameplayStage},
+    health::health::{death_timers, DamageEvent, DeathEvent, Health},
+    spatial::spatial_index::SpatialIndex,
     AppState,
 };
 
@@ -27,12 +30,15 @@ pub struct DefensiveBuildingPlugin;
 impl Plugin for DefensiveBuildingPlugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(
-            SystemSet::on_update(AppState::InGame)
-                .with_system(damage_dealing)
-                .with_system(defensive_buildings_targetting)
-                .with_system(building_death)
-                .with_system(despawn_event_handling),
+            SystemSet::on_update(AppState::InGame).with_system(despawn_event_handling),
             // .add_event::<DespawnEvent>()
+        )
+        .add_system_set_to_stage(
+            GameplayStage,
+            on_gameplay_tick()
+                .with_system(defensive_buildings_targetting)
+                .with_system(damage_dealing.after(defensive_buildings_targetting))
+                .with_system(building_death.after(death_timers)),
         );
     }
 }
@@ -51,9 +57,6 @@ impl Default for AlienTarget {
     }
 }
 
-fn distance(a: &Vec3, b: &Vec3) -> f32 {
-    f32::sqrt(f32::powi((a.x - b.x), 2) + f32::powi((a.z - b.z), 2))
-}
 // Used to keep consistent targets across game ticks
 #[derive(Component, Clone, Copy, Debug)]
 pub struct TargetSelecting {
@@ -70,37 +73,91 @@ impl TargetSelecting {
     }
 }
 
+// Which alien in range a turret picks when it needs a new target. Set by the player for each turret
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetingMode {
+    #[default]
+    Closest,
+    // The most health left
+    Strongest,
+    // The least health left, to finish them off
+    Weakest,
+}
+
+impl TargetingMode {
+    pub const ALL: [TargetingMode; 3] = [
+        TargetingMode::Closest,
+        TargetingMode::Strongest,
+        TargetingMode::Weakest,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TargetingMode::Closest => "Closest",
+            TargetingMode::Strongest => "Strongest",
+            TargetingMode::Weakest => "Weakest",
+        }
+    }
+}
+
 // This system handles only the targeting for defensive buildings
 // ALiens are handled in alien_ai
 pub fn defensive_buildings_targetting(
-    mut defensive_buildings: Query<(&mut Transform, &mut TargetSelecting), Without<Alien>>,
+    mut defensive_buildings: Query<
+        (&mut Transform, &mut TargetSelecting, Option<&TargetingMode>),
+        Without<Alien>,
+    >,
     aliens: Query<(&Health, &Alien, &Transform, Entity)>,
+    nests: Query<(&Health, &Transform), (With<Nest>, Without<TargetSelecting>)>,
+    index: Res<SpatialIndex>,
     // muzzleflash_template: Res<MuzzleflashTemplate>,
 ) {
-    for (mut gun_transform, mut gun_target) in defensive_buildings.iter_mut() {
+    for (mut gun_transform, mut gun_target, mode) in defensive_buildings.iter_mut() {
         // Choose a new target if needed
+        // The index only contains living aliens and nests.
+        // Aliens come first, the nests are only shot at when there's nothing else to do
         if let None = gun_target.target {
-            let alien = aliens.iter().find(|(_, a, t, _)| {
-                a.alive && distance(&t.translation, &gun_transform.translation) < gun_target.range
+            let position = gun_transform.translation;
+            let hp = |e: &(Entity, Vec3)| aliens.get(e.0).map_or(0, |a| a.0.hp);
+            let alien = match mode.copied().unwrap_or_default() {
+                TargetingMode::Closest => {
+                    index.aliens.nearest(position, gun_target.range, |_| true)
+                }
+                TargetingMode::Strongest => index
+                    .aliens
+                    .query_radius(position, gun_target.range)
+                    .max_by_key(hp),
+                TargetingMode::Weakest => index
+                    .aliens
+                    .query_radius(position, gun_target.range)
+                    .min_by_key(hp),
+            };
+            let target = alien.or_else(|| {
+                index
+                    .nests
+                    .nearest(gun_transform.translation, gun_target.range, |_| true)
             });
-            if let Some(new_target) = alien {
-                gun_target.target = Some(new_target.3);
+            if let Some((new_target, _)) = target {
+                gun_target.target = Some(new_target);
                 // println!("Speeder retargetting")
             }
         }
 
         // After target acquired, turn towards it
         if let Some(t) = gun_target.target {
-            let target = aliens.get(t);
+            let target = aliens
+                .get(t)
+                .map(|(h, _, t, _)| (h, t))
+                .or_else(|_| nests.get(t));
             if let Ok(target) = target {
-                
                 // If the target is dead, choose a new target
                 if target.0.hp <= 0 {
                     gun_target.target = None;
                     return;
                 }
 
-                let target = target.2.translation;
+                let target = target.1.translation;
                 let me = gun_transform.translation;
                 let diff = target - me;
                 let diff = diff.normalize();
@@ -133,7 +190,7 @@ impl DamageDealing {
 }
 
 pub fn damage_dealing(
-    time: Res<Time>,
+    time: Res<GameplayClock>,
     mut query_set: ParamSet<(
         Query<(
             &mut DamageDealing,
@@ -146,6 +203,7 @@ pub fn damage_dealing(
         Query<(&mut Health, &Transform, Entity)>,
     )>,
     mut ev: EventWriter<DeathEvent>,
+    mut damage_ev: EventWriter<DamageEvent>,
     mut gun_fire_event: EventWriter<GunFireEvent>,
 ) {
     let mut damage_dealers = query_set.p0();
@@ -197,6 +255,11 @@ pub fn damage_dealing(
         if let Ok((mut h, transform, _)) = query_set.p1().get_mut(target) {
             if transform.translation.distance(hitter_translation).abs() <= hitter_range {
                 h.hp -= damage;
+                damage_ev.send(DamageEvent {
+                    entity: target,
+                    attacker: killer,
+                    damage,
+                });
                 if h.hp <= 0 {
                     killed = true;
                     ev.send(DeathEvent {
@@ -231,7 +294,10 @@ pub fn building_death(
             let start = Vec3::new(0., 0., 0.);
             let end = Vec3::new(0., -3., 0.);
             let point = t.translation.clone();
-            grid.unblock_square_vec3(point);
+            // A demolished building's square is already free, and might have been built on again
+            if grid.get_entity(point) == Some(&e) {
+                grid.unblock_square_vec3(point);
+            }
 
             // Death animation
             // Shift the building down as if it crumpled to the ground
@@ -247,7 +313,9 @@ pub fn building_death(
             .with_completed_event(DESPAWN_EVENT_CODE);
             let e = commands.get_entity(e);
             if let Some(mut e) = e {
-                e.insert(Animator::new(tween));
+                // The animation takes frames, not ticks. The wreck stops blocking the aliens right away,
+                // so the physics doesn't depend on how long it's drawn for
+                e.insert(Animator::new(tween)).remove::<Collider>();
             }
             // a.alive = false;
         }
@@ -16,6 +16,9 @@ pub struct Grid {
     // This is used for spawning aliens,so that they don't spawn in the middle of the base and the player has time to react to them
     pub base_center: Vec3,
     pub center_radius: f32,
+
+    // Increased every time a square is blocked or unblocked, so the alien paths know when to be recalculated
+    pub version: u32,
 }
 
 impl Grid {
@@ -24,6 +27,7 @@ impl Grid {
             blocked_squares: HashMap::new(),
             base_center: Vec3::splat(0.),
             center_radius: 5.,
+            version: 0,
         }
     }
 
@@ -36,7 +40,11 @@ impl Grid {
 
     // Returns the center of the square that the point is in
     pub fn get_plane_pos(point: Vec3) -> Vec3 {
-        let t = Self::get_square_index(point);
+        return Self::get_square_pos(Self::get_square_index(point));
+    }
+
+    // Returns the center of the square with the index
+    pub fn get_square_pos(t: (i8, i8)) -> Vec3 {
         return Vec3::new(
             (t.0 as f32 + 0.5) * SQUARE_SIZE,
             0.01,
@@ -112,6 +120,7 @@ impl Grid {
     // Called everytime a building is constructed
     pub fn block_square(&mut self, point: (i8, i8), entity: Entity) {
         self.blocked_squares.insert(point, entity);
+        self.version += 1;
         self.update_base();
     }
 
@@ -122,6 +131,7 @@ impl Grid {
     // Used during building destruction
     pub fn unblock_square_vec3(&mut self, point: Vec3) -> Option<Entity> {
         let e = self.blocked_squares.remove(&Grid::get_square_index(point));
+        self.version += 1;
         self.update_base();
         return e;
     }
@@ -1,7 +1,7 @@
-
 pub mod defensive_buildings;
 pub mod building_system;
 pub mod  resources;
 pub mod building_bundles;
 pub mod grid;
-pub mod resource_images;
\ No newline at end of file
+pub mod resource_images;
+pub mod player_commands;
\ No newline at end of file
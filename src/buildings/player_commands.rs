@@ -0,0 +1,392 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game_timer::gameplay_schedule::{GameplayStage, GameplayTick},
+    replay::playback::ReplayPlayback,
+    stats::game_stats::ResourceTally,
+    ui::error_info::ErrorEvent,
+    AppState,
+};
+
+use super::{
+    building_bundles::BuildingTemplates, building_system::BuildingActions,
+    defensive_buildings::TargetingMode, grid::Grid,
+};
+
+// Everything the player can do to the base goes through here as a PlayerCommand.
+// The mouse, the building info window and the AI player only send commands, apply_player_commands is the one place that checks and carries them out.
+// Commands only hold plain data (template indices and grid squares) so they can be saved and played back.
+// They're applied at the start of the next gameplay tick, see gameplay_schedule.
+
+pub struct PlayerCommandsPlugin;
+
+impl Plugin for PlayerCommandsPlugin {
+    fn build(&self, app: &mut App) {
+        // Not a regular event, a fast frame rate can go more frames without a tick than an event lasts.
+        // apply_player_commands takes them out itself
+        app.init_resource::<Events<CommandEvent>>()
+            .add_event::<CommandResult>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(clear_commands))
+            .add_system_to_stage(
+                GameplayStage,
+                apply_player_commands
+                    .label(GameplayTick::Commands)
+                    .after(GameplayTick::Start),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum PlayerCommand {
+    // The index of the building in BuildingTemplates
+    Build {
+        template: usize,
+        square: (i8, i8),
+    },
+    Demolish {
+        square: (i8, i8),
+    },
+    // Back to full health, for part of the cost of the building
+    Repair {
+        square: (i8, i8),
+    },
+    // Into the building it upgrades_to, for the difference in cost
+    Upgrade {
+        square: (i8, i8),
+    },
+    SetTargeting {
+        square: (i8, i8),
+        mode: TargetingMode,
+    },
+}
+
+impl PlayerCommand {
+    pub fn square(&self) -> (i8, i8) {
+        match self {
+            PlayerCommand::Build { square, .. }
+            | PlayerCommand::Demolish { square }
+            | PlayerCommand::Repair { square }
+            | PlayerCommand::Upgrade { square }
+            | PlayerCommand::SetTargeting { square, .. } => *square,
+        }
+    }
+}
+
+// Who sent the command. Only the player gets to see the errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandSource {
+    Player,
+    AiPlayer,
+    Replay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandEvent {
+    pub command: PlayerCommand,
+    pub source: CommandSource,
+}
+
+impl CommandEvent {
+    pub fn player(command: PlayerCommand) -> Self {
+        CommandEvent {
+            command,
+            source: CommandSource::Player,
+        }
+    }
+}
+
+// What a command did, the entity is the building it was done to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandOutcome {
+    Built(Entity),
+    Demolished(Entity),
+    Repaired(Entity),
+    // Still the same entity, with what the upgrade cost
+    Upgraded {
+        building: Entity,
+        cost: ResourceTally,
+    },
+    TargetingSet(Entity),
+}
+
+// Sent for every CommandEvent, in the same order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandResult {
+    pub command: PlayerCommand,
+    pub source: CommandSource,
+    pub result: Result<CommandOutcome, ErrorEvent>,
+}
+
+pub fn apply_command(
+    actions: &mut BuildingActions,
+    templates: &BuildingTemplates,
+    command: &PlayerCommand,
+) -> Result<CommandOutcome, ErrorEvent> {
+    let point = Grid::get_square_pos(command.square());
+    match *command {
+        PlayerCommand::Build { template, .. } => {
+            let building = templates
+                .templates
+                .get(template)
+                .ok_or(ErrorEvent::UnknownBuilding)?;
+            actions.build(building, point).map(CommandOutcome::Built)
+        }
+        PlayerCommand::Demolish { .. } => actions.demolish(point).map(CommandOutcome::Demolished),
+        PlayerCommand::Repair { .. } => actions.repair(point).map(CommandOutcome::Repaired),
+        PlayerCommand::Upgrade { .. } => {
+            let (building, price) = actions.upgrade(templates, point)?;
+            let mut cost = ResourceTally::default();
+            cost.add_set(&price);
+            Ok(CommandOutcome::Upgraded { building, cost })
+        }
+        PlayerCommand::SetTargeting { mode, .. } => actions
+            .set_targeting(point, mode)
+            .map(CommandOutcome::TargetingSet),
+    }
+}
+
+// Anything sent after the last tick of the previous game
+pub fn clear_commands(mut commands: ResMut<Events<CommandEvent>>) {
+    commands.clear();
+}
+
+pub fn apply_player_commands(
+    mut commands: ResMut<Events<CommandEvent>>,
+    mut actions: BuildingActions,
+    templates: Res<BuildingTemplates>,
+    mut results: EventWriter<CommandResult>,
+    mut error_events: EventWriter<ErrorEvent>,
+    playback: Res<ReplayPlayback>,
+) {
+    for ev in commands.drain() {
+        // While watching a replay only the recording gets to build
+        if playback.active() && ev.source != CommandSource::Replay {
+            continue;
+        }
+        let result = apply_command(&mut actions, &templates, &ev.command);
+        if let (Err(e), CommandSource::Player) = (result, ev.source) {
+            error_events.send(e);
+        }
+        results.send(CommandResult {
+            command: ev.command,
+            source: ev.source,
+            result,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_player_commands {
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::Collider;
+
+    use crate::{
+        buildings::{
+            building_bundles::{
+                Building, BuildingBundle, BuildingInfoComponent, BuildingTemplates,
+                DefensiveBuildingBundle,
+            },
+            building_system::repair_cost,
+            defensive_buildings::{AlienTarget, DamageDealing, TargetSelecting, TargetingMode},
+            grid::Grid,
+            resources::{ResourceSet, ResourceState},
+        },
+        effects::muzzleflash::GunType,
+        game_timer::gameplay_schedule::InterpolatedRotation,
+        health::health::{DeathEvent, Health},
+        replay::playback::ReplayPlayback,
+        rules::game_rules::GameRules,
+        ui::error_info::ErrorEvent,
+    };
+
+    use super::{apply_player_commands, CommandEvent, CommandResult, PlayerCommand};
+
+    fn turret(
+        name: &'static str,
+        damage: i32,
+        cost: ResourceSet,
+        upgrades_to: Option<&'static str>,
+    ) -> Building {
+        Building {
+            show_in_menu: true,
+            building_info: BuildingInfoComponent {
+                name,
+                description: "",
+                icon: Default::default(),
+                image: Default::default(),
+            },
+            bundle: BuildingBundle::DEFENSIVE(DefensiveBuildingBundle {
+                health: Health::new(100),
+                alien_target: AlienTarget::default(),
+                damage_dealing: DamageDealing::new(damage, 1000),
+                target_selecting: TargetSelecting::new(8.),
+                gun_type: GunType::MachineGun,
+                targeting: TargetingMode::default(),
+                rotation: InterpolatedRotation::default(),
+                collider: Collider::cylinder(1.0, 0.5),
+            }),
+            cost,
+            scene_handle: Handle::default(),
+            scene_offset: Transform::default(),
+            upgrades_to,
+        }
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.init_resource::<Events<CommandEvent>>()
+            .add_event::<CommandResult>()
+            .add_event::<ErrorEvent>()
+            .add_event::<DeathEvent>()
+            .init_resource::<GameRules>()
+            .init_resource::<ReplayPlayback>()
+            .insert_resource(Grid::new())
+            .insert_resource(ResourceState {
+                resources: ResourceSet::new(1000, 100, 10),
+            })
+            .insert_resource(BuildingTemplates {
+                templates: vec![
+                    turret("Gun mk1", 10, ResourceSet::new(100, 0, 0), Some("Gun mk2")),
+                    turret("Gun mk2", 25, ResourceSet::new(250, 20, 0), None),
+                ],
+            })
+            .add_system(apply_player_commands);
+        app
+    }
+
+    fn send(app: &mut App, command: PlayerCommand) {
+        app.world
+            .resource_mut::<Events<CommandEvent>>()
+            .send(CommandEvent::player(command));
+        app.update();
+    }
+
+    #[test]
+    fn upgrades_keep_the_building() {
+        let mut app = app();
+        let square = (2, 3);
+        send(
+            &mut app,
+            PlayerCommand::Build {
+                template: 0,
+                square,
+            },
+        );
+        let gun = *app
+            .world
+            .resource::<Grid>()
+            .get_entity(Grid::get_square_pos(square))
+            .unwrap();
+        app.world.get_mut::<Health>(gun).unwrap().hp = 50;
+        send(
+            &mut app,
+            PlayerCommand::SetTargeting {
+                square,
+                mode: TargetingMode::Weakest,
+            },
+        );
+        send(&mut app, PlayerCommand::Upgrade { square });
+
+        assert_eq!(
+            app.world
+                .resource::<Grid>()
+                .get_entity(Grid::get_square_pos(square)),
+            Some(&gun)
+        );
+        let entity = app.world.entity(gun);
+        assert_eq!(
+            entity.get::<BuildingInfoComponent>().unwrap().name,
+            "Gun mk2"
+        );
+        assert_eq!(entity.get::<DamageDealing>().unwrap().damage, 25);
+        assert_eq!(entity.get::<Health>().unwrap().hp, 50);
+        assert_eq!(entity.get::<TargetingMode>(), Some(&TargetingMode::Weakest));
+        // 100 for building it and 150 + 20 for the upgrade
+        assert_eq!(
+            app.world.resource::<ResourceState>().resources,
+            ResourceSet::new(750, 80, 10)
+        );
+    }
+
+    #[test]
+    fn demolishing_twice_refunds_once() {
+        let mut app = app();
+        let square = (-1, 4);
+        send(
+            &mut app,
+            PlayerCommand::Build {
+                template: 0,
+                square,
+            },
+        );
+        // Both in the same tick, before the building is gone
+        for _ in 0..2 {
+            app.world
+                .resource_mut::<Events<CommandEvent>>()
+                .send(CommandEvent::player(PlayerCommand::Demolish { square }));
+        }
+        app.update();
+
+        // 100 for building it and half of it back
+        assert_eq!(
+            app.world.resource::<ResourceState>().resources,
+            ResourceSet::new(950, 100, 10)
+        );
+        assert_eq!(
+            app.world
+                .resource::<Grid>()
+                .get_entity(Grid::get_square_pos(square)),
+            None
+        );
+    }
+
+    #[test]
+    fn repairs_cost_half_of_the_missing_health() {
+        let cost = ResourceSet::new(100, 30, 0);
+        let mut health = Health::new(200);
+        assert_eq!(repair_cost(&cost, &health), ResourceSet::new(0, 0, 0));
+        health.hp = 100;
+        assert_eq!(repair_cost(&cost, &health), ResourceSet::new(25, 8, 0));
+        health.hp = 1;
+        assert_eq!(repair_cost(&cost, &health), ResourceSet::new(50, 15, 0));
+    }
+
+    #[test]
+    fn upgrades_never_refund() {
+        let cheap = ResourceSet::new(50, 10, 0);
+        let expensive = ResourceSet::new(150, 0, 20);
+        assert_eq!(
+            expensive.saturating_sub(&cheap),
+            ResourceSet::new(100, 0, 20)
+        );
+    }
+
+    #[test]
+    fn commands_round_trip_through_json() {
+        let commands = [
+            PlayerCommand::Build {
+                template: 3,
+                square: (-2, 5),
+            },
+            PlayerCommand::Demolish { square: (0, 4) },
+            PlayerCommand::SetTargeting {
+                square: (1, 1),
+                mode: TargetingMode::Weakest,
+            },
+        ];
+        for command in commands {
+            let json = serde_json::to_string(&command).unwrap();
+            assert_eq!(
+                serde_json::from_str::<PlayerCommand>(&json).unwrap(),
+                command
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&PlayerCommand::Repair { square: (1, -1) }).unwrap(),
+            r#"{"command":"repair","square":[1,-1]}"#
+        );
+    }
+}
@@ -7,7 +7,12 @@ use bevy_egui::{
 
 use bevy::prelude::*;
 
-use crate::{menu::menu::make_window, AppState};
+use crate::{
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayClock, GameplayStage},
+    menu::menu::make_window,
+    stats::game_stats::GameStats,
+    AppState,
+};
 
 use super::resource_images::{self, register_resource_images, ResourceImages};
 
@@ -150,6 +155,25 @@ impl ResourceSet {
             }
         });
     }
+    // The difference, with anything that would go negative at 0. Used for the price of upgrades
+    pub fn saturating_sub(&self, rhs: &Self) -> Self {
+        let vec = self
+            .vec
+            .iter()
+            .enumerate()
+            .map(|(i, (r, n))| (*r, n.saturating_sub(rhs.vec.get(i).map_or(0, |x| x.1))))
+            .collect::<Vec<_>>();
+        Self { vec }
+    }
+    // Every amount times the factor, rounded up so that nothing partial is free
+    pub fn scale(&self, factor: f32) -> Self {
+        let vec = self
+            .vec
+            .iter()
+            .map(|(r, n)| (*r, (*n as f32 * factor.max(0.)).ceil() as Amount))
+            .collect::<Vec<_>>();
+        Self { vec }
+    }
 }
 
 // The plugin that adds all the resources and their systems
@@ -187,14 +211,11 @@ impl ResourceState {
 
 impl Plugin for ResourcePlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ResourceState::new())
-            .add_system_set(
-                SystemSet::on_update(AppState::InGame)
-                    .with_system(resource_generation)
-                    .with_system(resource_ui),
-            )
-            .init_resource::<ResourceImages>()
-            .add_startup_system(register_resource_images);
+        // The resource window and its icons are part of the UIPlugin
+        app.insert_resource(ResourceState::new()).add_system_set_to_stage(
+            GameplayStage,
+            on_gameplay_tick().with_system(resource_generation),
+        );
     }
 }
 
@@ -207,6 +228,10 @@ pub enum ResourceType {
     Crystal,
 }
 
+impl ResourceType {
+    pub const ALL: [ResourceType; 3] = [ResourceType::Ore, ResourceType::Gas, ResourceType::Crystal];
+}
+
 // Used for the format! macro, not for ui displaying
 impl Display for ResourceType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -256,12 +281,16 @@ impl ResourceGenerator {
 pub fn resource_generation(
     mut resource_state: ResMut<ResourceState>,
     mut generators: Query<&mut ResourceGenerator>,
-    time: Res<Time>,
+    mut stats: ResMut<GameStats>,
+    time: Res<GameplayClock>,
 ) {
     for mut generator in generators.iter_mut() {
         generator.timer.tick(time.delta());
         if generator.timer.finished() {
             resource_state.add(generator.resource_type, generator.amount);
+            stats
+                .resources_earned
+                .add(generator.resource_type, generator.amount as u32);
 
             generator.timer.reset();
         }
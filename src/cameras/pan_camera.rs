@@ -15,6 +15,7 @@ use bevy_rapier3d::na::{clamp, Quaternion};
 
 use crate::{
     cameras::get_world_point_from_screen::get_plane_point_from_mouse_pos,
+    controls::controls::ActionInput,
     ui::ui::{UIMode, UIState},
 };
 
@@ -93,7 +94,8 @@ pub fn pan_orbit_camera(
     _windows: Res<Windows>,
     mut ev_motion: EventReader<MouseMotion>,
     mut ev_scroll: EventReader<MouseWheel>,
-    mut ev_keybd: EventReader<KeyboardInput>,
+    actions: Res<ActionInput>,
+    time: Res<Time>,
     mut mouse_pos: EventReader<CursorMoved>,
     input_mouse: Res<Input<MouseButton>>,
     windows: Res<Windows>,
@@ -109,7 +111,7 @@ pub fn pan_orbit_camera(
 
     let horizontal_orbit: f32 = 0.0;
     let mut scroll = 0.0;
-    let key_vec = get_keybd_vec(&mut ev_keybd);
+    let key_vec = get_keybd_vec(&actions);
 
     if input_mouse.pressed(pan_button) {
         // Pan only if we're not rotating at the moment
@@ -131,7 +133,8 @@ pub fn pan_orbit_camera(
         // The else makes the maths more robust, because zooming and panning at the same time creates issues
         if let Some(vec) = key_vec {
             let r = get_quaternion_y_rotation(transform.rotation);
-            transform.translation += r.mul_vec3(Vec3::new(vec.x, 0.0, vec.y) * 0.3)
+            transform.translation +=
+                r.mul_vec3(Vec3::new(vec.x, 0.0, vec.y) * KEYBOARD_PAN_SPEED * time.delta_seconds())
         } else if pan_delta.length_squared() > 0.0 && m_pos.length_squared() > 0.0 {
             let screen_size_half = get_primary_window_size(&windows) / 2.0;
 
@@ -196,6 +199,9 @@ pub fn pan_orbit_camera(
     }
 }
 
+// How fast the camera moves when using the keyboard/gamepad, in units per second
+const KEYBOARD_PAN_SPEED: f32 = 12.0;
+
 const ZOOM_AT_MAX_TILT: f32 = 0.5;
 
 const MAX_TILT: f32 = 0.3 * PI;
@@ -1,7 +1,6 @@
-use bevy::{
-    input::{keyboard::KeyboardInput, mouse::MouseWheel},
-    prelude::*,
-};
+use bevy::{input::mouse::MouseWheel, prelude::*};
+
+use crate::controls::controls::ActionInput;
 
 use super::utils::get_keybd_vec;
 
@@ -22,12 +21,11 @@ pub fn _spawn_camera(mut commands: Commands) {
 
 pub fn _move_camera(
     mut cam_query: Query<&mut Transform, With<TopDownCamera>>,
-    mut keybd_events: EventReader<KeyboardInput>,
+    actions: Res<ActionInput>,
     mut mouse_scroll: EventReader<MouseWheel>,
     // mut mouse_clicks: EventReader<MouseButton>,
 ) {
-    let keybd_vec = get_keybd_vec(&mut keybd_events);
-    // keybd_events.clear();
+    let keybd_vec = get_keybd_vec(&actions);
 
     if let Some(vec) = keybd_vec {
         for mut transform in cam_query.iter_mut() {
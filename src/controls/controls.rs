@@ -0,0 +1,419 @@
+use bevy::{input::InputSystem, prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::settings::config_file::{load_config, save_config};
+
+// The input action layer.
+// Systems never read the keyboard or gamepad directly, they ask ActionInput whether an Action is pressed.
+// Which keys and buttons trigger which action is defined by KeyBindings, which the player can change in the Controls menu.
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(KeyBindings::load())
+            .init_resource::<ActionInput>()
+            // Run right after bevy updates its input resources, so every system this frame sees the same actions
+            .add_system_to_stage(CoreStage::PreUpdate, update_action_input.after(InputSystem));
+    }
+}
+
+const KEY_BINDINGS_FILE: &str = "key_bindings.ron";
+
+// Everything the player can do with a key or a gamepad button
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    CameraUp,
+    CameraDown,
+    CameraLeft,
+    CameraRight,
+    DefensiveBuildings,
+    ResourceBuildings,
+    Demolish,
+    Pan,
+    // Selects the n-th building in the currently expanded building list
+    SelectBuilding(u8),
+    StartGame,
+    Instructions,
+    Pause,
+    ToggleMute,
+    Exit,
+}
+
+// How many buildings can be selected by a key in each building list
+pub const BUILDING_SLOTS: u8 = 10;
+
+impl Action {
+    // All the actions in the order they are shown in the Controls menu
+    pub fn all() -> Vec<Action> {
+        use Action::*;
+        let mut actions = vec![
+            CameraUp,
+            CameraDown,
+            CameraLeft,
+            CameraRight,
+            DefensiveBuildings,
+            ResourceBuildings,
+            Demolish,
+            Pan,
+        ];
+        actions.extend((0..BUILDING_SLOTS).map(SelectBuilding));
+        actions.extend([StartGame, Instructions, Pause, ToggleMute, Exit]);
+        return actions;
+    }
+
+    // Used for the ui
+    pub fn name(&self) -> String {
+        use Action::*;
+        match self {
+            CameraUp => "Move camera up".to_string(),
+            CameraDown => "Move camera down".to_string(),
+            CameraLeft => "Move camera left".to_string(),
+            CameraRight => "Move camera right".to_string(),
+            DefensiveBuildings => "Defensive buildings".to_string(),
+            ResourceBuildings => "Resource buildings".to_string(),
+            Demolish => "Demolish".to_string(),
+            Pan => "Pan".to_string(),
+            SelectBuilding(i) => format!("Select building {}", i + 1),
+            StartGame => "Start game".to_string(),
+            Instructions => "Instructions".to_string(),
+            Pause => "Pause".to_string(),
+            ToggleMute => "Mute/unmute".to_string(),
+            Exit => "Exit game".to_string(),
+        }
+    }
+}
+
+// The keys and buttons bound to a single action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionBinding {
+    pub action: Action,
+    pub keys: Vec<KeyCode>,
+    pub buttons: Vec<GamepadButtonType>,
+}
+
+// A vector rather than a map, so that the file keeps the menu order and is easy to edit by hand
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub bindings: Vec<ActionBinding>,
+}
+
+const NUMERALS: [KeyCode; BUILDING_SLOTS as usize] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Key0,
+];
+const NUMPADS: [KeyCode; BUILDING_SLOTS as usize] = [
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+    KeyCode::Numpad0,
+];
+
+// The default layout.
+// The camera keeps WASD and the arrows, so the build modes moved off Q/W/E/R to keys which don't collide with them
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use Action::*;
+        let binding =
+            |action: Action, keys: Vec<KeyCode>, buttons: Vec<GamepadButtonType>| ActionBinding {
+                action,
+                keys,
+                buttons,
+            };
+
+        let mut bindings = vec![
+            binding(
+                CameraUp,
+                vec![KeyCode::W, KeyCode::Up],
+                vec![GamepadButtonType::DPadUp],
+            ),
+            binding(
+                CameraDown,
+                vec![KeyCode::S, KeyCode::Down],
+                vec![GamepadButtonType::DPadDown],
+            ),
+            binding(
+                CameraLeft,
+                vec![KeyCode::A, KeyCode::Left],
+                vec![GamepadButtonType::DPadLeft],
+            ),
+            binding(
+                CameraRight,
+                vec![KeyCode::D, KeyCode::Right],
+                vec![GamepadButtonType::DPadRight],
+            ),
+            binding(
+                DefensiveBuildings,
+                vec![KeyCode::B],
+                vec![GamepadButtonType::North],
+            ),
+            binding(
+                ResourceBuildings,
+                vec![KeyCode::G],
+                vec![GamepadButtonType::West],
+            ),
+            binding(Demolish, vec![KeyCode::X], vec![GamepadButtonType::East]),
+            binding(Pan, vec![KeyCode::C], vec![GamepadButtonType::South]),
+        ];
+        bindings.extend((0..BUILDING_SLOTS).map(|i| {
+            binding(
+                SelectBuilding(i),
+                vec![NUMERALS[i as usize], NUMPADS[i as usize]],
+                vec![],
+            )
+        }));
+        bindings.extend([
+            binding(
+                StartGame,
+                vec![KeyCode::Return],
+                vec![GamepadButtonType::Start],
+            ),
+            binding(
+                Instructions,
+                vec![KeyCode::I],
+                vec![GamepadButtonType::Select],
+            ),
+            binding(Pause, vec![KeyCode::P], vec![]),
+            binding(ToggleMute, vec![KeyCode::M], vec![]),
+            binding(Exit, vec![KeyCode::Escape], vec![]),
+        ]);
+
+        KeyBindings { bindings }
+    }
+}
+
+impl KeyBindings {
+    // Loads the bindings from the config file, falling back to the default layout
+    // Actions missing from an older file get their default binding
+    pub fn load() -> Self {
+        let mut bindings = KeyBindings::default();
+        if let Some(loaded) = load_config::<KeyBindings>(KEY_BINDINGS_FILE) {
+            for b in loaded.bindings {
+                if let Some(existing) = bindings.get_mut(b.action) {
+                    *existing = b;
+                }
+            }
+        }
+        bindings
+    }
+
+    pub fn save(&self) {
+        save_config(KEY_BINDINGS_FILE, self);
+    }
+
+    pub fn get(&self, action: Action) -> Option<&ActionBinding> {
+        self.bindings.iter().find(|b| b.action == action)
+    }
+
+    fn get_mut(&mut self, action: Action) -> Option<&mut ActionBinding> {
+        self.bindings.iter_mut().find(|b| b.action == action)
+    }
+
+    // Makes the key the primary key of the action.
+    // The key is taken away from any other action, so that a key never triggers two actions
+    pub fn bind_key(&mut self, action: Action, key: KeyCode) {
+        for b in self.bindings.iter_mut() {
+            b.keys.retain(|k| *k != key);
+        }
+        if let Some(b) = self.get_mut(action) {
+            b.keys.insert(0, key);
+        }
+    }
+
+    // Same as bind_key, for gamepad buttons
+    pub fn bind_button(&mut self, action: Action, button: GamepadButtonType) {
+        for b in self.bindings.iter_mut() {
+            b.buttons.retain(|x| *x != button);
+        }
+        if let Some(b) = self.get_mut(action) {
+            b.buttons.insert(0, button);
+        }
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        if let Some(b) = self.get_mut(action) {
+            b.keys.clear();
+            b.buttons.clear();
+        }
+    }
+
+    // The name of the primary key of the action, used for the "(B) Defensive buildings" labels
+    pub fn label(&self, action: Action) -> String {
+        self.get(action)
+            .and_then(|b| b.keys.first())
+            .map(key_name)
+            .unwrap_or_else(|| "-".to_string())
+    }
+
+    // Returns every key and gamepad button which is bound to more than one action.
+    // Rebinding never causes one, but the file can be edited by hand
+    pub fn conflicts(&self) -> Vec<BoundInput> {
+        let mut seen = HashSet::new();
+        let mut conflicts = Vec::new();
+        let inputs = self.bindings.iter().flat_map(|b| {
+            let keys = b.keys.iter().map(|k| BoundInput::Key(*k));
+            keys.chain(b.buttons.iter().map(|x| BoundInput::Button(*x)))
+        });
+        for input in inputs {
+            if !seen.insert(input) && !conflicts.contains(&input) {
+                conflicts.push(input);
+            }
+        }
+        conflicts
+    }
+}
+
+// Either a key or a gamepad button, for reporting conflicts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BoundInput {
+    Key(KeyCode),
+    Button(GamepadButtonType),
+}
+
+impl BoundInput {
+    pub fn name(&self) -> String {
+        match self {
+            BoundInput::Key(key) => key_name(key),
+            BoundInput::Button(button) => button_name(button),
+        }
+    }
+}
+
+// A human readable key name. KeyCode's Debug gives us "Key1" for the numerals, so we strip that prefix
+pub fn key_name(key: &KeyCode) -> String {
+    let name = format!("{:?}", key);
+    match name.strip_prefix("Key") {
+        Some(stripped) if !stripped.is_empty() => stripped.to_string(),
+        _ => name,
+    }
+}
+
+pub fn button_name(button: &GamepadButtonType) -> String {
+    format!("{:?}", button)
+}
+
+// The actions triggered this frame.
+// Mirrors the bevy Input api, so systems can simply swap Input<KeyCode> for this
+#[derive(Resource, Debug, Default)]
+pub struct ActionInput {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+}
+
+impl ActionInput {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    // Returns whether the action was just pressed and takes it away from the systems after us.
+    // A state change reruns the stage in the same frame, the new state's systems would see the same press
+    pub fn clear_just_pressed(&mut self, action: Action) -> bool {
+        self.just_pressed.remove(&action)
+    }
+}
+
+// Translates the raw keyboard and gamepad input into actions
+pub fn update_action_input(
+    bindings: Res<KeyBindings>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut actions: ResMut<ActionInput>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+
+    for b in bindings.bindings.iter() {
+        // Any connected gamepad can trigger the action
+        let pressed = keys.any_pressed(b.keys.iter().copied())
+            || buttons
+                .get_pressed()
+                .any(|x| b.buttons.contains(&x.button_type));
+        let just_pressed = keys.any_just_pressed(b.keys.iter().copied())
+            || buttons
+                .get_just_pressed()
+                .any(|x| b.buttons.contains(&x.button_type));
+
+        if pressed {
+            actions.pressed.insert(b.action);
+        }
+        if just_pressed {
+            actions.just_pressed.insert(b.action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_key_bindings {
+    use bevy::prelude::KeyCode;
+
+    use bevy::prelude::GamepadButtonType;
+
+    use super::{Action, ActionInput, BoundInput, KeyBindings};
+
+    #[test]
+    fn default_layout_has_no_conflicts() {
+        let bindings = KeyBindings::default();
+        assert_eq!(bindings.conflicts(), vec![]);
+        assert_eq!(bindings.bindings.len(), Action::all().len());
+    }
+
+    #[test]
+    fn rebinding_steals_the_key() {
+        let mut bindings = KeyBindings::default();
+        bindings.bind_key(Action::Demolish, KeyCode::W);
+
+        assert_eq!(bindings.get(Action::Demolish).unwrap().keys[0], KeyCode::W);
+        assert!(!bindings
+            .get(Action::CameraUp)
+            .unwrap()
+            .keys
+            .contains(&KeyCode::W));
+        assert_eq!(bindings.conflicts(), vec![]);
+    }
+
+    #[test]
+    fn shared_buttons_are_conflicts() {
+        let mut bindings = KeyBindings::default();
+        // Like a hand edited file would do, bind_button would take it away from Pan
+        bindings.bindings[0].buttons.push(GamepadButtonType::South);
+        bindings.bindings[1].keys.push(KeyCode::W);
+
+        assert_eq!(
+            bindings.conflicts(),
+            vec![
+                BoundInput::Key(KeyCode::W),
+                BoundInput::Button(GamepadButtonType::South)
+            ]
+        );
+    }
+
+    #[test]
+    fn a_press_can_only_be_used_once() {
+        let mut actions = ActionInput::default();
+        actions.pressed.insert(Action::Pause);
+        actions.just_pressed.insert(Action::Pause);
+
+        assert!(actions.clear_just_pressed(Action::Pause));
+        assert!(!actions.clear_just_pressed(Action::Pause));
+        assert!(!actions.just_pressed(Action::Pause));
+        assert!(actions.pressed(Action::Pause));
+    }
+}
@@ -0,0 +1 @@
+pub mod controls;
\ No newline at end of file
@@ -5,6 +5,7 @@ use bevy_tweening::{
     lens::{TransformPositionLens, TransformScaleLens},
     Animator, Delay, EaseFunction, Tween,
 };
+use serde::Deserialize;
 
 // All the different firing effects are defined here
 // This includes all the machine guns
@@ -138,7 +139,7 @@ pub struct GunFireEvent {
     pub transform: Transform,
     pub gun_type: GunType,
 }
-#[derive(Component, PartialEq, Clone, Copy, Debug)]
+#[derive(Component, PartialEq, Eq, Hash, Clone, Copy, Debug, Deserialize)]
 pub enum GunType {
     MachineGun,
     MachineGunMk2,
@@ -6,12 +6,20 @@ use bevy_egui::{
     EguiContext,
 };
 
-use crate::{menu::menu::make_window, AppStage, AppState};
+use crate::{
+    aliens::nests::NestCount, menu::menu::make_window,
+    rules::game_rules::GameRules,
+    score::score::score,
+    stats::game_stats::GameStats,
+    AppStage, AppState,
+};
+
+use super::gameplay_schedule::{GameplayClock, GameplayStage, GameplayTick};
 
 /// Handles the in game timer
 /// Bevy's time doesn't account for our custom AppState::InGame state so we need to maintain this
-/// Also handles the win condition
-/// As well as displaying the time left before victory
+/// Also handles the win condition (the other one is destroying all the nests, see aliens::nests)
+/// As well as displaying the time left before victory, which is drawn by the UIPlugin
 pub struct GameTimerPlugin;
 
 impl Plugin for GameTimerPlugin {
@@ -20,20 +28,20 @@ impl Plugin for GameTimerPlugin {
             timer: Stopwatch::new(),
         })
         .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(start_in_game_time))
-        .add_system_set(SystemSet::on_update(AppState::InGame).with_system(update_in_game_time))
+        // Before anything else in the tick, so the whole tick sees the same time
+        .add_system_to_stage(
+            GameplayStage,
+            update_in_game_time.label(GameplayTick::Start),
+        )
         .add_system_set(SystemSet::on_pause(AppState::InGame).with_system(pause_in_game_time))
         .add_system_set(SystemSet::on_resume(AppState::InGame).with_system(unpause_in_game_time))
-        .add_system_set(
-            SystemSet::on_update(AppState::InGame)
-                .with_system(win_condition)
-                .with_system(game_time_ui),
-        );
+        .add_system_set(SystemSet::on_update(AppState::InGame).with_system(win_condition));
     }
 }
 
 // Bevy's in built time doesn't account for game starting/ending/pausing,
 // Therefore we have this stopwatch which only runs in-game.
-// We can still use the Res<Time> for the delta
+// It moves on with the gameplay ticks, see gameplay_schedule
 #[derive(Resource, Clone, Debug)]
 pub struct InGameTime {
     pub timer: Stopwatch,
@@ -56,18 +64,18 @@ pub fn unpause_in_game_time(mut time: ResMut<InGameTime>) {
 
 // We have to tick the timer every time we need to read it.
 // Since this gets awkward, I just tick it every gametick instead in this system
-pub fn update_in_game_time(t: Res<Time>, mut time: ResMut<InGameTime>) {
+pub fn update_in_game_time(t: Res<GameplayClock>, mut time: ResMut<InGameTime>) {
     time.timer.tick(t.delta());
 }
 
-// If the player survives this many minutes they win the game
-pub const WIN_MINUTES: u64 = 15;
-/// used in testing
-// pub const WIN_MINUTES: u64 = 100;
-pub const WIN_TIME: Duration = Duration::from_secs(60 * WIN_MINUTES);
-
-pub fn win_condition(time: Res<InGameTime>, mut game_state: ResMut<State<AppState>>) {
-    if time.timer.elapsed() > WIN_TIME {
+// If the player survives until the rules' win time they win the game, it depends on the difficulty
+// Endless games can't be won
+pub fn win_condition(
+    time: Res<InGameTime>,
+    rules: Res<GameRules>,
+    mut game_state: ResMut<State<AppState>>,
+) {
+    if !rules.endless() && time.timer.elapsed() > rules.win_time() {
         game_state.set(AppState::Victory).unwrap();
     }
 }
@@ -79,16 +87,29 @@ pub fn pad(string: String) -> String {
         string
     }
 }
-pub fn game_time_ui(time: Res<InGameTime>, mut ctx: ResMut<EguiContext>) {
+pub fn game_time_ui(
+    time: Res<InGameTime>,
+    nests: Res<NestCount>,
+    rules: Res<GameRules>,
+    stats: Res<GameStats>,
+    mut ctx: ResMut<EguiContext>,
+) {
+    let run_score = score(time.timer.elapsed(), &stats);
     make_window(Align2::RIGHT_TOP, None).show(ctx.ctx_mut(), |ui| {
         ui.set_width(80.);
         ui.vertical_centered(|ui| {
             ui.wrap_text();
-            ui.label("Time until extraction");
-            let time = (if WIN_TIME <= time.timer.elapsed() {
+            let win_time = rules.win_time();
+            // Endless games count up, as there's no ship coming
+            let time = (if rules.endless() {
+                ui.label("Time survived");
+                time.timer.elapsed().as_secs()
+            } else if win_time <= time.timer.elapsed() {
+                ui.label("Time until extraction");
                 0
             } else {
-                (WIN_TIME - time.timer.elapsed()).as_secs()
+                ui.label("Time until extraction");
+                (win_time - time.timer.elapsed()).as_secs()
             });
             let mins = time / 60;
             let secs = time % 60;
@@ -101,6 +122,15 @@ pub fn game_time_ui(time: Res<InGameTime>, mut ctx: ResMut<EguiContext>) {
                     })
                     .color(Color32::WHITE),
             );
+            if nests.total > 0 {
+                ui.label(format!(
+                    "Nests destroyed {}/{}",
+                    nests.destroyed, nests.total
+                ));
+            }
+            if rules.endless() {
+                ui.label(format!("Score {}", run_score));
+            }
         });
     });
 }
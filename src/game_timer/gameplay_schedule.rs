@@ -0,0 +1,450 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::schedule::ShouldRun,
+    prelude::*,
+    transform::{transform_propagate_system, TransformSystem},
+};
+use bevy_rapier3d::prelude::*;
+
+use crate::AppState;
+
+// The gameplay runs on a fixed timestep instead of once per frame.
+// Everything that decides how a game plays out (spawning, the AI player, the aliens' targeting and movement, turret targeting,
+// damage, deaths, resources and the player commands) runs in the GameplayStage, once for every GAMEPLAY_STEP of in game time.
+// A slow frame runs a few ticks, a fast one often none, so a game plays out the same at 30 and at 240 FPS.
+//
+// The systems of the ticks have to use GameplayClock for the delta, Res<Time> is still the time of the frame.
+// Events they send to each other have to be read in the same tick, a fast computer can go more frames
+// without a tick than bevy keeps events around for.
+// What's drawn in between the ticks is interpolated, see InterpolatedRotation.
+// The physics steps at the end of every tick by GAMEPLAY_STEP, see GameplayPhysicsPlugin. So the aliens only move on the ticks too.
+
+pub struct GameplaySchedulePlugin;
+
+impl Plugin for GameplaySchedulePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameplayClock>()
+            .add_stage_after(
+                CoreStage::Update,
+                GameplayStage,
+                SystemStage::parallel().with_run_criteria(gameplay_ticks),
+            )
+            .add_system_to_stage(GameplayStage, start_tick.label(GameplayTick::Start))
+            .add_system_to_stage(
+                GameplayStage,
+                end_tick
+                    .label(GameplayTick::End)
+                    .after(GameplayTick::Commands),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_rotations.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const GAMEPLAY_STEP: Duration = Duration::from_nanos(1_000_000_000 / TICKS_PER_SECOND as u64);
+// A frame longer than this only runs this much of the game, so a hitch doesn't turn into a long burst of ticks
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct GameplayStage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
+pub enum GameplayTick {
+    Start,
+    // Applying the PlayerCommands, the rest of the tick sees what they did.
+    // Whatever sends them in the tick (the AI player, the replays) goes between Start and this
+    Commands,
+    // The systems of on_gameplay_tick
+    Update,
+    // Stepping the physics with the velocities the tick left
+    Physics,
+    End,
+}
+
+// The SystemSet for the systems of the ticks, add it to the GameplayStage.
+// It's the tick version of SystemSet::on_update(AppState::InGame).
+// Systems in it that touch the same things need an order between them, or the order can change from one game to the next
+pub fn on_gameplay_tick() -> SystemSet {
+    SystemSet::new()
+        .label(GameplayTick::Update)
+        .after(GameplayTick::Commands)
+        .before(GameplayTick::Physics)
+        .before(GameplayTick::End)
+}
+
+// The physics, stepped once a tick by GAMEPLAY_STEP instead of once a frame by the frame time.
+// Add it instead of RapierPhysicsPlugin.
+// The stages rapier would add are run as part of the tick, after the systems of the tick set the velocities
+pub struct GameplayPhysicsPlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
+enum PhysicsTick {
+    DetectDespawn,
+    Propagate,
+    SyncBackend,
+    StepSimulation,
+    Writeback,
+}
+
+impl Plugin for GameplayPhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        let systems = RapierPhysicsPlugin::<NoUserData>::get_systems;
+        app.add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false),
+        )
+        .insert_resource(RapierConfiguration {
+            timestep_mode: TimestepMode::Fixed {
+                dt: GAMEPLAY_STEP.as_secs_f32(),
+                substeps: 1,
+            },
+            ..default()
+        })
+        // What died in the last tick is taken out before the step, not at the end of the frame
+        .add_system_set_to_stage(
+            GameplayStage,
+            systems(PhysicsStages::DetectDespawn)
+                .label(PhysicsTick::DetectDespawn)
+                .label(GameplayTick::Physics)
+                .after(GameplayTick::Update),
+        )
+        // Rapier reads the GlobalTransforms, which bevy only updates once a frame.
+        // The aliens spawned and turned in the tick have to be there for the step, not just the first tick of the frame
+        .add_system_to_stage(
+            GameplayStage,
+            transform_propagate_system
+                .label(PhysicsTick::Propagate)
+                .label(GameplayTick::Physics)
+                .after(PhysicsTick::DetectDespawn),
+        )
+        .add_system_set_to_stage(
+            GameplayStage,
+            systems(PhysicsStages::SyncBackend)
+                .label(PhysicsTick::SyncBackend)
+                .label(GameplayTick::Physics)
+                .after(PhysicsTick::Propagate),
+        )
+        .add_system_set_to_stage(
+            GameplayStage,
+            systems(PhysicsStages::StepSimulation)
+                .label(PhysicsTick::StepSimulation)
+                .label(GameplayTick::Physics)
+                .after(PhysicsTick::SyncBackend),
+        )
+        .add_system_set_to_stage(
+            GameplayStage,
+            systems(PhysicsStages::Writeback)
+                .label(PhysicsTick::Writeback)
+                .label(GameplayTick::Physics)
+                .after(PhysicsTick::StepSimulation)
+                .before(GameplayTick::End),
+        )
+        // Anything despawned outside of the ticks, like everything at the end of a game
+        .add_stage_before(
+            CoreStage::Last,
+            PhysicsStages::DetectDespawn,
+            SystemStage::parallel().with_system_set(systems(PhysicsStages::DetectDespawn)),
+        );
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct GameplayClock {
+    // The frame time that hasn't been ticked yet
+    accumulator: Duration,
+    // Set while the stage goes through the ticks of a frame
+    looping: bool,
+    // Ticks since the app started
+    pub ticks: u64,
+}
+
+impl GameplayClock {
+    // Named like the ones of Res<Time>, so the systems of the ticks read the same
+    pub fn delta(&self) -> Duration {
+        GAMEPLAY_STEP
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        GAMEPLAY_STEP.as_secs_f32()
+    }
+
+    // How far the frame is from the last tick to the next one, from 0 to 1
+    pub fn overstep(&self) -> f32 {
+        self.accumulator.as_secs_f32() / GAMEPLAY_STEP.as_secs_f32()
+    }
+
+    pub fn advance(&mut self, frame: Duration) {
+        self.accumulator = (self.accumulator + frame).min(MAX_FRAME_TIME);
+    }
+
+    // Takes the time of a tick out of the frame time, if there's enough of it left
+    pub fn tick(&mut self) -> bool {
+        if self.accumulator < GAMEPLAY_STEP {
+            return false;
+        }
+        self.accumulator -= GAMEPLAY_STEP;
+        self.ticks += 1;
+        return true;
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+        self.looping = false;
+    }
+}
+
+// The run criteria of the GameplayStage, the stage runs once for every tick the frame has time for.
+// It's checked again after every tick, only the first check of a frame adds the frame time
+pub fn gameplay_ticks(
+    time: Res<Time>,
+    state: Res<State<AppState>>,
+    mut clock: ResMut<GameplayClock>,
+) -> ShouldRun {
+    // Nothing ticks in the menus or while paused, and the time doesn't carry over
+    if state.current() != &AppState::InGame {
+        clock.reset();
+        return ShouldRun::No;
+    }
+    if !clock.looping {
+        clock.advance(time.delta());
+    }
+    clock.looping = clock.tick();
+    match clock.looping {
+        true => ShouldRun::YesAndCheckAgain,
+        false => ShouldRun::No,
+    }
+}
+
+// For rotations that change on the ticks, like the turrets turning to their targets.
+// The turn of the last tick is spread out over the frames until the next one, so the turning is as smooth as the frame rate allows.
+// It's an offset on top of the rotation of the tick, so the animations that turn the model locally (the laser gun tilting) keep working
+#[derive(Component, Debug, Clone, Copy)]
+pub struct InterpolatedRotation {
+    // The rotation at the start of the tick
+    before: Quat,
+    // How much the last tick turned it
+    turn: Quat,
+    // What the drawn rotation is off from the rotation of the tick
+    offset: Quat,
+}
+
+impl Default for InterpolatedRotation {
+    fn default() -> Self {
+        InterpolatedRotation {
+            before: Quat::IDENTITY,
+            turn: Quat::IDENTITY,
+            offset: Quat::IDENTITY,
+        }
+    }
+}
+
+// Puts back the rotations of the last tick, the systems of the tick work on them and not on the drawn ones
+fn start_tick(mut rotations: Query<(&mut Transform, &mut InterpolatedRotation)>) {
+    for (mut transform, mut rotation) in rotations.iter_mut() {
+        if rotation.offset != Quat::IDENTITY {
+            transform.rotation = (rotation.offset.inverse() * transform.rotation).normalize();
+            rotation.offset = Quat::IDENTITY;
+        }
+        rotation.before = transform.rotation;
+    }
+}
+
+fn end_tick(mut rotations: Query<(&Transform, &mut InterpolatedRotation)>) {
+    for (transform, mut rotation) in rotations.iter_mut() {
+        rotation.turn = transform.rotation * rotation.before.inverse();
+    }
+}
+
+// Draws the rotations somewhere between the last two ticks, depending on how far the frame is to the next one
+pub fn interpolate_rotations(
+    clock: Res<GameplayClock>,
+    mut rotations: Query<(&mut Transform, &mut InterpolatedRotation)>,
+) {
+    let t = clock.overstep();
+    for (mut transform, mut rotation) in rotations.iter_mut() {
+        let offset = rotation.turn.inverse().slerp(Quat::IDENTITY, t);
+        // Standing turrets don't need to be marked as changed every frame
+        if offset != rotation.offset {
+            transform.rotation =
+                (offset * rotation.offset.inverse() * transform.rotation).normalize();
+            rotation.offset = offset;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_gameplay_schedule {
+    use std::time::Duration;
+
+    use bevy::{prelude::*, utils::Instant};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        aliens::{
+            alien::{
+                alien_spawning_randomize_angle, spawn_aliens, AlienCount, AlienSpawnAngle,
+                AlienSpawnEvent, SpawnRateMultiplier,
+            },
+            alien_kinds::{AlienBehavior, AlienKind, AlienTemplate, AlienTemplates},
+            targeting::TargetPreference,
+        },
+        buildings::grid::Grid,
+        game_timer::game_timer::{update_in_game_time, InGameTime},
+        rules::game_rules::{Difficulty, GameRng, GameRules},
+        AppState,
+    };
+
+    use super::{
+        end_tick, interpolate_rotations, on_gameplay_tick, start_tick, GameplayClock,
+        GameplaySchedulePlugin, GameplayStage, GameplayTick, InterpolatedRotation, GAMEPLAY_STEP,
+    };
+
+    #[test]
+    fn frames_are_split_into_ticks() {
+        let mut clock = GameplayClock::default();
+        // 30 FPS, two ticks a frame
+        clock.advance(GAMEPLAY_STEP * 2);
+        assert!(clock.tick());
+        assert!(clock.tick());
+        assert!(!clock.tick());
+
+        // 120 FPS, a tick every other frame
+        let frame = GAMEPLAY_STEP / 2;
+        let ticked = (0..6)
+            .map(|_| {
+                clock.advance(frame);
+                clock.tick()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ticked, [false, true, false, true, false, true]);
+        assert_eq!(clock.ticks, 5);
+
+        // A hitch doesn't have to be caught up with
+        clock.reset();
+        clock.advance(Duration::from_secs(5));
+        let mut ticks = 0;
+        while clock.tick() {
+            ticks += 1;
+        }
+        assert_eq!(ticks, 15);
+    }
+
+    #[test]
+    fn turns_are_spread_over_the_frames() {
+        let mut world = World::new();
+        world.init_resource::<GameplayClock>();
+        let e = world
+            .spawn((Transform::default(), InterpolatedRotation::default()))
+            .id();
+        let rotation = |world: &World| world.get::<Transform>(e).unwrap().rotation;
+
+        // A tick turns it by 1 radian
+        SystemStage::single(start_tick).run(&mut world);
+        world.get_mut::<Transform>(e).unwrap().rotate_y(1.);
+        SystemStage::single(end_tick).run(&mut world);
+
+        // Right after the tick it's drawn where it was before it, half way to the next one it's turned half way
+        SystemStage::single(interpolate_rotations).run(&mut world);
+        assert!(rotation(&world).angle_between(Quat::IDENTITY) < 0.001);
+        world
+            .resource_mut::<GameplayClock>()
+            .advance(GAMEPLAY_STEP / 2);
+        SystemStage::single(interpolate_rotations).run(&mut world);
+        assert!(rotation(&world).angle_between(Quat::from_rotation_y(0.5)) < 0.001);
+
+        // The laser gun tilts in between, the next tick starts from the turn of the last one with the tilt kept
+        world.get_mut::<Transform>(e).unwrap().rotate_local_x(0.2);
+        SystemStage::single(start_tick).run(&mut world);
+        let expected = Quat::from_rotation_y(1.) * Quat::from_rotation_x(0.2);
+        assert!(rotation(&world).angle_between(expected) < 0.001);
+    }
+
+    #[derive(Resource, Default)]
+    struct SpawnHistory(Vec<u32>);
+
+    fn record_spawns(count: Res<AlienCount>, mut history: ResMut<SpawnHistory>) {
+        history.0.push(count.count);
+    }
+
+    // Plays the spawning of a game headless at the given frame rate and returns the alien count after every tick
+    fn spawns_at(fps: u32, ticks: usize) -> Vec<u32> {
+        let frame = Duration::from_secs(1) / fps;
+        let mut now = Instant::now();
+        let mut time = Time::default();
+        time.update_with_instant(now);
+
+        let drone = AlienTemplate {
+            kind: AlienKind::Drone,
+            name: "Drone",
+            health: 100,
+            speed: 5.,
+            damage: 10,
+            cooldown: 1000,
+            range: 2.,
+            behavior: AlienBehavior::Melee,
+            preference: TargetPreference::default(),
+            half_height: 0.4,
+            radius: 0.3,
+            scene_handle: Handle::default(),
+            scene_offset: Transform::default(),
+            tint: Color::WHITE,
+            spawn_weight: 1.,
+            appears_after: Duration::ZERO,
+        };
+
+        let mut app = App::new();
+        app.add_state(AppState::InGame)
+            .insert_resource(time)
+            .add_plugin(GameplaySchedulePlugin)
+            .insert_resource(GameRules {
+                difficulty: Difficulty::Nightmare,
+                ..default()
+            })
+            .insert_resource(GameRng(StdRng::seed_from_u64(3)))
+            .insert_resource(Grid::new())
+            .insert_resource(AlienTemplates {
+                templates: vec![drone],
+            })
+            .insert_resource(InGameTime {
+                timer: Default::default(),
+            })
+            .init_resource::<AlienCount>()
+            .init_resource::<AlienSpawnAngle>()
+            .init_resource::<SpawnRateMultiplier>()
+            .init_resource::<SpawnHistory>()
+            .add_event::<AlienSpawnEvent>()
+            .add_system_to_stage(
+                GameplayStage,
+                update_in_game_time.label(GameplayTick::Start),
+            )
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick()
+                    .with_system(alien_spawning_randomize_angle)
+                    .with_system(spawn_aliens.after(alien_spawning_randomize_angle))
+                    .with_system(record_spawns.after(spawn_aliens)),
+            );
+
+        while app.world.resource::<SpawnHistory>().0.len() < ticks {
+            now += frame;
+            app.world.resource_mut::<Time>().update_with_instant(now);
+            app.update();
+        }
+        let mut history = app.world.remove_resource::<SpawnHistory>().unwrap().0;
+        history.truncate(ticks);
+        return history;
+    }
+
+    #[test]
+    fn spawning_does_not_depend_on_the_frame_rate() {
+        // A minute and a half of Nightmare
+        let ticks = 90 * 60;
+        let slow = spawns_at(30, ticks);
+        let fast = spawns_at(240, ticks);
+        assert!(slow[ticks - 1] > 50);
+        assert_eq!(slow, fast);
+    }
+}
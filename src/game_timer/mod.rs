@@ -1 +1,2 @@
-pub mod game_timer;
\ No newline at end of file
+pub mod game_timer;
+pub mod gameplay_schedule;
\ No newline at end of file
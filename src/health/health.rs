@@ -1,5 +1,24 @@
 use bevy::prelude::*;
 
+use crate::{
+    aliens::alien_kinds::exploder_detonation,
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayClock, GameplayStage},
+};
+
+// Health management
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DeathEvent>()
+            .add_event::<DamageEvent>()
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick().with_system(death_timers.after(exploder_detonation)),
+            );
+    }
+}
+
 #[derive(Component, Debug, Clone)]
 pub struct Health {
     pub max_hp: i32,
@@ -24,6 +43,13 @@ impl Health {
     }
 }
 
+// Sent when the entity takes damage from a DamageDealing entity
+pub struct DamageEvent {
+    pub entity: Entity,
+    pub attacker: Entity,
+    pub damage: i32,
+}
+
 // Sent when the entity dies
 pub struct DeathEvent {
     pub entity: Entity,
@@ -32,9 +58,10 @@ pub struct DeathEvent {
 }
 
 // Ticks and starts the alien death timers
-// so that we don't have to remember to tick the death timer every time we check it
+// so that we don't have to remember to tick the death timer every time we check it.
+// Runs on the gameplay ticks, after everything that can kill
 pub fn death_timers(
-    time: Res<Time>,
+    time: Res<GameplayClock>,
     mut query: Query<&mut Health>,
     mut ev: EventReader<DeathEvent>,
 ) {
@@ -0,0 +1,45 @@
+#![allow(unused_imports, unused_parens)]
+// All of the game lives in this library, so the game in main.rs and the tools in src/bin can share it
+use bevy::prelude::*;
+
+pub mod aliens;
+pub mod audio;
+pub mod balance;
+pub mod bot;
+pub mod buildings;
+pub mod cameras;
+pub mod controls;
+pub mod effects;
+pub mod game_timer;
+pub mod health;
+pub mod main_base;
+pub mod map;
+pub mod menu;
+pub mod replay;
+pub mod rules;
+pub mod score;
+pub mod settings;
+pub mod spatial;
+pub mod stats;
+pub mod telemetry;
+pub mod ui;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum AppState {
+    MainMenu,
+    // Picking the difficulty and mutators before the game starts
+    GameSetup,
+    InGame,
+    GameOver,
+    Victory,
+    Paused,
+    Instructions,
+    Controls,
+    Settings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
+pub enum AppStage {
+    // Anything that sets up a Res object to add Handles to it.
+    RegisterResources,
+}
@@ -1,58 +1,42 @@
 #![allow(unused_imports, unused_parens)]
 use std::f32::consts::PI;
 
-use aliens::alien::{Alien, AlienPlugin};
-use audio::audio::MyAudioPlugin;
+use bevy_game::aliens::alien::{Alien, AlienPlugin};
+use bevy_game::audio::audio::MyAudioPlugin;
+use bevy_game::bot::ai_player::AiPlayerPlugin;
 use bevy::pbr::DirectionalLightShadowMap;
 use bevy::prelude::*;
 use bevy_rapier3d::na::Point;
 use bevy_rapier3d::prelude::*;
 
 use bevy_rapier3d::prelude::{Collider, CollisionGroups, Group, LockedAxes, RigidBody, Velocity};
-use buildings::building_bundles::{register_defensive, BuildingTemplates, BuildingTemplatesPlugin};
-use buildings::defensive_buildings::DefensiveBuildingPlugin;
-use buildings::grid::{Grid, SQUARE_SIZE};
-use buildings::resources::ResourcePlugin;
-use cameras::get_world_point_from_screen::{emit_world_click_events, WorldClickEvent};
-use cameras::pan_camera::{pan_orbit_camera, spawn_camera};
-use effects::effects::ParticlePlugin;
+use bevy_game::buildings::building_bundles::{register_defensive, BuildingTemplates, BuildingTemplatesPlugin};
+use bevy_game::buildings::defensive_buildings::DefensiveBuildingPlugin;
+use bevy_game::buildings::grid::{Grid, SQUARE_SIZE};
+use bevy_game::buildings::player_commands::PlayerCommandsPlugin;
+use bevy_game::buildings::resources::ResourcePlugin;
+use bevy_game::cameras::get_world_point_from_screen::{emit_world_click_events, WorldClickEvent};
+use bevy_game::cameras::pan_camera::{pan_orbit_camera, spawn_camera};
+use bevy_game::controls::controls::ControlsPlugin;
+use bevy_game::effects::effects::ParticlePlugin;
 
-use game_timer::game_timer::GameTimerPlugin;
-use health::health::{death_timers, DeathEvent};
-use main_base::main_base::{handle_main_base_gameover, spawn_main_base};
-use map::map::generate_map;
-use menu::menu::MenuPlugin;
-use ui::ui::UIPlugin;
+use bevy_game::game_timer::game_timer::GameTimerPlugin;
+use bevy_game::game_timer::gameplay_schedule::{GameplayPhysicsPlugin, GameplaySchedulePlugin};
+use bevy_game::health::health::HealthPlugin;
+use bevy_game::main_base::main_base::{MainBaseComponent, MainBasePlugin};
+use bevy_game::map::map::MapPlugin;
+use bevy_game::replay::{playback::ReplayPlaybackPlugin, replay::ReplayRecordingPlugin};
+use bevy_game::menu::menu::MenuPlugin;
+use bevy_game::rules::game_rules::GameRulesPlugin;
+use bevy_game::score::score::ScorePlugin;
+use bevy_game::settings::settings::{GameSettings, SettingsPlugin};
+use bevy_game::spatial::spatial_index::SpatialIndexPlugin;
+use bevy_game::stats::game_stats::StatsPlugin;
+use bevy_game::telemetry::telemetry::TelemetryPlugin;
+use bevy_game::ui::ui::UIPlugin;
 
-use crate::map::map::MAP_SIZE;
-mod cameras;
-
-mod aliens;
-mod audio;
-mod buildings;
-mod effects;
-mod game_timer;
-mod health;
-mod main_base;
-mod map;
-mod menu;
-mod ui;
-
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub enum AppState {
-    MainMenu,
-    InGame,
-    GameOver,
-    Victory,
-    Paused,
-    Instructions,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
-enum AppStage {
-    // Anything that sets up a Res object to add Handles to it.
-    RegisterResources,
-}
+use bevy_game::map::map::MAP_SIZE;
+use bevy_game::{AppStage, AppState};
 
 fn main() {
     // let mut wgpu_settings = WgpuSettings::default();
@@ -60,24 +44,35 @@ fn main() {
     //     .features
     //     .set(VERTEX_WRITABLE_STORAGE, true);
 
+    // The settings are needed before the window gets created
+    let settings = GameSettings::load();
+
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
-            window: WindowDescriptor {
-                mode: WindowMode::BorderlessFullscreen,
-                ..Default::default()
-            },
+            window: settings.window_descriptor(),
             ..Default::default()
         }))
-        .insert_resource(Msaa::default())
-        .insert_resource(DirectionalLightShadowMap { size: 8_000 })
+        .insert_resource(Msaa {
+            samples: settings.msaa_samples,
+        })
+        .insert_resource(DirectionalLightShadowMap {
+            size: settings.shadow_quality.shadow_map_size(),
+        })
+        .insert_resource(settings)
+        .add_plugin(SettingsPlugin)
         // .insert_resource(WindowDescriptor{
         //     mode: WindowMode::BorderlessFullscreen,
         //     ..Default::default()
         // })
         .add_state(AppState::MainMenu)
+        // The fixed timestep the gameplay runs on, before any of the plugins that add to it
+        .add_plugin(GameplaySchedulePlugin)
         //
-        // Physics
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        // Key bindings and the input actions
+        .add_plugin(ControlsPlugin)
+        //
+        // Physics, stepped on the gameplay ticks
+        .add_plugin(GameplayPhysicsPlugin)
         .add_plugin(RapierDebugRenderPlugin::default())
         .add_event::<CollisionEvent>()
         //
@@ -94,36 +89,47 @@ fn main() {
         .add_plugin(ParticlePlugin)
         .add_plugin(DefensiveBuildingPlugin)
         .add_plugin(UIPlugin)
+        // Building, demolishing, repairs and upgrades, from the player and the AI player alike
+        .add_plugin(PlayerCommandsPlugin)
+        // Plays the game by itself when turned on in the game setup
+        .add_plugin(AiPlayerPlugin)
         // Aliens
         .add_plugin(AlienPlugin)
+        // Lookups of the aliens and their targets by position
+        .add_plugin(SpatialIndexPlugin)
         // Resource management
         .add_plugin(ResourcePlugin)
         // Audio
         .add_plugin(MyAudioPlugin)
         // GameTimer
         .add_plugin(GameTimerPlugin)
+        // Difficulty and mutators
+        .add_plugin(GameRulesPlugin)
+        // Endless mode scores and the leaderboard
+        .add_plugin(ScorePlugin)
+        // Statistics of the run for the end screens
+        .add_plugin(StatsPlugin)
+        // Opt-in gameplay event log for balancing
+        .add_plugin(TelemetryPlugin)
+        // Every game is recorded and can be played back from the main menu
+        .add_plugin(ReplayRecordingPlugin)
+        .add_plugin(ReplayPlaybackPlugin)
         //
         // Health management
-        .add_event::<DeathEvent>()
-        .add_system_set(SystemSet::on_update(AppState::InGame).with_system(death_timers))
+        .add_plugin(HealthPlugin)
         //
         // Main menu as well as any other state changing menus
         .add_plugin(MenuPlugin)
         //
         // Setup and testing
         .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(setup))
-        .add_system_set(
-            // Any in game systems
-            SystemSet::on_update(AppState::InGame).with_system(handle_main_base_gameover),
-        )
-        .add_system_set(
-            // Any map initialization
-            SystemSet::on_enter(AppState::InGame)
-                .after(AppStage::RegisterResources)
-                .with_system(spawn_main_base)
-                .with_system(generate_map), // .with_system(testing_buildings),
-        )
+        // The game is lost when the main base falls
+        .add_plugin(MainBasePlugin)
+        // Any map initialization
+        .add_plugin(MapPlugin)
         .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(cleanup))
+        // Quitting from the pause menu goes straight back to the main menu
+        .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(cleanup_game))
         // .add_startup_system_to_stage(StartupStage::PostStartup, testing_buildings)
         .run();
 }
@@ -139,6 +145,19 @@ pub fn cleanup(entities: Query<Entity, Without<Camera3d>>, mut commands: Command
     }
 }
 
+// The main menu is also entered once at startup, when there's no game to clean up.
+// Anything spawned by the startup systems has to survive that
+pub fn cleanup_game(
+    main_base: Query<(), With<MainBaseComponent>>,
+    entities: Query<Entity, Without<Camera3d>>,
+    commands: Commands,
+) {
+    if main_base.is_empty() {
+        return;
+    }
+    cleanup(entities, commands);
+}
+
 fn _testing_buildings(
     building_templates: Res<BuildingTemplates>,
     mut commands: Commands,
@@ -156,6 +175,7 @@ fn _testing_buildings(
 
 fn setup(
     mut commands: Commands,
+    settings: Res<GameSettings>,
     // mut meshes: ResMut<Assets<Mesh>>,
     // mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
@@ -168,7 +188,7 @@ fn setup(
         ),
         directional_light: DirectionalLight {
             illuminance: 40_000.,
-            shadows_enabled: true,
+            shadows_enabled: settings.shadow_quality.shadows_enabled(),
             ..Default::default()
         },
         ..Default::default()
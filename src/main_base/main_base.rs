@@ -1,5 +1,5 @@
 use bevy::prelude::*;
-use bevy_egui::EguiContext;
+use bevy_egui::egui::TextureId;
 use bevy_rapier3d::prelude::Collider;
 
 use crate::{
@@ -16,6 +16,16 @@ use crate::{
     AppState,
 };
 
+pub struct MainBasePlugin;
+
+impl Plugin for MainBasePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::InGame).with_system(handle_main_base_gameover),
+        );
+    }
+}
+
 // Marker component
 #[derive(Clone, Copy, Debug, Component, PartialEq)]
 pub struct MainBaseComponent;
@@ -24,13 +34,13 @@ pub struct MainBaseComponent;
 pub fn register_main_base(
     mut templates: ResMut<BuildingTemplates>,
     ass: Res<AssetServer>,
-    mut ctx: ResMut<EguiContext>,
 ) {
     let b = Building {
         show_in_menu: false,
         building_info: BuildingInfoComponent {
             name: "Main base",
-            image: ctx.add_image(ass.load("spacekit_2/Isometric/hangar_largeA_SW.png")),
+            icon: ass.load("spacekit_2/Isometric/hangar_largeA_SW.png"),
+            image: TextureId::default(),
             description: "",
         },
         bundle: BuildingBundle::GENERATOR(GeneratorBuildingBundle {
@@ -47,6 +57,7 @@ pub fn register_main_base(
             translation: Vec3::new(-1.6, 0.0, -1.3),
             ..Default::default()
         },
+        upgrades_to: None,
     };
 
     templates.templates.push(b);
@@ -66,6 +77,11 @@ pub fn spawn_main_base(
         .build(&mut commands, Vec3::splat(0.))
         .unwrap();
 
+    // The buildings of the last game are gone. The version keeps counting so the alien paths still notice the change
+    *grid = Grid {
+        version: grid.version + 1,
+        ..Grid::new()
+    };
     // Block 4 squares in the center as the base is larger than a single square
     for x in -1..=0 {
         for y in -1..=0 {
@@ -1,6 +1,26 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::{Collider, Friction};
-use rand::{rngs::ThreadRng, thread_rng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    aliens::nests::spawn_nests, main_base::main_base::spawn_main_base,
+    rules::game_rules::GameRules, AppStage, AppState,
+};
+
+// Any map initialization, every game starts on a new map
+pub struct MapPlugin;
+
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::InGame)
+                .after(AppStage::RegisterResources)
+                .with_system(spawn_main_base)
+                .with_system(generate_map)
+                .with_system(spawn_nests),
+        );
+    }
+}
 
 
 pub const MAP_SIZE: f32 = 200.;
@@ -8,8 +28,7 @@ pub const MAP_SIZE: f32 = 200.;
 
 // A possible improvement would be to use some noise or sth to get better distributed random values
 // Currently we just toss a coin for each of the requested positions
-fn get_random_coordinates(count: u32) -> Vec<(f32, f32)> {
-    let mut rng = rand::thread_rng();
+fn get_random_coordinates(rng: &mut impl Rng, count: u32) -> Vec<(f32, f32)> {
     let mut n = || rng.gen::<f32>() * MAP_SIZE * 2. - MAP_SIZE;
     (0..count).map(|_| (n(), n())).collect::<Vec<_>>()
 }
@@ -18,7 +37,7 @@ fn get_random_coordinates(count: u32) -> Vec<(f32, f32)> {
 const MAP_COLOR: Color = Color::rgb(166. / 256., 89. / 256.,63. / 256.);
 
 // Get random member of a vector. Isn't generic because I only use it here
-fn get_random_member(rng: &mut ThreadRng, vec: &Vec<Handle<Scene>>) -> Handle<Scene> {
+fn get_random_member(rng: &mut impl Rng, vec: &Vec<Handle<Scene>>) -> Handle<Scene> {
     let n = rng.gen::<f32>();
     let i = (n * vec.len() as f32) as usize;
     return vec.get(i).unwrap().clone();
@@ -28,8 +47,10 @@ fn get_random_member(rng: &mut ThreadRng, vec: &Vec<Handle<Scene>>) -> Handle<Sc
 // Randomly spawns environment objects as well such as rocks etc
 // These don't have collisions and don't take up grid space. You can build a turret right over it.
 // Because they're mostly small enough it doesn't matter and adds to the variety
+// The same seed always gives the same map
 pub fn generate_map(
     ass: Res<AssetServer>,
+    rules: Res<GameRules>,
 
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -77,11 +98,11 @@ pub fn generate_map(
     })
     .collect::<Vec<_>>();
 
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(rules.seed);
     // Number of random map elements to spawn
     let count = 1000;
 
-    for (x, z) in get_random_coordinates(count).into_iter() {
+    for (x, z) in get_random_coordinates(&mut rng, count).into_iter() {
         commands.spawn(SceneBundle {
             scene: get_random_member(&mut rng, &assets),
             transform: Transform::from_translation(Vec3::new(x, 0., z)),
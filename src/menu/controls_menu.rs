@@ -0,0 +1,129 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2, Color32, RichText},
+    EguiContext,
+};
+
+use crate::{
+    controls::controls::{button_name, key_name, Action, KeyBindings},
+    AppState,
+};
+
+use super::menu::{make_window, set_menu_spacing};
+
+// The key rebinding screen.
+// Clicking Rebind on a row waits for the next key or gamepad button press and binds it to that action.
+// Escape cancels the wait instead, so it can only be bound by resetting to the defaults.
+// Every change is saved to the key bindings file straight away.
+
+pub fn controls_screen(
+    mut app_state: ResMut<State<AppState>>,
+    mut ctx: ResMut<EguiContext>,
+    mut bindings: ResMut<KeyBindings>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    // The action we're waiting to receive a key for
+    mut waiting_for: Local<Option<Action>>,
+) {
+    if let Some(action) = *waiting_for {
+        if keys.just_pressed(KeyCode::Escape) {
+            *waiting_for = None;
+        } else if let Some(key) = keys.get_just_pressed().next() {
+            bindings.bind_key(action, *key);
+            bindings.save();
+            *waiting_for = None;
+        } else if let Some(button) = buttons.get_just_pressed().next() {
+            bindings.bind_button(action, button.button_type);
+            bindings.save();
+            *waiting_for = None;
+        }
+    }
+
+    set_menu_spacing(&mut ctx);
+    make_window(Align2::CENTER_CENTER, None)
+        .min_width(600.)
+        .show(ctx.ctx_mut(), |ui| {
+            ui.heading("Controls");
+            if let Some(action) = *waiting_for {
+                ui.label(
+                    RichText::new(format!(
+                        "Press a key or a gamepad button for \"{}\", Escape to cancel",
+                        action.name()
+                    ))
+                    .color(Color32::WHITE),
+                );
+            } else {
+                ui.label("Binding a key that's already in use removes it from the other action.");
+            }
+
+            let conflicts = bindings.conflicts();
+            if !conflicts.is_empty() {
+                let names = conflicts.iter().map(|c| c.name()).collect::<Vec<_>>();
+                ui.label(
+                    RichText::new(format!(
+                        "Bound to more than one action: {}",
+                        names.join(", ")
+                    ))
+                    .color(Color32::RED),
+                );
+            }
+
+            egui::ScrollArea::vertical()
+                .max_height(500.)
+                .show(ui, |ui| {
+                    // The spacing from set_menu_spacing is way too large for a table
+                    ui.spacing_mut().item_spacing = egui::Vec2::new(16., 4.);
+
+                    egui::Grid::new("key_bindings")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for action in Action::all() {
+                                let binding = bindings.get(action).cloned();
+                                let (keys, buttons) = binding
+                                    .map(|b| {
+                                        (
+                                            b.keys.iter().map(key_name).collect::<Vec<_>>(),
+                                            b.buttons.iter().map(button_name).collect::<Vec<_>>(),
+                                        )
+                                    })
+                                    .unwrap_or_default();
+
+                                ui.label(action.name());
+                                ui.label(keys.join(" / "));
+                                ui.label(buttons.join(" / "));
+
+                                let rebind_text = if *waiting_for == Some(action) {
+                                    "Cancel"
+                                } else {
+                                    "Rebind"
+                                };
+                                if ui.button(rebind_text).clicked() {
+                                    *waiting_for = if *waiting_for == Some(action) {
+                                        None
+                                    } else {
+                                        Some(action)
+                                    };
+                                }
+                                if ui.button("Clear").clicked() {
+                                    bindings.clear(action);
+                                    bindings.save();
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+
+            ui.horizontal(|ui| {
+                if ui.button("Reset to defaults").clicked() {
+                    *bindings = KeyBindings::default();
+                    bindings.save();
+                    *waiting_for = None;
+                }
+                if ui.button("Back").clicked() {
+                    *waiting_for = None;
+                    // The screen is always pushed on top of the menu it was opened from
+                    app_state.pop().unwrap();
+                }
+            });
+        });
+}
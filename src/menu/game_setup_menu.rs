@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2},
+    EguiContext,
+};
+
+use crate::{
+    bot::ai_player::AiPlayer,
+    controls::controls::{Action, ActionInput, KeyBindings},
+    rules::game_rules::{Difficulty, GameMode, GameRules},
+    AppState,
+};
+
+use super::menu::{make_window, set_menu_spacing};
+
+// The screen between the main menu and the game, where the mode, the difficulty and the mutators are picked.
+// The choice is kept in GameRules, so the next game starts with the same rules
+
+pub fn game_setup_screen(
+    mut app_state: ResMut<State<AppState>>,
+    mut ctx: ResMut<EguiContext>,
+    mut rules: ResMut<GameRules>,
+    mut ai: ResMut<AiPlayer>,
+    actions: Res<ActionInput>,
+    bindings: Res<KeyBindings>,
+) {
+    // Same as the settings, edit a copy so the resource is only changed when something actually changes
+    let mut new_rules = *rules;
+    let mut ai_enabled = ai.enabled;
+
+    set_menu_spacing(&mut ctx);
+    make_window(Align2::CENTER_CENTER, None)
+        .min_width(600.)
+        .show(ctx.ctx_mut(), |ui| {
+            ui.heading("New game");
+            ui.spacing_mut().item_spacing = egui::Vec2::new(16., 8.);
+
+            ui.label("Mode");
+            ui.horizontal(|ui| {
+                for mode in GameMode::ALL {
+                    ui.selectable_value(&mut new_rules.mode, mode, mode.name());
+                }
+            });
+
+            ui.label("Difficulty");
+            ui.horizontal(|ui| {
+                for difficulty in Difficulty::ALL {
+                    ui.selectable_value(&mut new_rules.difficulty, difficulty, difficulty.name());
+                }
+            });
+            ui.label(new_rules.difficulty.description());
+            if new_rules.endless() {
+                ui.label("No rescue is coming. Hold out as long as you can for a place on the leaderboard");
+            } else {
+                ui.label(format!(
+                    "The rescue ship arrives after {} minutes",
+                    new_rules.win_minutes()
+                ));
+            }
+            ui.label(format!("Map seed {}", new_rules.seed));
+
+            ui.separator();
+            ui.label("Mutators");
+            let mutators = &mut new_rules.mutators;
+            ui.checkbox(&mut mutators.double_alien_speed, "Double alien speed");
+            ui.checkbox(
+                &mut mutators.no_refunds,
+                "No refunds for demolished buildings",
+            );
+            ui.checkbox(
+                &mut mutators.double_starting_resources,
+                "Starting resources x2",
+            );
+            ui.checkbox(&mut mutators.alien_loot, "Aliens drop ore when killed");
+
+            ui.separator();
+            ui.checkbox(
+                &mut ai_enabled,
+                format!("AI player ({} strategy builds the base for you)", ai.strategy_name()),
+            );
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let b = ui.button(format!("Start ({})", bindings.label(Action::StartGame)));
+                // Just pressed, otherwise holding the key from the main menu would skip this screen.
+                // The main menu uses up the press that opened this screen, see main_menu
+                if b.clicked() || actions.just_pressed(Action::StartGame) {
+                    app_state.set(AppState::InGame).unwrap();
+                }
+                if ui.button("Back").clicked() {
+                    app_state.set(AppState::MainMenu).unwrap();
+                }
+            });
+        });
+
+    if new_rules != *rules {
+        *rules = new_rules;
+    }
+    if ai_enabled != ai.enabled {
+        ai.enabled = ai_enabled;
+    }
+}
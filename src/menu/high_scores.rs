@@ -0,0 +1,47 @@
+use bevy_egui::egui::{self, Color32, RichText, Ui};
+
+use crate::score::leaderboard::{format_date, Leaderboard};
+
+// The leaderboard table, shown in the main menu and on the game over screen.
+// `highlight` is the rank of the run that just ended, if it made it on the table
+
+pub fn leaderboard_table(ui: &mut Ui, leaderboard: &Leaderboard, highlight: Option<usize>) {
+    if leaderboard.entries.is_empty() {
+        ui.label("No endless runs yet");
+        return;
+    }
+
+    egui::ScrollArea::vertical()
+        .max_height(300.)
+        .show(ui, |ui| {
+            egui::Grid::new("leaderboard")
+                .spacing(egui::Vec2::new(16., 4.))
+                .show(ui, |ui| {
+                    for header in ["#", "Score", "Time", "Kills", "Difficulty", "Seed", "Date"] {
+                        ui.label(header);
+                    }
+                    ui.end_row();
+
+                    for (i, entry) in leaderboard.entries.iter().enumerate() {
+                        let color = if highlight == Some(i) {
+                            Color32::WHITE
+                        } else {
+                            ui.visuals().text_color()
+                        };
+                        let cells = [
+                            (i + 1).to_string(),
+                            entry.score.to_string(),
+                            format!("{}:{:02}", entry.seconds / 60, entry.seconds % 60),
+                            entry.kills.to_string(),
+                            entry.difficulty.name().to_string(),
+                            entry.seed.to_string(),
+                            format_date(entry.date),
+                        ];
+                        for cell in cells {
+                            ui.label(RichText::new(cell).color(color));
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+}
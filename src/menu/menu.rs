@@ -5,7 +5,21 @@ use bevy_egui::{
     EguiContext,
 };
 
-use crate::{game_timer::game_timer::WIN_MINUTES, ui::ui::keys, AppState};
+use crate::{
+    bot::ai_player::AiPlayer,
+    controls::controls::{Action, ActionInput, KeyBindings},
+    replay::playback::{ReplayFiles, ReplayPlayback},
+    rules::game_rules::GameRules,
+    score::{leaderboard::Leaderboard, score::LastRun},
+    stats::game_stats::GameStats,
+    AppState,
+};
+
+use super::{
+    controls_menu::controls_screen, game_setup_menu::game_setup_screen,
+    high_scores::leaderboard_table, pause_menu::PauseMenuPlugin, replay_list::replay_list,
+    settings_menu::settings_screen, stats_screen::stats_panel,
+};
 
 // This is the main game menu that you see on the game start
 pub struct MenuPlugin;
@@ -15,11 +29,17 @@ impl Plugin for MenuPlugin {
         app.add_startup_system(set_styles)
             // .init_resource::<MainMenuState>()
             .add_system_set(SystemSet::on_update(AppState::MainMenu).with_system(main_menu))
+            .add_system_set(
+                SystemSet::on_update(AppState::GameSetup).with_system(game_setup_screen),
+            )
             .add_system_set(SystemSet::on_update(AppState::GameOver).with_system(game_over))
             .add_system_set(SystemSet::on_update(AppState::Victory).with_system(victory_screen))
             .add_system_set(
                 SystemSet::on_update(AppState::Instructions).with_system(instruction_screen),
-            );
+            )
+            .add_system_set(SystemSet::on_update(AppState::Controls).with_system(controls_screen))
+            .add_system_set(SystemSet::on_update(AppState::Settings).with_system(settings_screen))
+            .add_plugin(PauseMenuPlugin);
     }
 }
 
@@ -121,7 +141,7 @@ pub fn set_styles(mut ctx: ResMut<EguiContext>) {
 }
 
 // This function configures the ctx to have the large spacing that we use for main menus
-fn set_menu_spacing(ctx: &mut ResMut<EguiContext>) {
+pub fn set_menu_spacing(ctx: &mut ResMut<EguiContext>) {
     let margin = Margin::symmetric(80., 60.);
     let style = ctx.ctx_mut().style();
     ctx.ctx_mut().set_style(bevy_egui::egui::Style {
@@ -166,7 +186,13 @@ fn main_menu(
     mut app_state: ResMut<State<AppState>>,
     mut ctx: ResMut<EguiContext>,
     mut exit: EventWriter<AppExit>,
-    keys: Res<Input<KeyCode>>,
+    mut actions: ResMut<ActionInput>,
+    bindings: Res<KeyBindings>,
+    leaderboard: Res<Leaderboard>,
+    mut replays: ResMut<ReplayFiles>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut rules: ResMut<GameRules>,
+    mut ai: ResMut<AiPlayer>,
 ) {
     // Currently doesnt work - shelved
     // Check if font exists
@@ -206,18 +232,49 @@ fn main_menu(
                         heading("Resource Rumble");
                     });
 
-                    let b = ui.button("Start Game (Enter)");
-                    if b.clicked() || keys.pressed(keys::START_GAME) {
+                    let b = ui.button(format!(
+                        "Start Game ({})",
+                        bindings.label(Action::StartGame)
+                    ));
+                    // The press is used up here, the setup screen runs in the same frame and would start the game with it
+                    if b.clicked() || actions.clear_just_pressed(Action::StartGame) {
                         // This shouldnt throw an error as Im never changing app state anywhere else during menu
-                        app_state.set(AppState::InGame).unwrap();
+                        // The difficulty is picked before the game actually starts
+                        app_state.set(AppState::GameSetup).unwrap();
                     };
 
-                    let b = ui.button("Instructions (I)");
-                    if b.clicked() || keys.pressed(keys::INSTRUCTIONS) {
+                    let b = ui.button(format!(
+                        "Instructions ({})",
+                        bindings.label(Action::Instructions)
+                    ));
+                    if b.clicked() || actions.pressed(Action::Instructions) {
                         app_state.set(AppState::Instructions).unwrap();
                     }
 
-                    exit_game_button(ui, &mut exit, &keys);
+                    // Pushed on top of the main menu, so that these screens can go back to wherever they were opened from
+                    let b = ui.button("Settings");
+                    if b.clicked() {
+                        app_state.push(AppState::Settings).unwrap();
+                    }
+
+                    let b = ui.button("Controls");
+                    if b.clicked() {
+                        app_state.push(AppState::Controls).unwrap();
+                    }
+
+                    egui::CollapsingHeader::new("High scores").show(ui, |ui| {
+                        leaderboard_table(ui, &leaderboard, None);
+                    });
+
+                    egui::CollapsingHeader::new("Replays").show(ui, |ui| {
+                        if let Some(i) = replay_list(ui, &replays) {
+                            if replays.watch(i, &mut playback, &mut rules, &mut ai) {
+                                app_state.set(AppState::InGame).unwrap();
+                            }
+                        }
+                    });
+
+                    exit_game_button(ui, &mut exit, &actions, &bindings);
                 },
             )
             // style.text_styles
@@ -229,7 +286,11 @@ fn game_over(
     mut app_state: ResMut<State<AppState>>,
     mut ctx: ResMut<EguiContext>,
     mut exit: EventWriter<AppExit>,
-    keys: Res<Input<KeyCode>>,
+    actions: Res<ActionInput>,
+    bindings: Res<KeyBindings>,
+    leaderboard: Res<Leaderboard>,
+    last_run: Res<LastRun>,
+    mut stats: ResMut<GameStats>,
 ) {
     set_menu_spacing(&mut ctx);
 
@@ -237,9 +298,21 @@ fn game_over(
         ui.label("Game over");
         ui.label("Thanks for playing Deep Space Defenders 2: Resource Rumble Boogaloo");
 
+        // Only endless runs have a score
+        if let Some(score) = last_run.score {
+            ui.label(RichText::new(format!("Score: {}", score)).color(Color32::WHITE));
+            match last_run.rank {
+                Some(rank) => ui.label(format!("New high score! #{} on the leaderboard", rank + 1)),
+                None => ui.label("Not enough for the leaderboard this time"),
+            };
+            leaderboard_table(ui, &leaderboard, last_run.rank);
+        }
+
+        stats_panel(ui, &mut stats);
+
         main_menu_button(ui, app_state);
 
-        exit_game_button(ui, &mut exit, &keys);
+        exit_game_button(ui, &mut exit, &actions, &bindings);
     });
 }
 
@@ -252,9 +325,14 @@ fn main_menu_button(ui: &mut Ui, mut app_state: ResMut<State<AppState>>) {
 }
 
 // The exit game button, since we render it in both the main menu and the game over menu
-fn exit_game_button(ui: &mut Ui, exit: &mut EventWriter<AppExit>, keys: &Res<Input<KeyCode>>) {
-    let b = ui.button("Exit game (Esc)");
-    if b.clicked() || keys.pressed(keys::EXIT) {
+fn exit_game_button(
+    ui: &mut Ui,
+    exit: &mut EventWriter<AppExit>,
+    actions: &ActionInput,
+    bindings: &Res<KeyBindings>,
+) {
+    let b = ui.button(format!("Exit game ({})", bindings.label(Action::Exit)));
+    if b.clicked() || actions.pressed(Action::Exit) {
         exit.send(AppExit)
     };
     // return b;
@@ -265,7 +343,9 @@ fn victory_screen(
     mut ctx: ResMut<EguiContext>,
     mut exit: EventWriter<AppExit>,
 
-    keys: Res<Input<KeyCode>>,
+    actions: Res<ActionInput>,
+    bindings: Res<KeyBindings>,
+    mut stats: ResMut<GameStats>,
 ) {
     set_menu_spacing(&mut ctx);
     egui::Window::new("Victory!").show(ctx.ctx_mut(), |ui| {
@@ -274,11 +354,18 @@ fn victory_screen(
         ui.label("We'd love to tell you you unlocked a harder difficulty, or a new gun, but none of that's been implemented yet.");
         ui.label("In the meantime, follow us for more updates.");
 
+        stats_panel(ui, &mut stats);
+
         main_menu_button(ui, app_state);
-        exit_game_button(ui, &mut exit, &keys);
+        exit_game_button(ui, &mut exit, &actions, &bindings);
     });
 }
-pub fn instruction_screen(app_state: ResMut<State<AppState>>, mut ctx: ResMut<EguiContext>) {
+pub fn instruction_screen(
+    app_state: ResMut<State<AppState>>,
+    mut ctx: ResMut<EguiContext>,
+    bindings: Res<KeyBindings>,
+    rules: Res<GameRules>,
+) {
     make_window(Align2::CENTER_CENTER, None)
     .min_width(600.)
     .show(ctx.ctx_mut(), |ui| {
@@ -289,12 +376,23 @@ pub fn instruction_screen(app_state: ResMut<State<AppState>>, mut ctx: ResMut<Eg
 
         ui.heading("Lose/Win conditions");
         ui.label("The hangar in the middle of the map is your main base. If it gets destroyed you lose. Don't let it fall!");
-        ui.label(format!("To win the game you need to survive until the rescue ship arrive to extract you - {} minutes after the start of the game. You have a time in-game to keep track of remaining time until extraction", WIN_MINUTES));
+        ui.label(format!("To win the game you need to survive until the rescue ship arrive to extract you - {} minutes after the start of the game on {} difficulty. You have a time in-game to keep track of remaining time until extraction", rules.win_minutes(), rules.difficulty.name()));
 
         ui.heading("Controls:");
-        ui.label("The game should be played with a mouse. You can use a keyboard for the menu navigations, by pressing Q/W/E/R and then a number for selecting the specific building.");
+        ui.label(format!("The game should be played with a mouse. You can use a keyboard for the menu navigations, by pressing {}/{}/{}/{} and then a number for selecting the specific building. All the keys can be changed in the Controls menu.",
+            bindings.label(Action::DefensiveBuildings),
+            bindings.label(Action::ResourceBuildings),
+            bindings.label(Action::Demolish),
+            bindings.label(Action::Pan),
+        ));
         ui.label("By default your cursor is in the Panning mode. This means you can move the camera around by dragging the map, zoom using the scrollbar and also click on a building to view its details in the corner.");
-        ui.heading("Note: The map can also be moved using arrows");
+        ui.heading(format!(
+            "Note: The map can also be moved using {}/{}/{}/{} or the arrows",
+            bindings.label(Action::CameraUp),
+            bindings.label(Action::CameraLeft),
+            bindings.label(Action::CameraDown),
+            bindings.label(Action::CameraRight),
+        ));
         ui.label("To build a building you can click on either the Build Defensive or the Build Resource option in the main menu, depending on the category of your desired building. This will expand a list of all the possible buildings. By hovering on a building you can view its details, including its costs. To construct a building successfuly, you need to have enough resources. After selecting your building, click on an empty square on the map to build it.");
         ui.label("If you want to replace a building you can use the demolish option. Demolishing a building returns half its building costs into your inventory.");
 
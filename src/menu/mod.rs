@@ -1 +1,8 @@
-pub mod menu;
\ No newline at end of file
+pub mod menu;
+pub mod controls_menu;
+pub mod game_setup_menu;
+pub mod high_scores;
+pub mod pause_menu;
+pub mod replay_list;
+pub mod settings_menu;
+pub mod stats_screen;
\ No newline at end of file
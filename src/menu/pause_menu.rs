@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+use bevy_egui::{egui::Align2, EguiContext};
+use bevy_rapier3d::prelude::RapierConfiguration;
+
+use crate::{
+    controls::controls::{Action, ActionInput, KeyBindings},
+    AppState,
+};
+
+use super::menu::{make_window, set_menu_spacing};
+
+// The pause menu is pushed on top of AppState::InGame, so none of the in game systems run while it's open.
+// The in game time is paused in the game timer module, the physics are paused here.
+
+pub struct PauseMenuPlugin;
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(AppState::InGame).with_system(pause_game))
+            .add_system_set(SystemSet::on_update(AppState::Paused).with_system(pause_menu))
+            .add_system_set(SystemSet::on_pause(AppState::InGame).with_system(pause_physics))
+            .add_system_set(SystemSet::on_resume(AppState::InGame).with_system(resume_physics))
+            // Quitting to the main menu from the pause menu removes InGame from the stack without resuming it
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(resume_physics));
+    }
+}
+
+// The press is used up, otherwise pause_menu would see it in the same frame and resume straight away
+pub fn pause_game(mut actions: ResMut<ActionInput>, mut app_state: ResMut<State<AppState>>) {
+    if actions.clear_just_pressed(Action::Pause) {
+        app_state.push(AppState::Paused).unwrap();
+    }
+}
+
+// Aliens would otherwise keep running at their last velocity
+pub fn pause_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = false;
+}
+
+pub fn resume_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = true;
+}
+
+pub fn pause_menu(
+    mut app_state: ResMut<State<AppState>>,
+    mut ctx: ResMut<EguiContext>,
+    mut actions: ResMut<ActionInput>,
+    bindings: Res<KeyBindings>,
+) {
+    set_menu_spacing(&mut ctx);
+    make_window(Align2::CENTER_CENTER, None)
+        .min_width(300.)
+        .show(ctx.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                ui.heading("Paused");
+
+                let b = ui.button(format!("Resume ({})", bindings.label(Action::Pause)));
+                // Same as in pause_game, so the resumed game doesn't pause again
+                if b.clicked() || actions.clear_just_pressed(Action::Pause) {
+                    app_state.pop().unwrap();
+                    return;
+                }
+
+                if ui.button("Settings").clicked() {
+                    app_state.push(AppState::Settings).unwrap();
+                }
+
+                if ui.button("Controls").clicked() {
+                    app_state.push(AppState::Controls).unwrap();
+                }
+
+                if ui.button("Quit to main menu").clicked() {
+                    // Replaces the whole stack, so InGame gets exited too
+                    app_state.replace(AppState::MainMenu).unwrap();
+                }
+            });
+        });
+}
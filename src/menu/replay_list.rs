@@ -0,0 +1,44 @@
+use bevy_egui::egui::{self, Color32, RichText, Ui};
+
+use crate::{replay::playback::ReplayFiles, score::leaderboard::format_date};
+
+// The saved replays, shown in the main menu. Returns the index of the one to watch, if one was picked
+
+pub fn replay_list(ui: &mut Ui, files: &ReplayFiles) -> Option<usize> {
+    if let Some(error) = &files.error {
+        ui.label(RichText::new(error).color(Color32::RED));
+    }
+    if files.files.is_empty() {
+        ui.label("No replays yet, every game is recorded");
+        return None;
+    }
+
+    let mut picked = None;
+    egui::ScrollArea::vertical()
+        .max_height(300.)
+        .show(ui, |ui| {
+            egui::Grid::new("replays")
+                .spacing(egui::Vec2::new(16., 4.))
+                .show(ui, |ui| {
+                    for header in ["Date (UTC)", "Seed", ""] {
+                        ui.label(header);
+                    }
+                    ui.end_row();
+
+                    for (i, file) in files.files.iter().enumerate() {
+                        ui.label(format!(
+                            "{} {:02}:{:02}",
+                            format_date(file.saved),
+                            file.saved / 3600 % 24,
+                            file.saved / 60 % 60
+                        ));
+                        ui.label(file.seed.to_string());
+                        if ui.button("Watch").clicked() {
+                            picked = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+    return picked;
+}
@@ -0,0 +1,145 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2},
+    EguiContext,
+};
+
+use crate::{
+    audio::channels::SoundChannel,
+    settings::settings::{
+        GameSettings, ShadowQuality, WindowModeSetting, MSAA_SAMPLES, RESOLUTIONS,
+    },
+    AppState,
+};
+
+use super::menu::{make_window, set_menu_spacing};
+
+// The settings screen, reachable from both the main menu and the pause menu.
+// Changes are applied immediately and saved to the settings file when leaving the screen
+
+pub fn settings_screen(
+    mut app_state: ResMut<State<AppState>>,
+    mut ctx: ResMut<EguiContext>,
+    mut settings: ResMut<GameSettings>,
+) {
+    // We edit a copy and only write it back if something changed.
+    // Otherwise the ResMut would be marked as changed every frame and the settings reapplied constantly
+    let mut new_settings = settings.clone();
+
+    set_menu_spacing(&mut ctx);
+    make_window(Align2::CENTER_CENTER, None)
+        .min_width(600.)
+        .show(ctx.ctx_mut(), |ui| {
+            ui.heading("Settings");
+            ui.spacing_mut().item_spacing = egui::Vec2::new(16., 8.);
+
+            egui::Grid::new("settings").show(ui, |ui| {
+                ui.label("Window mode");
+                egui::ComboBox::from_id_source("window_mode")
+                    .selected_text(new_settings.window_mode.name())
+                    .show_ui(ui, |ui| {
+                        for mode in WindowModeSetting::ALL {
+                            ui.selectable_value(&mut new_settings.window_mode, mode, mode.name());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Resolution");
+                let resolution_name = |(w, h): (f32, f32)| format!("{} x {}", w, h);
+                egui::ComboBox::from_id_source("resolution")
+                    .selected_text(resolution_name(new_settings.resolution))
+                    .show_ui(ui, |ui| {
+                        for resolution in RESOLUTIONS {
+                            ui.selectable_value(
+                                &mut new_settings.resolution,
+                                resolution,
+                                resolution_name(resolution),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Shadow quality");
+                egui::ComboBox::from_id_source("shadow_quality")
+                    .selected_text(new_settings.shadow_quality.name())
+                    .show_ui(ui, |ui| {
+                        for quality in ShadowQuality::ALL {
+                            ui.selectable_value(
+                                &mut new_settings.shadow_quality,
+                                quality,
+                                quality.name(),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Anti-aliasing (MSAA)");
+                let msaa_name = |samples: u32| {
+                    if samples > 1 {
+                        format!("{}x", samples)
+                    } else {
+                        "Off".to_string()
+                    }
+                };
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("msaa")
+                        .selected_text(msaa_name(new_settings.msaa_samples))
+                        .show_ui(ui, |ui| {
+                            for samples in MSAA_SAMPLES {
+                                ui.selectable_value(
+                                    &mut new_settings.msaa_samples,
+                                    samples,
+                                    msaa_name(samples),
+                                );
+                            }
+                        });
+                    ui.label("(applies after restart)");
+                });
+                ui.end_row();
+
+                let slider = |ui: &mut egui::Ui, name: &str, value: &mut f32| {
+                    ui.label(name);
+                    ui.add(egui::Slider::new(value, 0.0..=1.0));
+                    ui.end_row();
+                };
+                slider(ui, "Master volume", &mut new_settings.master_volume);
+                for channel in SoundChannel::ALL {
+                    slider(
+                        ui,
+                        &format!("{} volume", channel.name()),
+                        new_settings.channel_volume_mut(channel),
+                    );
+                }
+
+                ui.label("Mute all sounds");
+                ui.checkbox(&mut new_settings.muted, "");
+                ui.end_row();
+
+                ui.label("Interface scale");
+                ui.add(egui::Slider::new(&mut new_settings.ui_scale, 0.5..=2.0));
+                ui.end_row();
+
+                ui.label("Record gameplay telemetry");
+                ui.checkbox(&mut new_settings.telemetry, "(for balancing, applies from the next game)");
+                ui.end_row();
+            });
+
+            ui.horizontal(|ui| {
+                if ui.button("Reset to defaults").clicked() {
+                    new_settings = GameSettings::default();
+                }
+                if ui.button("Controls").clicked() {
+                    app_state.push(AppState::Controls).unwrap();
+                }
+                if ui.button("Back").clicked() {
+                    new_settings.save();
+                    // The screen is always pushed on top of the menu it was opened from
+                    app_state.pop().unwrap();
+                }
+            });
+        });
+
+    if new_settings != *settings {
+        *settings = new_settings;
+    }
+}
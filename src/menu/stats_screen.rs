@@ -0,0 +1,118 @@
+use bevy_egui::egui::{
+    self,
+    plot::{Legend, Line, Plot, PlotPoints},
+    Color32, RichText, Ui,
+};
+
+use crate::{
+    game_timer::game_timer::pad,
+    stats::game_stats::{GameStats, ResourceTally, StatsSample},
+};
+
+// The statistics of the run that just ended, shown on both the game over and the victory screen
+
+const GRAPH_HEIGHT: f32 = 120.;
+const ORE_COLOR: Color32 = Color32::from_rgb(200, 140, 90);
+const GAS_COLOR: Color32 = Color32::from_rgb(120, 220, 120);
+const CRYSTAL_COLOR: Color32 = Color32::from_rgb(190, 120, 255);
+const ALIEN_COLOR: Color32 = Color32::from_rgb(255, 80, 80);
+
+fn line(
+    samples: &[StatsSample],
+    name: &str,
+    color: Color32,
+    value: fn(&StatsSample) -> u32,
+) -> Line {
+    let points: PlotPoints = samples
+        .iter()
+        .map(|s| [s.seconds as f64 / 60., value(s) as f64])
+        .collect();
+    Line::new(points).name(name).color(color)
+}
+
+// A small graph that can't be dragged or zoomed, the x axis is in minutes
+fn graph(ui: &mut Ui, id: &str, lines: Vec<Line>) {
+    Plot::new(id)
+        .height(GRAPH_HEIGHT)
+        .legend(Legend::default())
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .allow_boxed_zoom(false)
+        .include_y(0.)
+        .show(ui, |plot| {
+            for l in lines {
+                plot.line(l);
+            }
+        });
+}
+
+fn tally(t: &ResourceTally) -> String {
+    format!("{} ore, {} gas, {} crystal", t.ore, t.gas, t.crystal)
+}
+
+pub fn stats_panel(ui: &mut Ui, stats: &mut GameStats) {
+    ui.heading("Statistics");
+
+    let seconds = stats.seconds as u64;
+    egui::Grid::new("stats").show(ui, |ui| {
+        let mut row = |name: &str, value: String| {
+            ui.label(name);
+            ui.label(RichText::new(value).color(Color32::WHITE));
+            ui.end_row();
+        };
+        row(
+            "Time",
+            format!(
+                "{}:{}",
+                pad((seconds / 60).to_string()),
+                pad((seconds % 60).to_string())
+            ),
+        );
+        row("Aliens killed", stats.kills.to_string());
+        row("Most aliens at once", stats.peak_aliens.to_string());
+        row(
+            "Top turret",
+            match stats.top_turret() {
+                Some(t) => format!("{} ({} kills)", t.name, t.kills),
+                None => "-".to_string(),
+            },
+        );
+        row("Buildings built", stats.buildings_built.to_string());
+        row("Buildings lost", stats.buildings_lost.to_string());
+        row(
+            "Buildings demolished",
+            stats.buildings_demolished.to_string(),
+        );
+        row("Resources earned", tally(&stats.resources_earned));
+        row("Resources spent", tally(&stats.resources_spent));
+        row("Resources refunded", tally(&stats.resources_refunded));
+    });
+
+    ui.label("Resources");
+    graph(
+        ui,
+        "resources_graph",
+        vec![
+            line(&stats.samples, "Ore", ORE_COLOR, |s| s.ore),
+            line(&stats.samples, "Gas", GAS_COLOR, |s| s.gas),
+            line(&stats.samples, "Crystal", CRYSTAL_COLOR, |s| s.crystal),
+        ],
+    );
+    ui.label("Aliens");
+    graph(
+        ui,
+        "aliens_graph",
+        vec![line(&stats.samples, "Aliens", ALIEN_COLOR, |s| s.aliens)],
+    );
+
+    if ui.button("Export to JSON").clicked() {
+        stats.export_message = Some(match stats.export() {
+            Ok(path) => format!("Saved to {}", path.display()),
+            Err(e) => format!("Could not export the statistics: {}", e),
+        });
+    }
+    if let Some(message) = &stats.export_message {
+        ui.label(message.as_str());
+    }
+}
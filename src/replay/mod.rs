@@ -0,0 +1,2 @@
+pub mod replay;
+pub mod playback;
\ No newline at end of file
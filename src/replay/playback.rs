@@ -0,0 +1,533 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, time::TimeSystem, utils::Instant};
+use bevy_egui::{
+    egui::{self, Align2, Color32, RichText},
+    EguiContext,
+};
+
+use crate::{
+    bot::ai_player::AiPlayer,
+    buildings::player_commands::{CommandEvent, CommandSource, PlayerCommand},
+    game_timer::{
+        game_timer::InGameTime,
+        gameplay_schedule::{GameplayStage, GameplayTick, GAMEPLAY_STEP},
+    },
+    menu::menu::make_window,
+    rules::game_rules::GameRules,
+    AppState,
+};
+
+use super::replay::{due_hash, list_replays, GameStateParam, Replay, ReplayFile};
+
+// Plays a recorded game back. The game runs as usual, from the recorded rules, while the recorded commands are sent at the times they were applied.
+// The player can't build anything in the meantime.
+// The clock is driven by the playback: every frame moves the game a gameplay tick times the speed forward, no matter how long the frame actually took.
+// So fast forwarding runs more ticks a frame. The physics steps on the ticks too, so it plays out the same as watching at 1x.
+// The timeline can be dragged, going back restarts the game and fast forwards to the picked time
+
+pub struct ReplayPlaybackPlugin;
+
+impl Plugin for ReplayPlaybackPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayPlayback>()
+            .init_resource::<ReplayFiles>()
+            .add_system_to_stage(CoreStage::First, drive_replay_clock.after(TimeSystem))
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(rewind_playback))
+            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(replay_controls))
+            // Fed in the tick the commands are due, and checked at the end of it like they were recorded
+            .add_system_to_stage(
+                GameplayStage,
+                feed_replay_commands
+                    .after(GameplayTick::Start)
+                    .before(GameplayTick::Commands),
+            )
+            .add_system_to_stage(GameplayStage, check_state_hashes.after(GameplayTick::End))
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(replay_main_menu));
+    }
+}
+
+// How many ticks a frame of the playback runs
+pub const SPEEDS: [u32; 4] = [1, 2, 4, 8];
+// How fast the timeline seeks
+const SEEK_SPEED: u32 = 8;
+
+// Where the playback first stopped matching the recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub t_index: u32,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    // None when not watching a replay
+    replay: Option<Replay>,
+    pub name: String,
+    pub speed: u32,
+    pub paused: bool,
+    next_command: usize,
+    next_hash: u32,
+    // The next recorded hash to compare against
+    hash_cursor: usize,
+    // How many of the recorded hashes matched so far
+    pub matched: usize,
+    pub desync: Option<Desync>,
+    // Fast forwarding to this time
+    seek: Option<f32>,
+    // Where the timeline is being dragged to
+    scrub: Option<f32>,
+    // Set when seeking backwards, the game restarts through the main menu
+    restart: bool,
+    clock: Option<(Time, Instant)>,
+    // What the player had picked before, given back when the playback ends
+    rules_before: GameRules,
+    ai_was_enabled: bool,
+}
+
+impl Default for ReplayPlayback {
+    fn default() -> Self {
+        ReplayPlayback {
+            replay: None,
+            name: String::new(),
+            speed: 1,
+            paused: false,
+            next_command: 0,
+            next_hash: 0,
+            hash_cursor: 0,
+            matched: 0,
+            desync: None,
+            seek: None,
+            scrub: None,
+            restart: false,
+            clock: None,
+            rules_before: GameRules::default(),
+            ai_was_enabled: false,
+        }
+    }
+}
+
+impl ReplayPlayback {
+    pub fn active(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+
+    // The game has to be started afterwards. The commands of the AI player are in the recording, so it's turned off meanwhile
+    pub fn start(
+        &mut self,
+        replay: Replay,
+        name: String,
+        rules: &mut GameRules,
+        ai: &mut AiPlayer,
+    ) {
+        let (rules_before, ai_was_enabled) = if self.active() {
+            (self.rules_before, self.ai_was_enabled)
+        } else {
+            (*rules, ai.enabled)
+        };
+        *rules = replay.rules;
+        ai.enabled = false;
+        *self = ReplayPlayback {
+            replay: Some(replay),
+            name,
+            rules_before,
+            ai_was_enabled,
+            ..default()
+        };
+    }
+
+    pub fn stop(&mut self, rules: &mut GameRules, ai: &mut AiPlayer) {
+        if self.active() {
+            *rules = self.rules_before;
+            ai.enabled = self.ai_was_enabled;
+        }
+        *self = ReplayPlayback::default();
+    }
+
+    // Back to the start of the recording, for when the game (re)starts
+    pub fn rewind(&mut self) {
+        self.next_command = 0;
+        self.next_hash = 0;
+        self.hash_cursor = 0;
+        self.matched = 0;
+        self.desync = None;
+        self.clock = None;
+    }
+
+    // The recorded commands that are due by now, each of them only once
+    pub fn due_commands(&mut self, now: f32) -> Vec<PlayerCommand> {
+        let commands = match &self.replay {
+            Some(replay) => &replay.commands,
+            None => return Vec::new(),
+        };
+        let due = commands[self.next_command..]
+            .iter()
+            .take_while(|c| c.t <= now)
+            .map(|c| c.command)
+            .collect::<Vec<_>>();
+        self.next_command += due.len();
+        return due;
+    }
+
+    // Compares the hash of the state with the recorded one for the same time. None if there's no recorded hash for it
+    pub fn check_hash(&mut self, t_index: u32, hash: u64) -> Option<bool> {
+        let hashes = &self.replay.as_ref()?.hashes;
+        while hashes
+            .get(self.hash_cursor)
+            .map_or(false, |h| h.t_index < t_index)
+        {
+            self.hash_cursor += 1;
+        }
+        let expected = hashes
+            .get(self.hash_cursor)
+            .filter(|h| h.t_index == t_index)?
+            .hash;
+        if expected == hash {
+            self.matched += 1;
+            return Some(true);
+        }
+        if self.desync.is_none() {
+            self.desync = Some(Desync {
+                t_index,
+                expected,
+                actual: hash,
+            });
+        }
+        return Some(false);
+    }
+
+    // Returns true when the game has to restart to get there
+    pub fn seek_to(&mut self, now: f32, target: f32) -> bool {
+        self.seek = Some(target);
+        if target < now {
+            self.restart = true;
+        }
+        return self.restart;
+    }
+
+    // How much in game time the next frame is, always whole ticks.
+    // Each of them steps the physics by one tick as well, see GameplayPhysicsPlugin
+    pub fn step(&self) -> Duration {
+        if self.seek.is_some() {
+            return GAMEPLAY_STEP * SEEK_SPEED;
+        }
+        if self.paused {
+            return Duration::ZERO;
+        }
+        return GAMEPLAY_STEP * self.speed;
+    }
+}
+
+// Replaces the real frame time with the playback's. Runs right after bevy updates the Time.
+// Only the GameplayStage and what's drawn go by it, the game itself only moves on the ticks
+pub fn drive_replay_clock(
+    mut time: ResMut<Time>,
+    state: Res<State<AppState>>,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    if !playback.active() {
+        return;
+    }
+    // Nothing moves in the pause menu or while restarting
+    let step = match state.current() {
+        AppState::InGame => playback.step(),
+        _ => Duration::ZERO,
+    };
+    let (clock, instant) = playback.clock.get_or_insert_with(|| {
+        let now = Instant::now();
+        let mut clock = Time::default();
+        clock.update_with_instant(now);
+        (clock, now)
+    });
+    *instant += step;
+    clock.update_with_instant(*instant);
+    *time = clock.clone();
+}
+
+pub fn rewind_playback(mut playback: ResMut<ReplayPlayback>) {
+    playback.rewind();
+}
+
+pub fn feed_replay_commands(
+    time: Res<InGameTime>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut commands: EventWriter<CommandEvent>,
+) {
+    let length = match playback.replay() {
+        Some(replay) => replay.length,
+        None => return,
+    };
+    let now = time.timer.elapsed_secs();
+    for command in playback.due_commands(now) {
+        commands.send(CommandEvent {
+            command,
+            source: CommandSource::Replay,
+        });
+    }
+
+    if playback.seek.map_or(false, |target| now >= target) {
+        playback.seek = None;
+    }
+    // A recording that was quit before the game ended stops where it was quit
+    if now >= length && !playback.paused {
+        playback.paused = true;
+    }
+}
+
+pub fn check_state_hashes(
+    time: Res<InGameTime>,
+    mut playback: ResMut<ReplayPlayback>,
+    state: GameStateParam,
+) {
+    if !playback.active() {
+        return;
+    }
+    let t_index = match due_hash(playback.next_hash, time.timer.elapsed_secs()) {
+        Some(t_index) => t_index,
+        None => return,
+    };
+    playback.next_hash = t_index + 1;
+    let first = playback.desync.is_none();
+    if let Some(false) = playback.check_hash(t_index, state.snapshot().hash()) {
+        if first {
+            warn!(
+                "The replay desynced at {}, the state doesn't match the recording",
+                clock_time(t_index as f32)
+            );
+        }
+    }
+}
+
+fn clock_time(secs: f32) -> String {
+    let secs = secs.max(0.) as u32;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+// The timeline and the playback controls, at the bottom of the screen
+pub fn replay_controls(
+    mut ctx: ResMut<EguiContext>,
+    time: Res<InGameTime>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    let length = match playback.replay() {
+        Some(replay) => replay.length,
+        None => return,
+    };
+    let now = time.timer.elapsed_secs();
+
+    make_window(Align2::CENTER_BOTTOM, Some((0., -10.))).show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label(format!("Replay {}", playback.name));
+            let label = if playback.paused { "Play" } else { "Pause" };
+            if ui.button(label).clicked() {
+                playback.paused = !playback.paused;
+            }
+            for speed in SPEEDS {
+                if ui
+                    .selectable_label(playback.speed == speed, format!("{}x", speed))
+                    .clicked()
+                {
+                    playback.speed = speed;
+                }
+            }
+            if ui.button("Stop").clicked() {
+                app_state.replace(AppState::MainMenu).unwrap();
+            }
+        });
+
+        // The timeline. It only seeks once it's let go of
+        let mut t = playback.scrub.or(playback.seek).unwrap_or(now);
+        ui.spacing_mut().slider_width = 500.;
+        let slider = ui.add(
+            egui::Slider::new(&mut t, 0.0..=length.max(GAMEPLAY_STEP.as_secs_f32()))
+                .show_value(false),
+        );
+        if slider.dragged() {
+            playback.scrub = Some(t);
+        } else if slider.drag_released() || slider.changed() {
+            let target = playback.scrub.take().unwrap_or(t);
+            if playback.seek_to(now, target) {
+                app_state.replace(AppState::MainMenu).unwrap();
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{} / {}", clock_time(now), clock_time(length)));
+            if let Some(target) = playback.seek {
+                ui.label(format!("Seeking to {}", clock_time(target)));
+            }
+            match playback.desync {
+                Some(desync) => ui.label(
+                    RichText::new(format!("Desynced at {}", clock_time(desync.t_index as f32)))
+                        .color(Color32::RED),
+                ),
+                None => ui.label(format!("In sync ({} checks)", playback.matched)),
+            };
+        });
+    });
+}
+
+// The saved replays, listed in the main menu
+#[derive(Resource, Default)]
+pub struct ReplayFiles {
+    pub files: Vec<ReplayFile>,
+    // Why the last replay couldn't be loaded
+    pub error: Option<String>,
+}
+
+impl ReplayFiles {
+    // Starts the replay, or keeps the error for the menu to show
+    pub fn watch(
+        &mut self,
+        file: usize,
+        playback: &mut ReplayPlayback,
+        rules: &mut GameRules,
+        ai: &mut AiPlayer,
+    ) -> bool {
+        let file = match self.files.get(file) {
+            Some(file) => file,
+            None => return false,
+        };
+        match Replay::load(&file.path) {
+            Ok(replay) => {
+                let name = file
+                    .path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                playback.start(replay, name, rules, ai);
+                self.error = None;
+                return true;
+            }
+            Err(e) => {
+                self.error = Some(format!("Could not load {}: {}", file.path.display(), e));
+                return false;
+            }
+        }
+    }
+}
+
+// Seeking backwards goes through the main menu to restart the game, anything else that gets here ends the playback
+pub fn replay_main_menu(
+    mut playback: ResMut<ReplayPlayback>,
+    mut files: ResMut<ReplayFiles>,
+    mut rules: ResMut<GameRules>,
+    mut ai: ResMut<AiPlayer>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if playback.restart {
+        playback.restart = false;
+        app_state.set(AppState::InGame).unwrap();
+        return;
+    }
+    playback.stop(&mut rules, &mut ai);
+    files.files = list_replays();
+}
+
+#[cfg(test)]
+mod test_playback {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use crate::{
+        bot::ai_player::AiPlayer,
+        buildings::player_commands::PlayerCommand,
+        game_timer::gameplay_schedule::GAMEPLAY_STEP,
+        replay::replay::{RecordedCommand, Replay, StateHash},
+        rules::game_rules::GameRules,
+    };
+
+    use super::{Desync, ReplayPlayback};
+
+    fn replay() -> Replay {
+        let mut replay = Replay::new(GameRules {
+            seed: 77,
+            ..default()
+        });
+        replay.length = 30.;
+        for (t, x) in [(1.5, 0), (1.5, 1), (4., 2)] {
+            replay.commands.push(RecordedCommand {
+                t,
+                command: PlayerCommand::Demolish { square: (x, 0) },
+            });
+        }
+        for (t_index, hash) in [(0, 10), (1, 11), (3, 13)] {
+            replay.hashes.push(StateHash { t_index, hash });
+        }
+        replay
+    }
+
+    fn playback() -> ReplayPlayback {
+        let mut playback = ReplayPlayback::default();
+        let mut rules = GameRules {
+            seed: 5,
+            ..default()
+        };
+        let mut ai = AiPlayer::default();
+        ai.enabled = true;
+        playback.start(replay(), "test".to_string(), &mut rules, &mut ai);
+        assert_eq!(rules.seed, 77);
+        assert!(!ai.enabled);
+        playback.stop(&mut rules, &mut ai);
+        assert_eq!(rules.seed, 5);
+        assert!(ai.enabled);
+        playback.start(replay(), "test".to_string(), &mut rules, &mut ai);
+        playback
+    }
+
+    #[test]
+    fn commands_are_sent_once_when_due() {
+        let mut playback = playback();
+        assert!(playback.due_commands(1.).is_empty());
+        assert_eq!(playback.due_commands(2.).len(), 2);
+        assert!(playback.due_commands(2.).is_empty());
+        assert_eq!(
+            playback.due_commands(100.),
+            vec![PlayerCommand::Demolish { square: (2, 0) }]
+        );
+        playback.rewind();
+        assert_eq!(playback.due_commands(100.).len(), 3);
+    }
+
+    #[test]
+    fn the_first_desync_is_kept() {
+        let mut playback = playback();
+        assert_eq!(playback.check_hash(0, 10), Some(true));
+        // Nothing was recorded for 2
+        assert_eq!(playback.check_hash(2, 99), None);
+        assert_eq!(playback.check_hash(3, 99), Some(false));
+        assert_eq!(
+            playback.desync,
+            Some(Desync {
+                t_index: 3,
+                expected: 13,
+                actual: 99
+            })
+        );
+        assert_eq!(playback.matched, 1);
+    }
+
+    #[test]
+    fn seeking_back_restarts() {
+        let mut playback = playback();
+        assert!(!playback.seek_to(5., 20.));
+        assert_eq!(playback.step(), GAMEPLAY_STEP * 8);
+        assert!(playback.seek_to(5., 2.));
+    }
+
+    #[test]
+    fn paused_playback_stands_still() {
+        let mut playback = playback();
+        playback.speed = 4;
+        assert_eq!(playback.step(), GAMEPLAY_STEP * 4);
+        playback.paused = true;
+        assert_eq!(playback.step(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,557 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aliens::{alien::AlienCount, nests::NestCount},
+    buildings::{
+        building_bundles::BuildingInfoComponent,
+        grid::Grid,
+        player_commands::{CommandResult, CommandSource, PlayerCommand},
+        resources::{ResourceState, ResourceType},
+    },
+    game_timer::{
+        game_timer::InGameTime,
+        gameplay_schedule::{on_gameplay_tick, GameplayStage, GameplayTick},
+    },
+    health::health::Health,
+    rules::game_rules::GameRules,
+    settings::config_file::config_path,
+    stats::game_stats::GameStats,
+    AppState,
+};
+
+use super::playback::ReplayPlayback;
+
+// Every game is recorded, so a bug can be played back exactly as it happened.
+// A replay is the rules of the game (which hold the seed everything random comes from) and the PlayerCommands with the in game time they were applied at.
+// The commands are applied on the gameplay ticks, so the times are of the ticks and the playback can apply them on the same ones.
+// The recording also has a hash of the game state every HASH_INTERVAL, playback compares against them to find where it stopped matching the original.
+// The files are JSON, in the replays folder of the config directory. Only the last MAX_REPLAYS are kept
+
+pub struct ReplayRecordingPlugin;
+
+impl Plugin for ReplayRecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayRecorder>()
+            .init_resource::<ReplayDir>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(start_recording))
+            // In the tick the commands were applied in, so the times match the playback
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick().with_system(record_commands),
+            )
+            // Once everything in the tick is done
+            .add_system_to_stage(GameplayStage, record_state_hashes.after(GameplayTick::End))
+            // Every way a game can end
+            .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(finish_recording))
+            .add_system_set(SystemSet::on_enter(AppState::Victory).with_system(finish_recording))
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(finish_recording));
+    }
+}
+
+// 2: the commands and the hashes are on the gameplay ticks
+// 3: the physics and the aliens are on the ticks too, destroyed buildings aren't in the hashes
+pub const REPLAY_VERSION: u32 = 3;
+const REPLAY_DIR: &str = "replays";
+pub const MAX_REPLAYS: usize = 20;
+// In game seconds between the state hashes
+pub const HASH_INTERVAL: f32 = 1.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RecordedCommand {
+    // In game seconds
+    pub t: f32,
+    pub command: PlayerCommand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateHash {
+    // Taken on the first tick at or after t_index * HASH_INTERVAL
+    pub t_index: u32,
+    pub hash: u64,
+}
+
+impl StateHash {
+    pub fn time(&self) -> f32 {
+        self.t_index as f32 * HASH_INTERVAL
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub rules: GameRules,
+    // In game seconds until the game ended, or until it was quit
+    pub length: f32,
+    pub commands: Vec<RecordedCommand>,
+    pub hashes: Vec<StateHash>,
+}
+
+impl Replay {
+    pub fn new(rules: GameRules) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            rules,
+            length: 0.,
+            commands: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let replay: Replay = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        if replay.version != REPLAY_VERSION {
+            return Err(format!(
+                "Recorded with replay version {}, this game plays version {}",
+                replay.version, REPLAY_VERSION
+            ));
+        }
+        return Ok(replay);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, text).map_err(|e| e.to_string())
+    }
+}
+
+// The parts of the game state that should come out the same when a replay is played back.
+// Only whole numbers, so the hash doesn't depend on how the floats are printed.
+// Destroyed buildings are left out, they're taken away when their animation is done, which takes frames and not ticks
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StateSnapshot {
+    pub resources: [u16; 3],
+    // The square, name and health of every building, sorted by the square
+    pub buildings: Vec<((i8, i8), &'static str, i32)>,
+    pub aliens: u32,
+    pub kills: u32,
+    pub nests_destroyed: u32,
+}
+
+impl StateSnapshot {
+    // FNV-1a, unlike the std hashers it's the same on every build of the game
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |bytes: &[u8]| {
+            for b in bytes {
+                hash ^= *b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for r in self.resources {
+            write(&r.to_le_bytes());
+        }
+        for ((x, y), name, hp) in self.buildings.iter() {
+            write(&x.to_le_bytes());
+            write(&y.to_le_bytes());
+            write(name.as_bytes());
+            write(&hp.to_le_bytes());
+        }
+        write(&self.aliens.to_le_bytes());
+        write(&self.kills.to_le_bytes());
+        write(&self.nests_destroyed.to_le_bytes());
+        return hash;
+    }
+}
+
+#[derive(SystemParam)]
+pub struct GameStateParam<'w, 's> {
+    resources: Res<'w, ResourceState>,
+    buildings: Query<
+        'w,
+        's,
+        (
+            &'static BuildingInfoComponent,
+            &'static Transform,
+            &'static Health,
+        ),
+    >,
+    aliens: Res<'w, AlienCount>,
+    stats: Res<'w, GameStats>,
+    nests: Res<'w, NestCount>,
+}
+
+impl<'w, 's> GameStateParam<'w, 's> {
+    pub fn snapshot(&self) -> StateSnapshot {
+        let mut buildings = self
+            .buildings
+            .iter()
+            .filter(|(_, _, h)| h.hp > 0)
+            .map(|(info, t, h)| (Grid::get_square_index(t.translation), info.name, h.hp))
+            .collect::<Vec<_>>();
+        buildings.sort();
+        StateSnapshot {
+            resources: ResourceType::ALL.map(|r| self.resources.get(r).unwrap_or(0)),
+            buildings,
+            aliens: self.aliens.count,
+            kills: self.stats.kills,
+            nests_destroyed: self.nests.destroyed,
+        }
+    }
+}
+
+// The index of the hash that's due at this time, if it's `next` or later. Both the recording and the playback take them with this
+pub fn due_hash(next: u32, now: f32) -> Option<u32> {
+    let t_index = (now / HASH_INTERVAL).floor() as u32;
+    if t_index >= next {
+        return Some(t_index);
+    }
+    return None;
+}
+
+// A saved replay, the time it was saved at and the seed are in the file name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayFile {
+    pub path: PathBuf,
+    // Unix time
+    pub saved: u64,
+    pub seed: u64,
+}
+
+impl ReplayFile {
+    fn from_path(path: PathBuf) -> Option<Self> {
+        if path.extension()? != "json" {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?.to_string();
+        let mut parts = stem.split('-').skip(1);
+        let saved = parts.next()?.parse().ok()?;
+        let seed = parts.next()?.parse().ok()?;
+        Some(ReplayFile { path, saved, seed })
+    }
+}
+
+// Replays that were saved, the newest first
+pub fn list_replays() -> Vec<ReplayFile> {
+    match config_path(REPLAY_DIR) {
+        Some(dir) => sorted_replays(&dir),
+        None => Vec::new(),
+    }
+}
+
+fn sorted_replays(dir: &Path) -> Vec<ReplayFile> {
+    let mut files = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| ReplayFile::from_path(e.path()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    files.sort_by_key(|f| std::cmp::Reverse(f.saved));
+    return files;
+}
+
+// Removes all but the newest `keep` replays in the folder
+pub fn prune_replays(dir: &Path, keep: usize) {
+    for old in sorted_replays(dir).iter().skip(keep) {
+        if let Err(e) = fs::remove_file(&old.path) {
+            warn!("Could not remove {}: {}", old.path.display(), e);
+        }
+    }
+}
+
+// Where the finished games are saved, None if the platform has no config directory
+#[derive(Resource, Debug, Clone)]
+pub struct ReplayDir(pub Option<PathBuf>);
+
+impl Default for ReplayDir {
+    fn default() -> Self {
+        ReplayDir(config_path(REPLAY_DIR))
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ReplayRecorder {
+    // None while watching a replay
+    replay: Option<Replay>,
+    next_hash: u32,
+}
+
+pub fn start_recording(
+    rules: Res<GameRules>,
+    playback: Res<ReplayPlayback>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    *recorder = ReplayRecorder::default();
+    if !playback.active() {
+        recorder.replay = Some(Replay::new(*rules));
+    }
+}
+
+// Only the commands that were actually carried out, from the player or the AI player
+pub fn record_commands(
+    time: Res<InGameTime>,
+    mut recorder: ResMut<ReplayRecorder>,
+    mut results: EventReader<CommandResult>,
+) {
+    let replay = match recorder.replay.as_mut() {
+        Some(replay) => replay,
+        None => {
+            results.clear();
+            return;
+        }
+    };
+    for r in results.iter() {
+        if r.source != CommandSource::Replay && r.result.is_ok() {
+            replay.commands.push(RecordedCommand {
+                t: time.timer.elapsed_secs(),
+                command: r.command,
+            });
+        }
+    }
+}
+
+pub fn record_state_hashes(
+    time: Res<InGameTime>,
+    mut recorder: ResMut<ReplayRecorder>,
+    state: GameStateParam,
+) {
+    let next = recorder.next_hash;
+    let t_index = match (&recorder.replay, due_hash(next, time.timer.elapsed_secs())) {
+        (Some(_), Some(t_index)) => t_index,
+        _ => return,
+    };
+    let hash = state.snapshot().hash();
+    recorder.next_hash = t_index + 1;
+    if let Some(replay) = recorder.replay.as_mut() {
+        replay.hashes.push(StateHash { t_index, hash });
+    }
+}
+
+// Saves the replay of the game that just ended. Also runs when the app gets to the main menu at startup, there's nothing recorded then
+pub fn finish_recording(
+    time: Res<InGameTime>,
+    dir: Res<ReplayDir>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let mut replay = match recorder.replay.take() {
+        Some(replay) => replay,
+        None => return,
+    };
+    replay.length = time.timer.elapsed_secs();
+
+    let dir = match &dir.0 {
+        Some(dir) => dir,
+        None => {
+            warn!("Could not find a config directory, the replay won't be saved");
+            return;
+        }
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = dir.join(format!("replay-{}-{}.json", timestamp, replay.rules.seed));
+    match replay.save(&path) {
+        Ok(()) => info!("Saved the replay to {}", path.display()),
+        Err(e) => warn!("Could not save the replay to {}: {}", path.display(), e),
+    }
+    prune_replays(dir, MAX_REPLAYS);
+}
+
+#[cfg(test)]
+mod test_replay {
+    use std::{fs, path::Path};
+
+    use bevy::{prelude::*, utils::Instant};
+
+    use crate::{
+        balance::simulation::{headless_game, SimParams},
+        bot::ai_player::AiPlayer,
+        buildings::player_commands::PlayerCommand,
+        game_timer::{
+            game_timer::InGameTime,
+            gameplay_schedule::{GameplayStage, GameplayTick, GAMEPLAY_STEP},
+        },
+        replay::playback::{
+            check_state_hashes, drive_replay_clock, feed_replay_commands, ReplayPlayback,
+        },
+        rules::game_rules::{Difficulty, GameMode, GameRules},
+        AppState,
+    };
+
+    use super::{
+        due_hash, prune_replays, sorted_replays, RecordedCommand, Replay, ReplayDir,
+        ReplayRecordingPlugin, StateHash, StateSnapshot,
+    };
+
+    fn snapshot() -> StateSnapshot {
+        StateSnapshot {
+            resources: [120, 5, 0],
+            buildings: vec![
+                ((-1, -1), "Main base", 1000),
+                ((3, 2), "Machine gun mk1", 80),
+            ],
+            aliens: 7,
+            kills: 12,
+            nests_destroyed: 1,
+        }
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        assert_eq!(snapshot().hash(), snapshot().hash());
+        // A different build of the game has to agree with this one
+        assert_eq!(StateSnapshot::default().hash(), 0x77e875b1c7b6a32d);
+    }
+
+    #[test]
+    fn any_change_changes_the_hash() {
+        let original = snapshot().hash();
+        let mut damaged = snapshot();
+        damaged.buildings[1].2 -= 1;
+        assert_ne!(damaged.hash(), original);
+        let mut richer = snapshot();
+        richer.resources[0] += 1;
+        assert_ne!(richer.hash(), original);
+        let mut moved = snapshot();
+        moved.buildings[1].0 = (2, 3);
+        assert_ne!(moved.hash(), original);
+    }
+
+    #[test]
+    fn hashes_are_due_every_interval() {
+        assert_eq!(due_hash(0, 0.), Some(0));
+        assert_eq!(due_hash(1, 0.99), None);
+        assert_eq!(due_hash(1, 1.01), Some(1));
+        assert_eq!(due_hash(1, 3.5), Some(3));
+    }
+
+    #[test]
+    fn replays_round_trip() {
+        let mut replay = Replay::new(GameRules {
+            seed: u64::MAX - 3,
+            ..default()
+        });
+        replay.length = 64.5;
+        replay.commands.push(RecordedCommand {
+            t: 12.25,
+            command: PlayerCommand::Build {
+                template: 2,
+                square: (4, -3),
+            },
+        });
+        replay.hashes.push(StateHash {
+            t_index: 3,
+            hash: u64::MAX,
+        });
+
+        let dir = std::env::temp_dir().join(format!("replay-test-{}", std::process::id()));
+        let path = dir.join("replay-1-2.json");
+        replay.save(&path).unwrap();
+        assert_eq!(Replay::load(&path).unwrap(), replay);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_newest_are_kept() {
+        let dir = std::env::temp_dir().join(format!("replay-prune-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for t in [5, 100, 30, 7] {
+            fs::write(dir.join(format!("replay-{}-0.json", t)), "{}").unwrap();
+        }
+        // Anything else in the folder is left alone
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        prune_replays(&dir, 2);
+        let saved = sorted_replays(&dir)
+            .iter()
+            .map(|f| f.saved)
+            .collect::<Vec<_>>();
+        assert_eq!(saved, vec![100, 30]);
+        assert!(dir.join("notes.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn elapsed(app: &App) -> f32 {
+        app.world.resource::<InGameTime>().timer.elapsed_secs()
+    }
+
+    // The in game time stops once the game is over
+    fn in_game(app: &App) -> bool {
+        app.world.resource::<State<AppState>>().current() == &AppState::InGame
+    }
+
+    // Up to a minute of the AI player against the aliens, headless, recorded and saved to `dir` like any other game
+    fn record(dir: &Path) -> Replay {
+        let rules = GameRules {
+            mode: GameMode::Endless,
+            difficulty: Difficulty::Easy,
+            seed: 11,
+            ..default()
+        };
+        let mut app = headless_game(rules, SimParams::default());
+        app.add_plugin(ReplayRecordingPlugin)
+            .insert_resource(ReplayDir(Some(dir.to_path_buf())));
+        let mut now = Instant::now();
+        app.world.resource_mut::<Time>().update_with_instant(now);
+        while in_game(&app) && elapsed(&app) < 60. {
+            now += GAMEPLAY_STEP;
+            app.world.resource_mut::<Time>().update_with_instant(now);
+            app.update();
+        }
+        // Quitting to the main menu saves it, if the base didn't fall before that
+        if in_game(&app) {
+            app.world
+                .resource_mut::<State<AppState>>()
+                .set(AppState::MainMenu)
+                .unwrap();
+            app.update();
+        }
+        let saved = sorted_replays(dir);
+        assert_eq!(saved.len(), 1);
+        return Replay::load(&saved[0].path).unwrap();
+    }
+
+    // Watches the replay at the speed, the clock is driven by the playback like in the game
+    fn watch(replay: &Replay, speed: u32) -> ReplayPlayback {
+        let mut app = headless_game(replay.rules, SimParams::default());
+        app.add_system_to_stage(CoreStage::First, drive_replay_clock)
+            .add_system_to_stage(
+                GameplayStage,
+                feed_replay_commands
+                    .after(GameplayTick::Start)
+                    .before(GameplayTick::Commands),
+            )
+            .add_system_to_stage(GameplayStage, check_state_hashes.after(GameplayTick::End));
+
+        let mut rules = *app.world.resource::<GameRules>();
+        let mut ai = app.world.remove_resource::<AiPlayer>().unwrap();
+        let mut playback = ReplayPlayback::default();
+        playback.start(replay.clone(), "test".to_string(), &mut rules, &mut ai);
+        playback.speed = speed;
+        app.insert_resource(rules)
+            .insert_resource(ai)
+            .insert_resource(playback);
+
+        while in_game(&app) && elapsed(&app) < replay.length {
+            app.update();
+        }
+        return app.world.remove_resource::<ReplayPlayback>().unwrap();
+    }
+
+    #[test]
+    fn replays_match_the_recording_at_every_speed() {
+        let dir = std::env::temp_dir().join(format!("replay-speeds-{}", std::process::id()));
+        let replay = record(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        // The AI player built something, every second has a hash
+        assert!(!replay.commands.is_empty());
+        assert_eq!(replay.hashes.len(), replay.length.floor() as usize + 1);
+
+        for speed in [1, 8] {
+            let playback = watch(&replay, speed);
+            assert_eq!(playback.desync, None, "desynced at {}x", speed);
+            assert_eq!(playback.matched, replay.hashes.len());
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aliens::{alien::Alien, alien_kinds::AlienSpeed, bosses::boss_phases},
+    buildings::resources::{ResourceSet, ResourceState},
+    game_timer::gameplay_schedule::{on_gameplay_tick, GameplayStage},
+    health::health::Health,
+    AppState,
+};
+
+// The rules of a single game: the mode, the difficulty, the map seed and any mutators the player turned on.
+// They're picked on the game setup screen before the game starts, everything that used to be a balance constant reads them from here.
+
+pub struct GameRulesPlugin;
+
+impl Plugin for GameRulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameRules>()
+            .init_resource::<GameRng>()
+            .add_system_set(SystemSet::on_enter(AppState::GameSetup).with_system(roll_seed))
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame)
+                    .with_system(reset_starting_resources)
+                    .with_system(reset_game_rng),
+            )
+            // Before anything in the tick sees the new aliens
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick().with_system(apply_alien_rules.before(boss_phases)),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    // Survive until the rescue ship comes
+    #[default]
+    Standard,
+    // The waves never stop growing, the game only ends when the base falls. Scored on the leaderboard
+    Endless,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 2] = [GameMode::Standard, GameMode::Endless];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Standard => "Standard",
+            GameMode::Endless => "Endless",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Nightmare,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+        Difficulty::Nightmare,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+            Difficulty::Nightmare => "Nightmare",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => {
+                "A long quiet start, more ore and weaker aliens. The ship comes early."
+            }
+            Difficulty::Normal => "The game as it's meant to be played.",
+            Difficulty::Hard => "Less ore, a short grace period and tougher, faster aliens.",
+            Difficulty::Nightmare => {
+                "No grace period, little ore and relentless waves. The ship takes its time."
+            }
+        }
+    }
+
+    // The period at the start of the game where aliens don't spawn
+    fn grace_period(&self) -> Duration {
+        match self {
+            Difficulty::Easy => Duration::from_secs(30),
+            Difficulty::Normal => Duration::from_secs(10),
+            Difficulty::Hard => Duration::from_secs(5),
+            Difficulty::Nightmare => Duration::ZERO,
+        }
+    }
+
+    // If the player survives this many minutes they win the game
+    fn win_minutes(&self) -> u64 {
+        match self {
+            Difficulty::Easy => 12,
+            Difficulty::Normal | Difficulty::Hard => 15,
+            Difficulty::Nightmare => 18,
+        }
+    }
+
+    fn starting_ore(&self) -> u16 {
+        match self {
+            Difficulty::Easy => 1500,
+            Difficulty::Normal => 1000,
+            Difficulty::Hard => 800,
+            Difficulty::Nightmare => 600,
+        }
+    }
+
+    // Multipliers on the alien stats and on how many of them spawn
+    fn alien_speed(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.85,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 1.1,
+            Difficulty::Nightmare => 1.2,
+        }
+    }
+
+    fn alien_health(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 1.3,
+            Difficulty::Nightmare => 1.75,
+        }
+    }
+
+    fn spawn_rate(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.7,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 1.3,
+            Difficulty::Nightmare => 1.7,
+        }
+    }
+}
+
+// Optional twists on top of the difficulty, any of them can be combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Mutators {
+    pub double_alien_speed: bool,
+    // Demolishing a building gives nothing back
+    pub no_refunds: bool,
+    pub double_starting_resources: bool,
+    // Killed aliens drop ore
+    pub alien_loot: bool,
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct GameRules {
+    pub mode: GameMode,
+    pub difficulty: Difficulty,
+    pub mutators: Mutators,
+    // The map and the nests are generated from it, a new one is rolled every time the setup screen opens
+    pub seed: u64,
+}
+
+// Ore dropped per this much max hp of the killed alien, with the loot mutator
+const LOOT_HP_PER_ORE: i32 = 20;
+
+impl GameRules {
+    pub fn endless(&self) -> bool {
+        self.mode == GameMode::Endless
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.difficulty.grace_period()
+    }
+
+    pub fn win_minutes(&self) -> u64 {
+        self.difficulty.win_minutes()
+    }
+
+    pub fn win_time(&self) -> Duration {
+        Duration::from_secs(60 * self.win_minutes())
+    }
+
+    pub fn starting_resources(&self) -> ResourceSet {
+        let mut ore = self.difficulty.starting_ore();
+        if self.mutators.double_starting_resources {
+            ore *= 2;
+        }
+        ResourceSet::new(ore, 0, 0)
+    }
+
+    pub fn alien_speed_multiplier(&self) -> f32 {
+        let mut speed = self.difficulty.alien_speed();
+        if self.mutators.double_alien_speed {
+            speed *= 2.;
+        }
+        return speed;
+    }
+
+    pub fn alien_health_multiplier(&self) -> f32 {
+        self.difficulty.alien_health()
+    }
+
+    pub fn spawn_rate_multiplier(&self) -> f32 {
+        self.difficulty.spawn_rate()
+    }
+
+    // What demolishing a building with this cost gives back
+    pub fn refund(&self, cost: &ResourceSet) -> ResourceSet {
+        if self.mutators.no_refunds {
+            return ResourceSet::new(0, 0, 0);
+        }
+        cost.div(2)
+    }
+
+    // The ore a killed alien drops, if any
+    pub fn loot(&self, max_hp: i32) -> u16 {
+        if !self.mutators.alien_loot {
+            return 0;
+        }
+        (max_hp / LOOT_HP_PER_ORE).clamp(1, u16::MAX as i32) as u16
+    }
+}
+
+pub fn roll_seed(mut rules: ResMut<GameRules>) {
+    rules.seed = rand::thread_rng().gen();
+}
+
+// Everything random during a game draws from this instead of thread_rng, so the same seed and the same commands play out the same way.
+// That's what makes replays possible
+#[derive(Resource)]
+pub struct GameRng(pub StdRng);
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng(StdRng::seed_from_u64(0))
+    }
+}
+
+// The stream is offset from the map and the nests, see nests::spawn_nests
+pub fn reset_game_rng(rules: Res<GameRules>, mut rng: ResMut<GameRng>) {
+    rng.0 = StdRng::seed_from_u64(rules.seed.wrapping_add(2));
+}
+
+// Every game starts with the resources of its rules, not what was left over from the last one
+pub fn reset_starting_resources(rules: Res<GameRules>, mut resources: ResMut<ResourceState>) {
+    resources.resources = rules.starting_resources();
+}
+
+// The alien templates are the Normal stats, scale every new alien to the rules of this game
+pub fn apply_alien_rules(
+    rules: Res<GameRules>,
+    mut aliens: Query<(&mut AlienSpeed, &mut Health), Added<Alien>>,
+) {
+    for (mut speed, mut health) in aliens.iter_mut() {
+        speed.0 *= rules.alien_speed_multiplier();
+        let hp = (health.max_hp as f32 * rules.alien_health_multiplier()).round() as i32;
+        *health = Health::new(hp.max(1));
+    }
+}
+
+#[cfg(test)]
+mod test_game_rules {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use crate::{
+        aliens::{alien::Alien, alien_kinds::AlienSpeed},
+        buildings::resources::{ResourceSet, ResourceType},
+        health::health::Health,
+    };
+
+    use super::{apply_alien_rules, Difficulty, GameRules, Mutators};
+
+    fn rules(difficulty: Difficulty, mutators: Mutators) -> GameRules {
+        GameRules {
+            difficulty,
+            mutators,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn normal_keeps_the_old_balance() {
+        let normal = GameRules::default();
+        assert_eq!(normal.win_time(), Duration::from_secs(15 * 60));
+        assert_eq!(
+            normal.starting_resources().get(ResourceType::Ore),
+            Some(1000)
+        );
+        assert_eq!(normal.alien_speed_multiplier(), 1.);
+        assert_eq!(normal.alien_health_multiplier(), 1.);
+        assert_eq!(normal.spawn_rate_multiplier(), 1.);
+    }
+
+    #[test]
+    fn difficulties_get_harder() {
+        for pair in Difficulty::ALL.windows(2) {
+            let (easier, harder) = (
+                rules(pair[0], Mutators::default()),
+                rules(pair[1], Mutators::default()),
+            );
+            assert!(easier.grace_period() >= harder.grace_period());
+            assert!(easier.win_time() <= harder.win_time());
+            assert!(
+                easier.starting_resources().get(ResourceType::Ore)
+                    > harder.starting_resources().get(ResourceType::Ore)
+            );
+            assert!(easier.alien_health_multiplier() < harder.alien_health_multiplier());
+            assert!(easier.spawn_rate_multiplier() < harder.spawn_rate_multiplier());
+        }
+    }
+
+    #[test]
+    fn mutators() {
+        let all = Mutators {
+            double_alien_speed: true,
+            no_refunds: true,
+            double_starting_resources: true,
+            alien_loot: true,
+        };
+        let normal = GameRules::default();
+        let mutated = rules(Difficulty::Normal, all);
+
+        assert_eq!(mutated.alien_speed_multiplier(), 2.);
+        assert_eq!(
+            mutated.starting_resources().get(ResourceType::Ore),
+            Some(2000)
+        );
+
+        let cost = ResourceSet::new(100, 50, 0);
+        assert_eq!(normal.refund(&cost), ResourceSet::new(50, 25, 0));
+        assert_eq!(mutated.refund(&cost), ResourceSet::new(0, 0, 0));
+
+        assert_eq!(normal.loot(200), 0);
+        assert_eq!(mutated.loot(200), 10);
+        // Even the smallest alien drops something
+        assert_eq!(mutated.loot(5), 1);
+    }
+
+    #[test]
+    fn new_aliens_are_scaled() {
+        let mut app = App::new();
+        app.insert_resource(rules(Difficulty::Nightmare, Mutators::default()))
+            .add_system(apply_alien_rules);
+
+        let alien = app
+            .world
+            .spawn((Alien::default(), AlienSpeed(5.), Health::new(100)))
+            .id();
+        app.update();
+        // Only once, not every frame
+        app.update();
+
+        let speed = app.world.get::<AlienSpeed>(alien).unwrap().0;
+        let health = app.world.get::<Health>(alien).unwrap();
+        assert!((speed - 6.).abs() < 0.001);
+        assert_eq!(health.max_hp, 175);
+        assert_eq!(health.hp, 175);
+    }
+}
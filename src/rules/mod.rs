@@ -0,0 +1 @@
+pub mod game_rules;
\ No newline at end of file
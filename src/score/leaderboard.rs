@@ -0,0 +1,130 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    rules::game_rules::Difficulty,
+    settings::config_file::{load_config, save_config},
+};
+
+// The local high score table of endless runs.
+// It's saved next to the settings, sorted from the best run down and cut off at LEADERBOARD_SIZE.
+
+const LEADERBOARD_FILE: &str = "leaderboard.ron";
+pub const LEADERBOARD_SIZE: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub score: u32,
+    pub seconds: u64,
+    pub kills: u32,
+    pub resources: u32,
+    // The map seed the run was played on
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    // Seconds since the unix epoch
+    pub date: u64,
+}
+
+#[derive(Resource, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub entries: Vec<HighScore>,
+}
+
+impl Leaderboard {
+    pub fn load() -> Self {
+        let mut leaderboard: Leaderboard = load_config(LEADERBOARD_FILE).unwrap_or_default();
+        // In case the file was edited by hand
+        leaderboard.entries.sort_by(|a, b| b.score.cmp(&a.score));
+        leaderboard.entries.truncate(LEADERBOARD_SIZE);
+        return leaderboard;
+    }
+
+    pub fn save(&self) {
+        save_config(LEADERBOARD_FILE, self);
+    }
+
+    // Puts the run in its place and returns it, or None if it's not good enough to be on the table.
+    // A tie goes below the older runs
+    pub fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        let rank = self
+            .entries
+            .iter()
+            .position(|e| e.score < entry.score)
+            .unwrap_or(self.entries.len());
+        if rank >= LEADERBOARD_SIZE {
+            return None;
+        }
+        self.entries.insert(rank, entry);
+        self.entries.truncate(LEADERBOARD_SIZE);
+        return Some(rank);
+    }
+}
+
+// Formats the seconds since the unix epoch as a YYYY-MM-DD date, in UTC.
+// The days are turned into a date with Howard Hinnant's civil_from_days
+pub fn format_date(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod test_leaderboard {
+    use crate::rules::game_rules::Difficulty;
+
+    use super::{format_date, HighScore, Leaderboard, LEADERBOARD_SIZE};
+
+    fn entry(score: u32) -> HighScore {
+        HighScore {
+            score,
+            seconds: 0,
+            kills: 0,
+            resources: 0,
+            seed: score as u64,
+            difficulty: Difficulty::Normal,
+            date: 0,
+        }
+    }
+
+    #[test]
+    fn sorted_best_first() {
+        let mut leaderboard = Leaderboard::default();
+        assert_eq!(leaderboard.insert(entry(100)), Some(0));
+        assert_eq!(leaderboard.insert(entry(300)), Some(0));
+        assert_eq!(leaderboard.insert(entry(200)), Some(1));
+        // Ties go below
+        assert_eq!(leaderboard.insert(entry(200)), Some(2));
+        let scores: Vec<_> = leaderboard.entries.iter().map(|e| e.score).collect();
+        assert_eq!(scores, vec![300, 200, 200, 100]);
+    }
+
+    #[test]
+    fn only_the_top_runs_are_kept() {
+        let mut leaderboard = Leaderboard::default();
+        for i in 0..LEADERBOARD_SIZE as u32 {
+            leaderboard.insert(entry(100 + i));
+        }
+        assert_eq!(leaderboard.insert(entry(50)), None);
+        assert_eq!(leaderboard.entries.len(), LEADERBOARD_SIZE);
+
+        assert_eq!(leaderboard.insert(entry(1000)), Some(0));
+        assert_eq!(leaderboard.entries.len(), LEADERBOARD_SIZE);
+        // The worst one fell off
+        assert_eq!(leaderboard.entries.last().unwrap().score, 101);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(format_date(1792368000), "2026-10-19");
+    }
+}
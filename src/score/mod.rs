@@ -0,0 +1,2 @@
+pub mod leaderboard;
+pub mod score;
\ No newline at end of file
@@ -0,0 +1,164 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+
+use crate::{
+    game_timer::game_timer::InGameTime, replay::playback::ReplayPlayback,
+    rules::game_rules::GameRules, stats::game_stats::GameStats, AppState,
+};
+
+use super::leaderboard::{HighScore, Leaderboard};
+
+// Scoring of endless runs.
+// The score comes from how long the base survived, how many aliens the turrets killed and how many resources were gathered.
+// When an endless run ends it's put on the local leaderboard, if it's good enough.
+
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LastRun>()
+            .insert_resource(Leaderboard::load())
+            .add_system_set(
+                SystemSet::on_enter(AppState::GameOver)
+                    .with_system(record_run)
+                    .with_system(save_leaderboard.after(record_run)),
+            );
+    }
+}
+
+const POINTS_PER_SECOND: u32 = 10;
+const POINTS_PER_KILL: u32 = 25;
+// Points per resource gathered, of any type
+const POINTS_PER_RESOURCE: u32 = 1;
+
+// The last run that was recorded, so the game over screen can show it
+#[derive(Resource, Debug, Clone, Default)]
+pub struct LastRun {
+    pub score: Option<u32>,
+    // The place on the leaderboard, None if it didn't make it
+    pub rank: Option<usize>,
+}
+
+// The kills and resources come from the GameStats, which are counted in every game
+pub fn score(survived: Duration, stats: &GameStats) -> u32 {
+    survived.as_secs() as u32 * POINTS_PER_SECOND
+        + stats.kills * POINTS_PER_KILL
+        + stats.resources_earned.total() * POINTS_PER_RESOURCE
+}
+
+// Only endless runs are scored, a standard game is either won or lost
+pub fn record_run(
+    time: Res<InGameTime>,
+    stats: Res<GameStats>,
+    rules: Res<GameRules>,
+    mut leaderboard: ResMut<Leaderboard>,
+    mut last_run: ResMut<LastRun>,
+    playback: Res<ReplayPlayback>,
+) {
+    // Watching a replay doesn't count as a run
+    if !rules.endless() || playback.active() {
+        *last_run = LastRun::default();
+        return;
+    }
+
+    let survived = time.timer.elapsed();
+    let entry = HighScore {
+        score: score(survived, &stats),
+        seconds: survived.as_secs(),
+        kills: stats.kills,
+        resources: stats.resources_earned.total(),
+        seed: rules.seed,
+        difficulty: rules.difficulty,
+        date: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    };
+
+    *last_run = LastRun {
+        score: Some(entry.score),
+        rank: leaderboard.insert(entry),
+    };
+}
+
+// Kept apart from record_run so the tests don't write to the real leaderboard file
+pub fn save_leaderboard(leaderboard: Res<Leaderboard>, last_run: Res<LastRun>) {
+    if last_run.rank.is_some() {
+        leaderboard.save();
+    }
+}
+
+#[cfg(test)]
+mod test_score {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use crate::{
+        buildings::resources::ResourceType,
+        game_timer::game_timer::InGameTime,
+        replay::playback::ReplayPlayback,
+        rules::game_rules::{GameMode, GameRules},
+        score::leaderboard::Leaderboard,
+        stats::game_stats::GameStats,
+    };
+
+    use super::{record_run, score, LastRun};
+
+    #[test]
+    fn everything_counts() {
+        let nothing = GameStats::default();
+        assert_eq!(score(Duration::ZERO, &nothing), 0);
+        assert!(
+            score(Duration::from_secs(60), &nothing) > score(Duration::from_secs(30), &nothing)
+        );
+
+        let mut kills = GameStats::default();
+        kills.kills = 10;
+        let mut resources = GameStats::default();
+        resources.resources_earned.add(ResourceType::Gas, 500);
+        assert!(score(Duration::ZERO, &kills) > 0);
+        assert!(score(Duration::ZERO, &resources) > 0);
+    }
+
+    fn run(mode: GameMode) -> (Leaderboard, LastRun) {
+        let mut app = App::new();
+        let mut time = InGameTime {
+            timer: Default::default(),
+        };
+        time.timer.tick(Duration::from_secs(120));
+        let mut stats = GameStats::default();
+        stats.kills = 3;
+        stats.resources_earned.add(ResourceType::Ore, 100);
+        app.insert_resource(time)
+            .insert_resource(stats)
+            .insert_resource(GameRules {
+                mode,
+                seed: 42,
+                ..default()
+            })
+            .insert_resource(Leaderboard::default())
+            .init_resource::<LastRun>()
+            .init_resource::<ReplayPlayback>()
+            .add_system(record_run);
+        app.update();
+        (
+            app.world.resource::<Leaderboard>().clone(),
+            app.world.resource::<LastRun>().clone(),
+        )
+    }
+
+    #[test]
+    fn endless_runs_are_recorded() {
+        let (leaderboard, last_run) = run(GameMode::Endless);
+        assert_eq!(leaderboard.entries.len(), 1);
+        assert_eq!(leaderboard.entries[0].seed, 42);
+        assert_eq!(leaderboard.entries[0].seconds, 120);
+        assert_eq!(last_run.rank, Some(0));
+        assert_eq!(last_run.score, Some(leaderboard.entries[0].score));
+
+        let (leaderboard, last_run) = run(GameMode::Standard);
+        assert!(leaderboard.entries.is_empty());
+        assert_eq!(last_run.score, None);
+    }
+}
@@ -0,0 +1,61 @@
+use std::{fs, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+// Everything the player can configure (key bindings etc.) is stored as a RON file
+// in the user's config directory, e.g. ~/.config/deep_space_defenders/ on linux.
+
+const CONFIG_DIR_NAME: &str = "deep_space_defenders";
+
+// Returns the full path of a config file, or None if the platform has no config directory
+pub fn config_path(file_name: &str) -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME).join(file_name))
+}
+
+// Loads and parses a config file.
+// Returns None if the file doesn't exist yet or can't be parsed, the caller should then fall back to the defaults
+pub fn load_config<T: DeserializeOwned>(file_name: &str) -> Option<T> {
+    let path = config_path(file_name)?;
+    let string = fs::read_to_string(&path).ok()?;
+
+    match ron::from_str(&string) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            println!("Could not parse {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+// Writes the config file, creating the config directory if needed.
+// Failing to save isn't fatal for the game, so we only log the error
+pub fn save_config<T: Serialize>(file_name: &str, config: &T) {
+    let path = match config_path(file_name) {
+        Some(path) => path,
+        None => {
+            println!(
+                "Could not find a config directory, {} won't be saved",
+                file_name
+            );
+            return;
+        }
+    };
+
+    let string = match ron::ser::to_string_pretty(config, ron::ser::PrettyConfig::default()) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Could not serialize {}: {}", file_name, e);
+            return;
+        }
+    };
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            println!("Could not create {}: {}", dir.display(), e);
+            return;
+        }
+    }
+    if let Err(e) = fs::write(&path, string) {
+        println!("Could not write {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,220 @@
+use bevy::{pbr::DirectionalLightShadowMap, prelude::*, window::WindowMode};
+use bevy_egui::EguiSettings;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::channels::SoundChannel;
+
+use super::config_file::{load_config, save_config};
+
+// The graphics, audio and window options the player can change in the settings menu.
+// They are loaded in main() before the app is built, so the window is created with the right mode and size,
+// and applied again every time they change.
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(apply_settings);
+    }
+}
+
+const SETTINGS_FILE: &str = "settings.ron";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowModeSetting {
+    Windowed,
+    BorderlessFullscreen,
+    // Exclusive fullscreen using the chosen resolution
+    Fullscreen,
+}
+
+impl WindowModeSetting {
+    pub const ALL: [WindowModeSetting; 3] = [
+        WindowModeSetting::Windowed,
+        WindowModeSetting::BorderlessFullscreen,
+        WindowModeSetting::Fullscreen,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WindowModeSetting::Windowed => "Windowed",
+            WindowModeSetting::BorderlessFullscreen => "Borderless fullscreen",
+            WindowModeSetting::Fullscreen => "Fullscreen",
+        }
+    }
+}
+
+impl From<WindowModeSetting> for WindowMode {
+    fn from(mode: WindowModeSetting) -> Self {
+        match mode {
+            WindowModeSetting::Windowed => WindowMode::Windowed,
+            WindowModeSetting::BorderlessFullscreen => WindowMode::BorderlessFullscreen,
+            WindowModeSetting::Fullscreen => WindowMode::SizedFullscreen,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShadowQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    pub const ALL: [ShadowQuality; 4] = [
+        ShadowQuality::Off,
+        ShadowQuality::Low,
+        ShadowQuality::Medium,
+        ShadowQuality::High,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShadowQuality::Off => "Off",
+            ShadowQuality::Low => "Low",
+            ShadowQuality::Medium => "Medium",
+            ShadowQuality::High => "High",
+        }
+    }
+
+    // The size of the directional light shadow map. High is what the game always used before the settings existed
+    pub fn shadow_map_size(&self) -> usize {
+        match self {
+            ShadowQuality::Off | ShadowQuality::Low => 2_048,
+            ShadowQuality::Medium => 4_096,
+            ShadowQuality::High => 8_000,
+        }
+    }
+
+    pub fn shadows_enabled(&self) -> bool {
+        *self != ShadowQuality::Off
+    }
+}
+
+pub const RESOLUTIONS: [(f32, f32); 5] = [
+    (1280., 720.),
+    (1600., 900.),
+    (1920., 1080.),
+    (2560., 1440.),
+    (3840., 2160.),
+];
+
+// Bevy only supports 1 (off) and 4 samples
+pub const MSAA_SAMPLES: [u32; 2] = [1, 4];
+
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameSettings {
+    pub window_mode: WindowModeSetting,
+    pub resolution: (f32, f32),
+    pub shadow_quality: ShadowQuality,
+    // Can only be applied at startup
+    pub msaa_samples: u32,
+    // All volumes are between 0 and 1
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub weapons_volume: f32,
+    pub aliens_volume: f32,
+    pub ui_volume: f32,
+    pub alerts_volume: f32,
+    pub muted: bool,
+    pub ui_scale: f32,
+    // Writes a log of the gameplay events of every game, see telemetry
+    pub telemetry: bool,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            window_mode: WindowModeSetting::BorderlessFullscreen,
+            resolution: (1920., 1080.),
+            shadow_quality: ShadowQuality::High,
+            msaa_samples: 4,
+            master_volume: 1.,
+            music_volume: 0.6,
+            weapons_volume: 1.,
+            aliens_volume: 1.,
+            ui_volume: 1.,
+            alerts_volume: 1.,
+            muted: false,
+            ui_scale: 1.,
+            telemetry: false,
+        }
+    }
+}
+
+impl GameSettings {
+    pub fn load() -> Self {
+        load_config(SETTINGS_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        save_config(SETTINGS_FILE, self);
+    }
+
+    pub fn channel_volume_mut(&mut self, channel: SoundChannel) -> &mut f32 {
+        match channel {
+            SoundChannel::Music => &mut self.music_volume,
+            SoundChannel::Weapons => &mut self.weapons_volume,
+            SoundChannel::Aliens => &mut self.aliens_volume,
+            SoundChannel::Ui => &mut self.ui_volume,
+            SoundChannel::Alerts => &mut self.alerts_volume,
+        }
+    }
+
+    // The multiplier the audio systems apply on top of each sound's own volume
+    pub fn channel_volume(&self, channel: SoundChannel) -> f64 {
+        if self.muted {
+            return 0.;
+        }
+        let volume = match channel {
+            SoundChannel::Music => self.music_volume,
+            SoundChannel::Weapons => self.weapons_volume,
+            SoundChannel::Aliens => self.aliens_volume,
+            SoundChannel::Ui => self.ui_volume,
+            SoundChannel::Alerts => self.alerts_volume,
+        };
+        (self.master_volume * volume) as f64
+    }
+
+    pub fn window_descriptor(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            mode: self.window_mode.into(),
+            width: self.resolution.0,
+            height: self.resolution.1,
+            ..Default::default()
+        }
+    }
+}
+
+// Applies the settings whenever they change (and once on startup, when the resource is first added)
+pub fn apply_settings(
+    settings: Res<GameSettings>,
+    mut windows: ResMut<Windows>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+    mut lights: Query<&mut DirectionalLight>,
+    mut egui_settings: ResMut<EguiSettings>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        if window.mode() != settings.window_mode.into() {
+            window.set_mode(settings.window_mode.into());
+        }
+        let (width, height) = settings.resolution;
+        if window.requested_width() != width || window.requested_height() != height {
+            window.set_resolution(width, height);
+        }
+    }
+
+    shadow_map.size = settings.shadow_quality.shadow_map_size();
+    for mut light in lights.iter_mut() {
+        light.shadows_enabled = settings.shadow_quality.shadows_enabled();
+    }
+
+    egui_settings.scale_factor = settings.ui_scale as f64;
+}
@@ -0,0 +1 @@
+pub mod spatial_index;
\ No newline at end of file
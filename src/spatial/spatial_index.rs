@@ -0,0 +1,354 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    aliens::{alien::Alien, nests::Nest},
+    buildings::defensive_buildings::AlienTarget,
+    game_timer::gameplay_schedule::{GameplayStage, GameplayTick},
+    health::health::Health,
+};
+
+// A uniform grid of the aliens, the nests and the alien targets, so the targeting systems only look at what's close to them
+// instead of scanning every entity. It's rebuilt at the start of every gameplay tick, with where the physics left everything.
+// Only the horizontal position is used, the same as the range checks of the buildings.
+
+pub struct SpatialIndexPlugin;
+
+impl Plugin for SpatialIndexPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialIndex>().add_system_to_stage(
+            GameplayStage,
+            update_spatial_index.label(GameplayTick::Start),
+        );
+    }
+}
+
+// The machine gun range, so most range queries only touch the 9 cells around the turret
+const CELL_SIZE: f32 = 8.;
+
+#[derive(Debug, Clone)]
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(Entity, Vec3)>>,
+    // The range of cells with anything in them, nearest() stops searching past it
+    min_cell: (i32, i32),
+    max_cell: (i32, i32),
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            min_cell: (i32::MAX, i32::MAX),
+            max_cell: (i32::MIN, i32::MIN),
+        }
+    }
+
+    fn cell(&self, position: Vec3) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.z / self.cell_size).floor() as i32,
+        )
+    }
+
+    pub fn clear(&mut self) {
+        // Keep the vectors around, so we don't reallocate them every frame
+        for entities in self.cells.values_mut() {
+            entities.clear();
+        }
+        self.min_cell = (i32::MAX, i32::MAX);
+        self.max_cell = (i32::MIN, i32::MIN);
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vec3) {
+        let cell = self.cell(position);
+        self.min_cell = (self.min_cell.0.min(cell.0), self.min_cell.1.min(cell.1));
+        self.max_cell = (self.max_cell.0.max(cell.0), self.max_cell.1.max(cell.1));
+        self.cells.entry(cell).or_default().push((entity, position));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min_cell.0 > self.max_cell.0
+    }
+
+    // Every entity in the hash, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        self.cells
+            .values()
+            .flat_map(|entities| entities.iter().copied())
+    }
+
+    // All the entities within the radius
+    pub fn query_radius(
+        &self,
+        position: Vec3,
+        radius: f32,
+    ) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        let min = self.cell(position - Vec3::new(radius, 0., radius));
+        let max = self.cell(position + Vec3::new(radius, 0., radius));
+        (min.0..=max.0)
+            .flat_map(move |x| (min.1..=max.1).map(move |z| (x, z)))
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flat_map(|entities| entities.iter().copied())
+            .filter(move |(_, p)| horizontal_distance(position, *p) <= radius)
+    }
+
+    // The closest entity within max_radius that passes the filter
+    pub fn nearest(
+        &self,
+        position: Vec3,
+        max_radius: f32,
+        mut filter: impl FnMut(Entity) -> bool,
+    ) -> Option<(Entity, Vec3)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // Search the cells in rings around the position.
+        // Everything in ring k+1 is at least k cells away, so once we have something closer than that we can stop
+        let center = self.cell(position);
+        let max_ring = ((max_radius / self.cell_size).ceil() as i32).saturating_add(1);
+        // The rings before the first occupied cell are empty, no need to look at them
+        let gap = |c: i32, min: i32, max: i32| (min - c).max(c - max).max(0);
+        let first_ring = gap(center.0, self.min_cell.0, self.max_cell.0).max(gap(
+            center.1,
+            self.min_cell.1,
+            self.max_cell.1,
+        ));
+        let mut best: Option<(Entity, Vec3, f32)> = None;
+
+        for ring in first_ring..=max_ring {
+            for x in (center.0 - ring)..=(center.0 + ring) {
+                for z in (center.1 - ring)..=(center.1 + ring) {
+                    // Only the edge of the ring, the inside was searched already
+                    if (x - center.0).abs() != ring && (z - center.1).abs() != ring {
+                        continue;
+                    }
+                    let entities = match self.cells.get(&(x, z)) {
+                        Some(entities) => entities,
+                        None => continue,
+                    };
+                    for (e, p) in entities.iter() {
+                        let d = horizontal_distance(position, *p);
+                        if d > max_radius || best.map_or(false, |b| d >= b.2) || !filter(*e) {
+                            continue;
+                        }
+                        best = Some((*e, *p, d));
+                    }
+                }
+            }
+
+            if let Some((_, _, d)) = best {
+                if d <= ring as f32 * self.cell_size {
+                    break;
+                }
+            }
+            // Nothing left to find outside of this ring
+            let covered = center.0 - ring <= self.min_cell.0
+                && center.1 - ring <= self.min_cell.1
+                && center.0 + ring >= self.max_cell.0
+                && center.1 + ring >= self.max_cell.1;
+            if covered {
+                break;
+            }
+        }
+
+        return best.map(|(e, p, _)| (e, p));
+    }
+}
+
+pub fn horizontal_distance(a: Vec3, b: Vec3) -> f32 {
+    Vec2::new(a.x, a.z).distance(Vec2::new(b.x, b.z))
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct SpatialIndex {
+    // Living aliens
+    pub aliens: SpatialHash,
+    // Living buildings the aliens can attack
+    pub targets: SpatialHash,
+    // Living alien nests, the turrets shoot them when there are no aliens in range
+    pub nests: SpatialHash,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        Self {
+            aliens: SpatialHash::new(CELL_SIZE),
+            targets: SpatialHash::new(CELL_SIZE),
+            nests: SpatialHash::new(CELL_SIZE),
+        }
+    }
+}
+
+pub fn update_spatial_index(
+    mut index: ResMut<SpatialIndex>,
+    aliens: Query<(Entity, &Transform, &Alien, &Health)>,
+    targets: Query<(Entity, &Transform, &Health), (With<AlienTarget>, Without<Alien>)>,
+    nests: Query<(Entity, &Transform, &Health), With<Nest>>,
+) {
+    index.aliens.clear();
+    for (e, t, alien, health) in aliens.iter() {
+        if alien.alive && health.hp > 0 {
+            index.aliens.insert(e, t.translation);
+        }
+    }
+
+    index.targets.clear();
+    for (e, t, health) in targets.iter() {
+        if health.hp > 0 {
+            index.targets.insert(e, t.translation);
+        }
+    }
+
+    index.nests.clear();
+    for (e, t, health) in nests.iter() {
+        if health.hp > 0 {
+            index.nests.insert(e, t.translation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_spatial_index {
+    use std::time::Instant;
+
+    use bevy::prelude::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{horizontal_distance, SpatialHash};
+
+    fn random_points(rng: &mut StdRng, count: usize, size: f32) -> Vec<(Entity, Vec3)> {
+        (0..count)
+            .map(|i| {
+                let p = Vec3::new(rng.gen_range(-size..size), 0., rng.gen_range(-size..size));
+                (Entity::from_raw(i as u32), p)
+            })
+            .collect()
+    }
+
+    fn brute_force_nearest(points: &[(Entity, Vec3)], position: Vec3, max: f32) -> Option<Entity> {
+        points
+            .iter()
+            .filter(|(_, p)| horizontal_distance(position, *p) <= max)
+            .min_by(|a, b| {
+                horizontal_distance(position, a.1).total_cmp(&horizontal_distance(position, b.1))
+            })
+            .map(|(e, _)| *e)
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let points = random_points(&mut rng, 500, 100.);
+        let mut hash = SpatialHash::new(8.);
+        for (e, p) in points.iter() {
+            hash.insert(*e, *p);
+        }
+
+        for _ in 0..200 {
+            let position = Vec3::new(rng.gen_range(-150. ..150.), 0., rng.gen_range(-150. ..150.));
+            let radius = rng.gen_range(0. ..30.);
+
+            let mut found = hash
+                .query_radius(position, radius)
+                .map(|(e, _)| e)
+                .collect::<Vec<_>>();
+            let mut expected = points
+                .iter()
+                .filter(|(_, p)| horizontal_distance(position, *p) <= radius)
+                .map(|(e, _)| *e)
+                .collect::<Vec<_>>();
+            found.sort();
+            expected.sort();
+            assert_eq!(found, expected);
+
+            let nearest = hash.nearest(position, f32::INFINITY, |_| true).map(|n| n.0);
+            assert_eq!(
+                nearest,
+                brute_force_nearest(&points, position, f32::INFINITY)
+            );
+            let nearest = hash.nearest(position, radius, |_| true).map(|n| n.0);
+            assert_eq!(nearest, brute_force_nearest(&points, position, radius));
+        }
+    }
+
+    #[test]
+    fn nearest_respects_the_filter() {
+        let mut hash = SpatialHash::new(8.);
+        hash.insert(Entity::from_raw(1), Vec3::new(1., 0., 0.));
+        hash.insert(Entity::from_raw(2), Vec3::new(50., 0., 0.));
+        let nearest = hash.nearest(Vec3::ZERO, f32::INFINITY, |e| e != Entity::from_raw(1));
+        assert_eq!(nearest.map(|n| n.0), Some(Entity::from_raw(2)));
+        assert_eq!(
+            SpatialHash::new(8.).nearest(Vec3::ZERO, 10., |_| true),
+            None
+        );
+    }
+
+    // Compares the targeting queries with the brute force scans the systems used to do.
+    // Run with: cargo test --release spatial_index_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn spatial_index_benchmark() {
+        const ALIENS: usize = 1000;
+        const TURRETS: usize = 200;
+        const FRAMES: u32 = 100;
+        const RANGE: f32 = 8.;
+
+        let mut rng = StdRng::seed_from_u64(9);
+        let aliens = random_points(&mut rng, ALIENS, 80.);
+        let turrets = random_points(&mut rng, TURRETS, 30.);
+
+        // Every turret looks for an alien in range, every alien looks for the nearest turret
+        let start = Instant::now();
+        let mut found = 0;
+        for _ in 0..FRAMES {
+            for (_, turret) in turrets.iter() {
+                found += aliens
+                    .iter()
+                    .find(|(_, a)| horizontal_distance(*a, *turret) < RANGE)
+                    .is_some() as usize;
+            }
+            for (_, alien) in aliens.iter() {
+                found += brute_force_nearest(&turrets, *alien, f32::INFINITY).is_some() as usize;
+            }
+        }
+        let brute_force = start.elapsed() / FRAMES;
+
+        let start = Instant::now();
+        let mut alien_hash = SpatialHash::new(8.);
+        let mut turret_hash = SpatialHash::new(8.);
+        let mut found_indexed = 0;
+        for _ in 0..FRAMES {
+            alien_hash.clear();
+            turret_hash.clear();
+            for (e, p) in aliens.iter() {
+                alien_hash.insert(*e, *p);
+            }
+            for (e, p) in turrets.iter() {
+                turret_hash.insert(*e, *p);
+            }
+
+            for (_, turret) in turrets.iter() {
+                found_indexed += alien_hash.nearest(*turret, RANGE, |_| true).is_some() as usize;
+            }
+            for (_, alien) in aliens.iter() {
+                found_indexed += turret_hash
+                    .nearest(*alien, f32::INFINITY, |_| true)
+                    .is_some() as usize;
+            }
+        }
+        let indexed = start.elapsed() / FRAMES;
+
+        assert_eq!(found, found_indexed);
+        println!(
+            "{} aliens x {} turrets: brute force {:?} per frame, spatial hash {:?} per frame ({:.1}x faster)",
+            ALIENS,
+            TURRETS,
+            brute_force,
+            indexed,
+            brute_force.as_secs_f64() / indexed.as_secs_f64()
+        );
+    }
+}
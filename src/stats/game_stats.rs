@@ -0,0 +1,432 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    aliens::alien::{alien_death, Alien, AlienCount},
+    buildings::{
+        building_bundles::BuildingInfoComponent,
+        player_commands::{CommandOutcome, CommandResult},
+        resources::{ResourceSet, ResourceState, ResourceType},
+    },
+    game_timer::{
+        game_timer::InGameTime,
+        gameplay_schedule::{on_gameplay_tick, GameplayStage},
+    },
+    health::health::DeathEvent,
+    main_base::main_base::MainBaseComponent,
+    rules::game_rules::GameRules,
+    settings::config_file::config_path,
+    AppState,
+};
+
+// Statistics of the current run, shown on the game over and victory screens and exportable as JSON.
+// Most of them are counted here from events and new entities, the resource income is added where it's generated.
+// Every SAMPLE_INTERVAL the resources and the alien count are sampled for the graphs.
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameStats>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(reset_stats))
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(count_new_buildings)
+                    .with_system(count_upgrades)
+                    .with_system(sample_stats),
+            )
+            // The kills are part of the replays' state hashes, they're counted in the tick they happened
+            .add_system_set_to_stage(
+                GameplayStage,
+                on_gameplay_tick().with_system(count_deaths.after(alien_death)),
+            )
+            // The last bit of the game since the last sample
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(final_sample));
+    }
+}
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+// The exports go in this folder of the config directory
+const EXPORT_DIR: &str = "stats";
+
+// The amount of each resource type, for resources earned and spent.
+// A ResourceSet is too small for totals over a whole game
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceTally {
+    pub ore: u32,
+    pub gas: u32,
+    pub crystal: u32,
+}
+
+impl ResourceTally {
+    pub fn add(&mut self, r: ResourceType, amount: u32) {
+        match r {
+            ResourceType::Ore => self.ore += amount,
+            ResourceType::Gas => self.gas += amount,
+            ResourceType::Crystal => self.crystal += amount,
+        }
+    }
+
+    pub fn add_set(&mut self, set: &ResourceSet) {
+        for r in ResourceType::ALL {
+            self.add(r, set.get(r).unwrap_or(0) as u32);
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.ore + self.gas + self.crystal
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TurretStats {
+    pub name: &'static str,
+    pub kills: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StatsSample {
+    pub seconds: f32,
+    // The resources in the bank at that time
+    pub ore: u32,
+    pub gas: u32,
+    pub crystal: u32,
+    pub aliens: u32,
+}
+
+#[derive(Resource, Debug, Clone, Default, Serialize)]
+pub struct GameStats {
+    pub seconds: f32,
+    pub kills: u32,
+    pub buildings_built: u32,
+    // Destroyed by the aliens
+    pub buildings_lost: u32,
+    pub buildings_demolished: u32,
+    // Generated and looted
+    pub resources_earned: ResourceTally,
+    pub resources_spent: ResourceTally,
+    // Given back for demolished buildings
+    pub resources_refunded: ResourceTally,
+    pub peak_aliens: u32,
+    // Every turret that killed something, in the order of their first kill
+    pub turrets: Vec<TurretStats>,
+    pub samples: Vec<StatsSample>,
+
+    // An upgraded turret is the same entity under a new name, its kills go on the new name
+    #[serde(skip)]
+    turret_index: HashMap<(Entity, &'static str), usize>,
+    // Death events can come multiple times for the same entity, every death is only counted once
+    #[serde(skip)]
+    counted_deaths: HashSet<Entity>,
+    // In game seconds
+    #[serde(skip)]
+    next_sample: f32,
+    // Where the stats were exported to, or the error, shown on the end screens
+    #[serde(skip)]
+    pub export_message: Option<String>,
+}
+
+impl GameStats {
+    pub fn record_kill(&mut self, turret: Entity, name: &'static str) {
+        self.kills += 1;
+        let i = *self.turret_index.entry((turret, name)).or_insert_with(|| {
+            self.turrets.push(TurretStats { name, kills: 0 });
+            self.turrets.len() - 1
+        });
+        self.turrets[i].kills += 1;
+    }
+
+    // The turret with the most kills. On a tie the one that got there first
+    pub fn top_turret(&self) -> Option<&TurretStats> {
+        self.turrets.iter().rev().max_by_key(|t| t.kills)
+    }
+
+    fn first_death(&mut self, e: Entity) -> bool {
+        self.counted_deaths.insert(e)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // Writes the stats to a new file in the config directory and returns its path
+    pub fn export(&self) -> Result<PathBuf, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = config_path(EXPORT_DIR)
+            .ok_or("Could not find a config directory")?
+            .join(format!("run-{}.json", timestamp));
+
+        let json = self.to_json().map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(&path, json).map_err(|e| e.to_string())?;
+        return Ok(path);
+    }
+}
+
+pub fn reset_stats(mut stats: ResMut<GameStats>) {
+    *stats = GameStats::default();
+}
+
+// Alien kills by the turrets, and buildings destroyed or demolished.
+// Demolishing sends a death event without a killer
+pub fn count_deaths(
+    mut ev: EventReader<DeathEvent>,
+    mut stats: ResMut<GameStats>,
+    rules: Res<GameRules>,
+    aliens: Query<(), With<Alien>>,
+    buildings: Query<&ResourceSet, (With<BuildingInfoComponent>, Without<MainBaseComponent>)>,
+    turrets: Query<&BuildingInfoComponent>,
+) {
+    for e in ev.iter() {
+        if aliens.contains(e.entity) {
+            // Exploders blowing themselves up don't count
+            let turret = match e.killer.and_then(|k| turrets.get(k).ok().map(|t| (k, t))) {
+                Some(turret) => turret,
+                None => continue,
+            };
+            if stats.first_death(e.entity) {
+                stats.record_kill(turret.0, turret.1.name);
+            }
+        } else if let Ok(cost) = buildings.get(e.entity) {
+            if !stats.first_death(e.entity) {
+                continue;
+            }
+            if e.killer.is_some() {
+                stats.buildings_lost += 1;
+            } else {
+                stats.buildings_demolished += 1;
+                stats.resources_refunded.add_set(&rules.refund(cost));
+            }
+        }
+    }
+}
+
+pub fn count_new_buildings(
+    mut stats: ResMut<GameStats>,
+    buildings: Query<&ResourceSet, (Added<BuildingInfoComponent>, Without<MainBaseComponent>)>,
+) {
+    for cost in buildings.iter() {
+        stats.buildings_built += 1;
+        stats.resources_spent.add_set(cost);
+    }
+}
+
+// Upgrades keep the entity, so they aren't new buildings. Only the difference in cost was spent
+pub fn count_upgrades(mut results: EventReader<CommandResult>, mut stats: ResMut<GameStats>) {
+    for r in results.iter() {
+        if let Ok(CommandOutcome::Upgraded { cost, .. }) = r.result {
+            let spent = &mut stats.resources_spent;
+            spent.ore += cost.ore;
+            spent.gas += cost.gas;
+            spent.crystal += cost.crystal;
+        }
+    }
+}
+
+fn take_sample(
+    stats: &mut GameStats,
+    time: &InGameTime,
+    resources: &ResourceState,
+    count: &AlienCount,
+) {
+    let amount = |r| resources.get(r).unwrap_or(0) as u32;
+    stats.samples.push(StatsSample {
+        seconds: time.timer.elapsed_secs(),
+        ore: amount(ResourceType::Ore),
+        gas: amount(ResourceType::Gas),
+        crystal: amount(ResourceType::Crystal),
+        aliens: count.count,
+    });
+}
+
+// The first sample is taken at the very start, so the graphs start at 0
+pub fn sample_stats(
+    mut stats: ResMut<GameStats>,
+    game_time: Res<InGameTime>,
+    resources: Res<ResourceState>,
+    count: Res<AlienCount>,
+) {
+    stats.seconds = game_time.timer.elapsed_secs();
+    stats.peak_aliens = stats.peak_aliens.max(count.count);
+
+    if stats.seconds >= stats.next_sample {
+        take_sample(&mut stats, &game_time, &resources, &count);
+        stats.next_sample += SAMPLE_INTERVAL.as_secs_f32();
+    }
+}
+
+pub fn final_sample(
+    mut stats: ResMut<GameStats>,
+    game_time: Res<InGameTime>,
+    resources: Res<ResourceState>,
+    count: Res<AlienCount>,
+) {
+    take_sample(&mut stats, &game_time, &resources, &count);
+}
+
+#[cfg(test)]
+mod test_game_stats {
+    use bevy::prelude::*;
+
+    use crate::{
+        aliens::alien::Alien,
+        buildings::{
+            building_bundles::BuildingInfoComponent,
+            player_commands::{CommandOutcome, CommandResult, CommandSource, PlayerCommand},
+            resources::{ResourceSet, ResourceType},
+        },
+        health::health::DeathEvent,
+        rules::game_rules::GameRules,
+    };
+
+    use super::{count_deaths, count_new_buildings, count_upgrades, GameStats, ResourceTally};
+
+    fn info(name: &'static str) -> BuildingInfoComponent {
+        BuildingInfoComponent {
+            name,
+            description: "",
+            icon: Default::default(),
+            image: Default::default(),
+        }
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.init_resource::<GameStats>()
+            .init_resource::<GameRules>()
+            .add_event::<DeathEvent>()
+            .add_event::<CommandResult>()
+            .add_system(count_deaths)
+            .add_system(count_new_buildings)
+            .add_system(count_upgrades);
+        app
+    }
+
+    fn kill(app: &mut App, entity: Entity, killer: Option<Entity>) {
+        app.world
+            .resource_mut::<Events<DeathEvent>>()
+            .send(DeathEvent { entity, killer });
+    }
+
+    #[test]
+    fn kills_are_counted_per_turret() {
+        let mut app = app();
+        let gun = app.world.spawn(info("Machine gun")).id();
+        let laser = app.world.spawn(info("Laser")).id();
+        let aliens: Vec<_> = (0..4)
+            .map(|_| app.world.spawn(Alien::default()).id())
+            .collect();
+
+        kill(&mut app, aliens[0], Some(gun));
+        kill(&mut app, aliens[1], Some(laser));
+        kill(&mut app, aliens[2], Some(laser));
+        // The same death twice
+        kill(&mut app, aliens[2], Some(laser));
+        // Blew itself up
+        kill(&mut app, aliens[3], None);
+        app.update();
+
+        let stats = app.world.resource::<GameStats>();
+        assert_eq!(stats.kills, 3);
+        assert_eq!(stats.top_turret().unwrap().name, "Laser");
+        assert_eq!(stats.top_turret().unwrap().kills, 2);
+    }
+
+    #[test]
+    fn buildings_built_lost_and_demolished() {
+        let mut app = app();
+        let cost = ResourceSet::new(100, 20, 0);
+        let a = app.world.spawn((info("Mine"), cost.clone())).id();
+        let b = app.world.spawn((info("Mine"), cost.clone())).id();
+        let alien = app.world.spawn(Alien::default()).id();
+        app.update();
+
+        kill(&mut app, a, Some(alien));
+        kill(&mut app, b, None);
+        kill(&mut app, b, None);
+        app.update();
+
+        let stats = app.world.resource::<GameStats>();
+        assert_eq!(stats.buildings_built, 2);
+        assert_eq!(stats.buildings_lost, 1);
+        assert_eq!(stats.buildings_demolished, 1);
+        assert_eq!(
+            stats.resources_spent,
+            ResourceTally {
+                ore: 200,
+                gas: 40,
+                crystal: 0
+            }
+        );
+        // Half of it back for the demolished one
+        assert_eq!(stats.resources_refunded.ore, 50);
+        assert_eq!(stats.resources_refunded.gas, 10);
+        assert_eq!(stats.resources_earned.total(), 0);
+    }
+
+    #[test]
+    fn upgrades_only_count_the_difference() {
+        let mut app = app();
+        let alien = app.world.spawn(Alien::default()).id();
+        let gun = app
+            .world
+            .spawn((info("Machine gun mk1"), ResourceSet::new(100, 0, 0)))
+            .id();
+        app.update();
+        kill(&mut app, alien, Some(gun));
+        app.update();
+
+        // What BuildingActions::upgrade does to the entity
+        app.world
+            .entity_mut(gun)
+            .insert((info("Machine gun mk2"), ResourceSet::new(250, 0, 0)));
+        let cost = ResourceTally {
+            ore: 150,
+            gas: 0,
+            crystal: 0,
+        };
+        app.world
+            .resource_mut::<Events<CommandResult>>()
+            .send(CommandResult {
+                command: PlayerCommand::Upgrade { square: (0, 0) },
+                source: CommandSource::Player,
+                result: Ok(CommandOutcome::Upgraded {
+                    building: gun,
+                    cost,
+                }),
+            });
+        let second = app.world.spawn(Alien::default()).id();
+        kill(&mut app, second, Some(gun));
+        app.update();
+
+        let stats = app.world.resource::<GameStats>();
+        assert_eq!(stats.buildings_built, 1);
+        assert_eq!(stats.resources_spent.ore, 250);
+        let names: Vec<_> = stats.turrets.iter().map(|t| (t.name, t.kills)).collect();
+        assert_eq!(names, vec![("Machine gun mk1", 1), ("Machine gun mk2", 1)]);
+    }
+
+    #[test]
+    fn exports_to_json() {
+        let mut stats = GameStats::default();
+        stats.resources_earned.add(ResourceType::Gas, 30);
+        stats.record_kill(Entity::from_raw(1), "Laser");
+        let json = stats.to_json().unwrap();
+        assert!(json.contains("\"kills\": 1"));
+        assert!(json.contains("\"gas\": 30"));
+        assert!(json.contains("\"name\": \"Laser\""));
+        // The bookkeeping isn't part of it
+        assert!(!json.contains("counted_deaths"));
+    }
+}
@@ -0,0 +1 @@
+pub mod game_stats;
\ No newline at end of file
@@ -0,0 +1 @@
+pub mod telemetry;
\ No newline at end of file
@@ -0,0 +1,595 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    aliens::{
+        alien::{Alien, AlienCount},
+        alien_kinds::AlienKind,
+        nests::NestCount,
+    },
+    buildings::{
+        building_bundles::BuildingInfoComponent,
+        player_commands::{CommandOutcome, CommandResult},
+        resources::{ResourceSet, ResourceState, ResourceType},
+    },
+    game_timer::game_timer::InGameTime,
+    health::health::DeathEvent,
+    main_base::main_base::MainBaseComponent,
+    replay::playback::ReplayPlayback,
+    rules::game_rules::{Difficulty, GameMode, GameRules, Mutators},
+    settings::{config_file::config_path, settings::GameSettings},
+    stats::game_stats::{GameStats, ResourceTally},
+    ui::error_info::ErrorEvent,
+    AppState,
+};
+
+// An opt-in log of the gameplay events, for balancing.
+// When it's turned on in the settings every game writes a JSON Lines file (one JSON object per line) to the telemetry
+// folder of the config directory. Every line has the in game time `t` in seconds and the `event` type.
+// The schema of all the events is documented in docs/telemetry.md, bump SCHEMA_VERSION when changing it.
+
+pub struct TelemetryPlugin;
+
+impl Plugin for TelemetryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TelemetryLog>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(start_telemetry))
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(log_buildings_placed)
+                    .with_system(log_upgrades)
+                    .with_system(log_deaths)
+                    .with_system(log_aliens_spawned)
+                    .with_system(log_income)
+                    .with_system(log_errors),
+            )
+            // Every way a game can end
+            .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(finish_telemetry))
+            .add_system_set(SystemSet::on_enter(AppState::Victory).with_system(finish_telemetry))
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(finish_telemetry));
+    }
+}
+
+pub const SCHEMA_VERSION: u32 = 2;
+const TELEMETRY_DIR: &str = "telemetry";
+// The income is summed up over this many in game seconds, instead of a line for every generator tick
+const INCOME_INTERVAL: f32 = 1.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Victory,
+    Defeat,
+    // Quit to the main menu
+    Abandoned,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TelemetryEvent {
+    GameStarted {
+        schema: u32,
+        mode: GameMode,
+        difficulty: Difficulty,
+        seed: u64,
+        mutators: Mutators,
+    },
+    BuildingPlaced {
+        building: &'static str,
+        x: f32,
+        z: f32,
+        cost: ResourceTally,
+    },
+    // The name it was upgraded to and the difference in cost that was paid
+    BuildingUpgraded {
+        building: &'static str,
+        x: f32,
+        z: f32,
+        cost: ResourceTally,
+    },
+    BuildingDestroyed {
+        building: &'static str,
+        x: f32,
+        z: f32,
+        // Demolished by the player rather than destroyed by the aliens
+        demolished: bool,
+    },
+    AlienSpawned {
+        kind: AlienKind,
+        x: f32,
+        z: f32,
+    },
+    AlienKilled {
+        kind: AlienKind,
+        x: f32,
+        z: f32,
+        // The building that killed it, None for exploders blowing themselves up
+        killer: Option<&'static str>,
+    },
+    ResourceIncome {
+        ore: u32,
+        gas: u32,
+        crystal: u32,
+    },
+    Error {
+        error: ErrorEvent,
+    },
+    GameEnded {
+        outcome: Outcome,
+        kills: u32,
+        buildings_built: u32,
+        buildings_lost: u32,
+        buildings_demolished: u32,
+        peak_aliens: u32,
+        aliens_alive: u32,
+        nests_destroyed: u32,
+        // What was left in the bank
+        resources: ResourceTally,
+    },
+}
+
+#[derive(Serialize)]
+struct TelemetryRecord<'a> {
+    t: f32,
+    #[serde(flatten)]
+    event: &'a TelemetryEvent,
+}
+
+// One line of the log, without the newline
+pub fn record_line(t: f32, event: &TelemetryEvent) -> String {
+    // The events are plain data, serializing them can't fail
+    serde_json::to_string(&TelemetryRecord { t, event }).unwrap_or_default()
+}
+
+#[derive(Resource, Default)]
+pub struct TelemetryLog {
+    // None when telemetry is off, or once writing failed
+    writer: Option<Box<dyn Write + Send + Sync>>,
+    // Death events can come multiple times for the same entity
+    logged_deaths: HashSet<Entity>,
+    last_income: ResourceTally,
+    next_income: f32,
+}
+
+impl TelemetryLog {
+    pub fn new(writer: Box<dyn Write + Send + Sync>) -> Self {
+        TelemetryLog {
+            writer: Some(writer),
+            ..default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn write(&mut self, t: f32, event: TelemetryEvent) {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return,
+        };
+        // A failing log isn't worth stopping the game for, it just stops logging
+        if let Err(e) = writeln!(writer, "{}", record_line(t, &event)) {
+            warn!("Could not write telemetry, turning it off: {}", e);
+            self.writer = None;
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(e) = writer.flush() {
+                warn!("Could not write telemetry: {}", e);
+            }
+        }
+    }
+}
+
+fn open_log_file(seed: u64) -> Option<BufWriter<File>> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let dir = config_path(TELEMETRY_DIR)?;
+    let path = dir.join(format!("game-{}-{}.jsonl", timestamp, seed));
+    let file = fs::create_dir_all(&dir).and_then(|_| File::create(&path));
+    match file {
+        Ok(file) => {
+            info!("Writing telemetry to {}", path.display());
+            Some(BufWriter::new(file))
+        }
+        Err(e) => {
+            warn!("Could not create {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn now(time: &InGameTime) -> f32 {
+    time.timer.elapsed_secs()
+}
+
+pub fn start_telemetry(
+    settings: Res<GameSettings>,
+    rules: Res<GameRules>,
+    mut log: ResMut<TelemetryLog>,
+    playback: Res<ReplayPlayback>,
+) {
+    log.close();
+    *log = TelemetryLog::default();
+    // A replay would only log the recorded game again
+    if !settings.telemetry || playback.active() {
+        return;
+    }
+    if let Some(file) = open_log_file(rules.seed) {
+        *log = TelemetryLog::new(Box::new(file));
+    }
+    log.write(
+        0.,
+        TelemetryEvent::GameStarted {
+            schema: SCHEMA_VERSION,
+            mode: rules.mode,
+            difficulty: rules.difficulty,
+            seed: rules.seed,
+            mutators: rules.mutators,
+        },
+    );
+}
+
+pub fn log_buildings_placed(
+    time: Res<InGameTime>,
+    mut log: ResMut<TelemetryLog>,
+    buildings: Query<
+        (&BuildingInfoComponent, &Transform, &ResourceSet),
+        (Added<BuildingInfoComponent>, Without<MainBaseComponent>),
+    >,
+) {
+    if !log.enabled() {
+        return;
+    }
+    for (info, transform, cost) in buildings.iter() {
+        let mut tally = ResourceTally::default();
+        tally.add_set(cost);
+        log.write(
+            now(&time),
+            TelemetryEvent::BuildingPlaced {
+                building: info.name,
+                x: transform.translation.x,
+                z: transform.translation.z,
+                cost: tally,
+            },
+        );
+    }
+}
+
+// The upgraded building keeps its entity, so it isn't picked up by log_buildings_placed
+pub fn log_upgrades(
+    time: Res<InGameTime>,
+    mut log: ResMut<TelemetryLog>,
+    mut results: EventReader<CommandResult>,
+    buildings: Query<(&BuildingInfoComponent, &Transform)>,
+) {
+    if !log.enabled() {
+        return;
+    }
+    for r in results.iter() {
+        let (building, cost) = match r.result {
+            Ok(CommandOutcome::Upgraded { building, cost }) => (building, cost),
+            _ => continue,
+        };
+        if let Ok((info, transform)) = buildings.get(building) {
+            log.write(
+                now(&time),
+                TelemetryEvent::BuildingUpgraded {
+                    building: info.name,
+                    x: transform.translation.x,
+                    z: transform.translation.z,
+                    cost,
+                },
+            );
+        }
+    }
+}
+
+pub fn log_aliens_spawned(
+    time: Res<InGameTime>,
+    mut log: ResMut<TelemetryLog>,
+    aliens: Query<(&AlienKind, &Transform), Added<Alien>>,
+) {
+    if !log.enabled() {
+        return;
+    }
+    for (kind, transform) in aliens.iter() {
+        log.write(
+            now(&time),
+            TelemetryEvent::AlienSpawned {
+                kind: *kind,
+                x: transform.translation.x,
+                z: transform.translation.z,
+            },
+        );
+    }
+}
+
+pub fn log_deaths(
+    time: Res<InGameTime>,
+    mut log: ResMut<TelemetryLog>,
+    mut ev: EventReader<DeathEvent>,
+    aliens: Query<(&AlienKind, &Transform)>,
+    buildings: Query<(&BuildingInfoComponent, &Transform)>,
+) {
+    if !log.enabled() {
+        ev.clear();
+        return;
+    }
+    for e in ev.iter() {
+        if !log.logged_deaths.insert(e.entity) {
+            continue;
+        }
+        let event = if let Ok((kind, transform)) = aliens.get(e.entity) {
+            TelemetryEvent::AlienKilled {
+                kind: *kind,
+                x: transform.translation.x,
+                z: transform.translation.z,
+                killer: e
+                    .killer
+                    .and_then(|k| buildings.get(k).ok())
+                    .map(|(info, _)| info.name),
+            }
+        } else if let Ok((info, transform)) = buildings.get(e.entity) {
+            TelemetryEvent::BuildingDestroyed {
+                building: info.name,
+                x: transform.translation.x,
+                z: transform.translation.z,
+                demolished: e.killer.is_none(),
+            }
+        } else {
+            continue;
+        };
+        log.write(now(&time), event);
+    }
+}
+
+// The resources earned since the last line, from the stats
+pub fn log_income(time: Res<InGameTime>, stats: Res<GameStats>, mut log: ResMut<TelemetryLog>) {
+    let t = now(&time);
+    if !log.enabled() || t < log.next_income {
+        return;
+    }
+    log.next_income = t + INCOME_INTERVAL;
+
+    let earned = stats.resources_earned;
+    let last = log.last_income;
+    log.last_income = earned;
+    if earned == last {
+        return;
+    }
+    log.write(
+        t,
+        TelemetryEvent::ResourceIncome {
+            ore: earned.ore.saturating_sub(last.ore),
+            gas: earned.gas.saturating_sub(last.gas),
+            crystal: earned.crystal.saturating_sub(last.crystal),
+        },
+    );
+}
+
+pub fn log_errors(
+    time: Res<InGameTime>,
+    mut log: ResMut<TelemetryLog>,
+    mut ev: EventReader<ErrorEvent>,
+) {
+    for error in ev.iter() {
+        log.write(now(&time), TelemetryEvent::Error { error: *error });
+    }
+}
+
+// Writes the final state and closes the file. Also runs when the app gets back to the main menu at startup,
+// there's no log open then
+pub fn finish_telemetry(
+    state: Res<State<AppState>>,
+    time: Res<InGameTime>,
+    stats: Res<GameStats>,
+    resources: Res<ResourceState>,
+    count: Res<AlienCount>,
+    nests: Res<NestCount>,
+    mut log: ResMut<TelemetryLog>,
+) {
+    if !log.enabled() {
+        return;
+    }
+    let outcome = match state.current() {
+        AppState::Victory => Outcome::Victory,
+        AppState::GameOver => Outcome::Defeat,
+        _ => Outcome::Abandoned,
+    };
+    let mut bank = ResourceTally::default();
+    for r in ResourceType::ALL {
+        bank.add(r, resources.get(r).unwrap_or(0) as u32);
+    }
+    log.write(
+        now(&time),
+        TelemetryEvent::GameEnded {
+            outcome,
+            kills: stats.kills,
+            buildings_built: stats.buildings_built,
+            buildings_lost: stats.buildings_lost,
+            buildings_demolished: stats.buildings_demolished,
+            peak_aliens: stats.peak_aliens,
+            aliens_alive: count.count,
+            nests_destroyed: nests.destroyed,
+            resources: bank,
+        },
+    );
+    log.close();
+}
+
+#[cfg(test)]
+mod test_telemetry {
+    use std::{
+        fs,
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use bevy::prelude::*;
+
+    use crate::{
+        aliens::{alien::Alien, alien_kinds::AlienKind},
+        game_timer::game_timer::InGameTime,
+        health::health::DeathEvent,
+        replay::playback::ReplayPlayback,
+        rules::game_rules::GameRules,
+        settings::{config_file::config_path, settings::GameSettings},
+        ui::error_info::ErrorEvent,
+    };
+
+    use super::{
+        log_aliens_spawned, log_deaths, record_line, start_telemetry, TelemetryEvent, TelemetryLog,
+        TELEMETRY_DIR,
+    };
+
+    // A writer the test can read back from after the app took the log
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<serde_json::Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn lines_are_flat_and_tagged() {
+        let line = record_line(
+            12.5,
+            &TelemetryEvent::AlienSpawned {
+                kind: AlienKind::Swarmer,
+                x: 1.,
+                z: -2.,
+            },
+        );
+        assert_eq!(
+            line,
+            r#"{"t":12.5,"event":"alien_spawned","kind":"swarmer","x":1.0,"z":-2.0}"#
+        );
+
+        let line = record_line(
+            3.,
+            &TelemetryEvent::Error {
+                error: ErrorEvent::NotEnoughResources,
+            },
+        );
+        assert_eq!(
+            line,
+            r#"{"t":3.0,"event":"error","error":"not_enough_resources"}"#
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&record_line(
+            0.,
+            &TelemetryEvent::ResourceIncome {
+                ore: 5,
+                gas: 0,
+                crystal: 1,
+            },
+        ))
+        .unwrap();
+        assert_eq!(value["event"], "resource_income");
+        assert_eq!(value["ore"], 5);
+    }
+
+    #[test]
+    fn spawns_and_kills_are_logged_once() {
+        let buffer = SharedBuffer::default();
+        let mut app = App::new();
+        app.insert_resource(TelemetryLog::new(Box::new(buffer.clone())))
+            .insert_resource(InGameTime {
+                timer: Default::default(),
+            })
+            .add_event::<DeathEvent>()
+            .add_system(log_aliens_spawned)
+            .add_system(log_deaths);
+
+        let alien = app
+            .world
+            .spawn((Alien::default(), AlienKind::Brute, Transform::default()))
+            .id();
+        app.update();
+        for _ in 0..2 {
+            app.world
+                .resource_mut::<Events<DeathEvent>>()
+                .send(DeathEvent {
+                    entity: alien,
+                    killer: None,
+                });
+        }
+        app.update();
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "alien_spawned");
+        assert_eq!(lines[0]["kind"], "brute");
+        assert_eq!(lines[1]["event"], "alien_killed");
+        assert!(lines[1]["killer"].is_null());
+    }
+
+    // A log left over from the last game must not be written to either
+    #[test]
+    fn nothing_is_written_when_off() {
+        let files = || {
+            config_path(TELEMETRY_DIR)
+                .and_then(|dir| fs::read_dir(dir).ok())
+                .map_or(0, |dir| dir.count())
+        };
+        let files_before = files();
+
+        let buffer = SharedBuffer::default();
+        let mut app = App::new();
+        app.insert_resource(TelemetryLog::new(Box::new(buffer.clone())))
+            .insert_resource(GameSettings {
+                telemetry: false,
+                ..default()
+            })
+            .init_resource::<GameRules>()
+            .init_resource::<ReplayPlayback>()
+            .insert_resource(InGameTime {
+                timer: Default::default(),
+            })
+            .add_event::<DeathEvent>()
+            .add_startup_system(start_telemetry)
+            .add_system(log_aliens_spawned);
+
+        app.world
+            .spawn((Alien::default(), AlienKind::Drone, Transform::default()));
+        app.update();
+
+        let mut log = app.world.resource_mut::<TelemetryLog>();
+        assert!(!log.enabled());
+        log.write(
+            0.,
+            TelemetryEvent::Error {
+                error: ErrorEvent::SpaceOccupied,
+            },
+        );
+        assert!(buffer.lines().is_empty());
+        assert_eq!(files(), files_before);
+    }
+}
@@ -5,10 +5,19 @@ use bevy_egui::{
 };
 
 use crate::{
-    buildings::{building_bundles::BuildingInfoComponent, grid::Grid},
+    buildings::{
+        building_bundles::{BuildingInfoComponent, BuildingTemplates},
+        building_system::{repair_cost, upgrade_cost},
+        defensive_buildings::TargetingMode,
+        grid::Grid,
+        player_commands::{CommandEvent, PlayerCommand},
+        resource_images::ResourceImages,
+        resources::ResourceSet,
+    },
     cameras::get_world_point_from_screen::WorldClickEvent,
     effects::muzzleflash::GunType,
     health::health::Health,
+    main_base::main_base::MainBaseComponent,
     menu::menu::make_window,
 };
 
@@ -49,18 +58,78 @@ pub fn building_info(
 
 // Shows the info about the selected building
 // Buildings can be selected by clicking on them when the UI is in panning mode
+// The buttons only send commands, see player_commands
 pub fn building_info_ui(
-    query: Query<(&Health, Option<&GunType>, &BuildingInfoComponent)>,
+    query: Query<(
+        &Health,
+        Option<&GunType>,
+        &BuildingInfoComponent,
+        &Transform,
+        Option<&ResourceSet>,
+        Option<&TargetingMode>,
+        Option<&MainBaseComponent>,
+    )>,
     mut ctx: ResMut<EguiContext>,
     building_info: ResMut<BuildingInfo>,
+    templates: Res<BuildingTemplates>,
+    images: Res<ResourceImages>,
+    mut command_events: EventWriter<CommandEvent>,
 ) {
     if let Some(e) = building_info.selected_entity {
         let w = make_window(Align2::LEFT_BOTTOM, None).show(ctx.ctx_mut(), |ui| {
-            if let Ok((h, _, building_info)) = query.get(e) {
+            if let Ok((h, _, building_info, transform, cost, targeting, main_base)) = query.get(e) {
                 ui.label(building_info.name);
                 ui.image(building_info.image, (100., 100.));
                 ui.label(format!("Health: {} / {}", h.hp, h.max_hp));
                 ui.label(building_info.description);
+
+                // The main base can't be repaired or upgraded
+                if main_base.is_some() {
+                    return;
+                }
+                let square = Grid::get_square_index(transform.translation);
+                let mut send = |command| command_events.send(CommandEvent::player(command));
+
+                if let Some(cost) = cost {
+                    if h.hp < h.max_hp {
+                        ui.horizontal(|ui| {
+                            if ui.button("Repair").clicked() {
+                                send(PlayerCommand::Repair { square });
+                            }
+                            repair_cost(cost, h).display(ui, &images, false);
+                        });
+                    }
+                }
+
+                let current = templates.get(building_info.name);
+                let next = current
+                    .and_then(|b| b.upgrades_to)
+                    .and_then(|name| templates.get(name));
+                if let (Some(current), Some(next)) = (current, next) {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button(format!("Upgrade to {}", next.building_info.name))
+                            .clicked()
+                        {
+                            send(PlayerCommand::Upgrade { square });
+                        }
+                        upgrade_cost(current, next).display(ui, &images, false);
+                    });
+                }
+
+                if let Some(targeting) = targeting {
+                    ui.horizontal(|ui| {
+                        ui.label("Target:");
+                        for mode in TargetingMode::ALL {
+                            if ui
+                                .selectable_label(*targeting == mode, mode.name())
+                                .clicked()
+                            {
+                                send(PlayerCommand::SetTargeting { square, mode });
+                            }
+                        }
+                    });
+                }
             };
         });
     }
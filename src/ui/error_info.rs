@@ -1,6 +1,7 @@
 use std::{fmt::Display, time::Duration};
 
 use bevy::{prelude::*, time::Timer};
+use serde::Serialize;
 use bevy_egui::{
     egui::{self, Align2, Color32, Frame, RichText, Stroke, TextStyle},
     EguiContext,
@@ -20,12 +21,17 @@ impl Plugin for ErrorMessagePlugin {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ErrorEvent {
     NothingToDestroy,
     CantDestroyYourOwnBase,
     NotEnoughResources,
     SpaceOccupied,
+    NothingToRepair,
+    NothingToUpgrade,
+    NotATurret,
+    UnknownBuilding,
 }
 
 impl Display for ErrorEvent {
@@ -36,6 +42,10 @@ impl Display for ErrorEvent {
             NotEnoughResources => "You don't have enough resources to construct this building.",
             CantDestroyYourOwnBase => "You can't demolish your main base",
             SpaceOccupied => "This space is already occupied by another building.",
+            NothingToRepair => "There is no damaged building on the selected square",
+            NothingToUpgrade => "This building can't be upgraded any further",
+            NotATurret => "Only defensive buildings can change what they target",
+            UnknownBuilding => "There is no such building",
         })
     }
 }
@@ -15,12 +15,16 @@ use bevy_egui::{
 };
 
 use crate::{
+    aliens::bosses::boss_health_bar,
     buildings::{
         building_bundles::{Building, BuildingBundle, BuildingTemplates},
         building_system::{self, building_system, hide_highlight_square},
-        resource_images::ResourceImages,
+        resource_images::{register_resource_images, ResourceImages},
+        resources::resource_ui,
     },
     cameras::pan_camera::{get_primary_window_size, PanOrbitCamera},
+    controls::controls::{Action, ActionInput, KeyBindings},
+    game_timer::game_timer::game_time_ui,
     menu::menu::make_window,
     AppState,
 };
@@ -28,6 +32,7 @@ use crate::{
 use super::{building_info::{building_info, building_info_ui, BuildingInfo}, error_info::ErrorMessagePlugin};
 
 /// This module defines all the ingame menus UI
+/// The windows of the other modules are added here as well, so the gameplay plugins don't need egui and can run headless
 
 #[derive(Resource, Debug)]
 
@@ -50,13 +55,20 @@ impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(EguiPlugin)
             .init_resource::<BuildingInfo>()
+            .init_resource::<ResourceImages>()
+            .add_startup_system(register_resource_images)
+            // After all the templates are registered
+            .add_startup_system_to_stage(StartupStage::PostStartup, register_building_images)
             .add_system_set(
                 SystemSet::on_update(AppState::InGame)
                     .with_system(building_info_ui)
                     .with_system(building_system)
                     .with_system(hide_highlight_square)
                     .with_system(ui_system)
-                    .with_system(building_info),
+                    .with_system(building_info)
+                    .with_system(resource_ui)
+                    .with_system(game_time_ui)
+                    .with_system(boss_health_bar),
             )
             .add_plugin(ErrorMessagePlugin)
             .add_system_set(
@@ -68,6 +80,16 @@ impl Plugin for UIPlugin {
     }
 }
 
+// The building icons are loaded with the templates, egui needs them registered before it can draw them
+fn register_building_images(
+    mut templates: ResMut<BuildingTemplates>,
+    mut ctx: ResMut<EguiContext>,
+) {
+    for b in templates.templates.iter_mut() {
+        b.building_info.image = ctx.add_image(b.building_info.icon.clone());
+    }
+}
+
 // A system to set the styling for the menus drawn in game
 // This runs on game start.
 // Style changes are expensive
@@ -86,45 +108,6 @@ fn set_in_game_menu_styling(mut ctx: ResMut<EguiContext>) {
     });
 }
 
-// Aliases for the menu control keys
-pub mod keys {
-    use bevy::prelude::KeyCode;
-    use bevy_egui::egui::Key;
-
-    pub const START_GAME: KeyCode = KeyCode::Return;
-    pub const EXIT: KeyCode = KeyCode::Escape;
-    pub const INSTRUCTIONS: KeyCode = KeyCode::I;
-
-    pub const DEF_BUILDING: KeyCode = KeyCode::Q;
-    pub const RES_BUILDING: KeyCode = KeyCode::W;
-    pub const DEMOLISH: KeyCode = KeyCode::E;
-    pub const PAN: KeyCode = KeyCode::R;
-    pub const NUMPADS: [KeyCode; 10] = [
-        KeyCode::Numpad1,
-        KeyCode::Numpad2,
-        KeyCode::Numpad3,
-        KeyCode::Numpad4,
-        KeyCode::Numpad5,
-        KeyCode::Numpad6,
-        KeyCode::Numpad7,
-        KeyCode::Numpad8,
-        KeyCode::Numpad9,
-        KeyCode::Numpad0,
-    ];
-    pub const NUMERALS: [KeyCode; 10] = [
-        KeyCode::Key1,
-        KeyCode::Key2,
-        KeyCode::Key3,
-        KeyCode::Key4,
-        KeyCode::Key5,
-        KeyCode::Key6,
-        KeyCode::Key7,
-        KeyCode::Key8,
-        KeyCode::Key9,
-        KeyCode::Key0,
-    ];
-}
-
 // pub trait MyToString  {
 //     fn string(&self) -> &str;
 // }
@@ -148,7 +131,8 @@ fn ui_system(
     resource_images: Res<ResourceImages>,
     templates: Res<BuildingTemplates>,
     // For keyboard navigation
-    keys: Res<Input<KeyCode>>,
+    actions: Res<ActionInput>,
+    bindings: Res<KeyBindings>,
 ) {
     let mut building_details_to_show: Option<&Building> = None;
 
@@ -160,9 +144,12 @@ fn ui_system(
                 } else {
                     false
                 },
-                "(Q) Defensive buildings",
+                format!(
+                    "({}) Defensive buildings",
+                    bindings.label(Action::DefensiveBuildings)
+                ),
             );
-            if b.clicked() || keys.pressed(keys::DEF_BUILDING) {
+            if b.clicked() || actions.pressed(Action::DefensiveBuildings) {
                 ui_state.mode = UIMode::BuildingDefensive(None);
             }
 
@@ -172,20 +159,29 @@ fn ui_system(
                 } else {
                     false
                 },
-                "(W) Resource buildings",
+                format!(
+                    "({}) Resource buildings",
+                    bindings.label(Action::ResourceBuildings)
+                ),
             );
-            if b.clicked() || keys.pressed(keys::RES_BUILDING) {
+            if b.clicked() || actions.pressed(Action::ResourceBuildings) {
                 ui_state.mode = UIMode::BuildingResources(None);
             }
 
-            let b = ui.selectable_label(ui_state.mode == UIMode::Destroying, "(E) Demolish");
-            if b.clicked() || keys.pressed(keys::DEMOLISH) {
+            let b = ui.selectable_label(
+                ui_state.mode == UIMode::Destroying,
+                format!("({}) Demolish", bindings.label(Action::Demolish)),
+            );
+            if b.clicked() || actions.pressed(Action::Demolish) {
                 ui_state.mode = UIMode::Destroying;
             }
 
-            let a = ui.selectable_label(ui_state.mode == UIMode::Panning, "(R) Pan");
+            let a = ui.selectable_label(
+                ui_state.mode == UIMode::Panning,
+                format!("({}) Pan", bindings.label(Action::Pan)),
+            );
 
-            if a.clicked() || keys.pressed(keys::PAN) {
+            if a.clicked() || actions.pressed(Action::Pan) {
                 ui_state.mode = UIMode::Panning;
             }
         });
@@ -209,10 +205,13 @@ fn ui_system(
                             let checked = Some(b) == selected_building.as_ref();
                             let but = ui.selectable_label(
                                 checked,
-                                format!("({}) {}", i + 1 , b.building_info.name.to_string()),
+                                format!(
+                                    "({}) {}",
+                                    bindings.label(Action::SelectBuilding(i as u8)),
+                                    b.building_info.name.to_string()
+                                ),
                             );
-                            let pressed = keys.pressed(keys::NUMERALS[i])
-                                || keys.pressed(keys::NUMPADS[i]);
+                            let pressed = actions.pressed(Action::SelectBuilding(i as u8));
                             if but.clicked() || pressed {
                                 *selected_building = Some(b.clone());
                             };
@@ -239,11 +238,14 @@ fn ui_system(
                             let checked = Some(b) == selected_building.as_ref();
                             let but = ui.selectable_label(
                                 checked,
-                                format!("({}) {}", i + 1, b.building_info.name.to_string()),
+                                format!(
+                                    "({}) {}",
+                                    bindings.label(Action::SelectBuilding(i as u8)),
+                                    b.building_info.name.to_string()
+                                ),
                             );
 
-                            let pressed = keys.pressed(keys::NUMERALS[i])
-                                || keys.pressed(keys::NUMPADS[i]);
+                            let pressed = actions.pressed(Action::SelectBuilding(i as u8));
                             if but.clicked() || pressed {
                                 *selected_building = Some(b.clone());
                             };